@@ -0,0 +1,149 @@
+//! Checkpoints allowing an interrupted conversion to be resumed.
+//!
+//! A checkpoint is a small JSON sidecar file, next to the output, that is rewritten every few records.
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::error::ClinvarXMLTabError;
+use crate::reader::ReadStats;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Offset in the uncompressed input, right after the last completed ClinVarSet
+    pub input_offset: u64,
+    /// Number of ClinVarSets handled up to `input_offset`
+    pub records: u64,
+    /// Length of the output file once everything up to `input_offset` was flushed
+    pub output_length: u64,
+    /// Length of the `--replaced-table` file at the same point, if there is one
+    #[serde(default)]
+    pub replaced_table_length: Option<u64>,
+    /// Options the output depends on (format, fields, filters...), which must be the same to resume
+    #[serde(default)]
+    pub options: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Checkpoint {
+    pub fn new(stats: &ReadStats, output_length: u64) -> Self {
+        Self {
+            input_offset: stats.input_offset,
+            records: stats.records,
+            output_length,
+            replaced_table_length: None,
+            options: serde_json::Map::new(),
+        }
+    }
+
+    pub fn with_replaced_table_length(mut self, length: Option<u64>) -> Self {
+        self.replaced_table_length = length;
+        self
+    }
+
+    pub fn with_options(mut self, options: serde_json::Map<String, serde_json::Value>) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Checks that the conversion is resumed with the `options` it was started with
+    pub fn check_options(
+        &self,
+        options: &serde_json::Map<String, serde_json::Value>,
+    ) -> Result<(), ClinvarXMLTabError> {
+        let null = serde_json::Value::Null;
+        let changed: Vec<&str> = options
+            .keys()
+            .chain(self.options.keys())
+            .filter(|key| {
+                self.options.get(*key).unwrap_or(&null) != options.get(*key).unwrap_or(&null)
+            })
+            .map(String::as_str)
+            .collect::<std::collections::BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        if changed.is_empty() {
+            return Ok(());
+        }
+        Err(ClinvarXMLTabError::ResumeError(format!(
+            "the conversion was started with other options ({}), run it again without --resume",
+            changed.join(", ")
+        )))
+    }
+
+    /// Where the checkpoint of `output` is stored (`<output>.checkpoint`)
+    pub fn sidecar_path<P: AsRef<Path>>(output: P) -> PathBuf {
+        let mut path = output.as_ref().as_os_str().to_owned();
+        path.push(".checkpoint");
+        PathBuf::from(path)
+    }
+
+    /// Reads the checkpoint at `path`, if any
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Option<Self>, ClinvarXMLTabError> {
+        match std::fs::read(path) {
+            Ok(content) => Ok(Some(serde_json::from_slice(&content)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Writes the checkpoint to `path`.
+    /// The file is replaced atomically, so that a crash while saving leaves the previous checkpoint intact.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ClinvarXMLTabError> {
+        let path = path.as_ref();
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(self)?)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    pub fn stats(&self) -> ReadStats {
+        ReadStats {
+            records: self.records,
+            input_offset: self.input_offset,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn options(format: &str) -> serde_json::Map<String, serde_json::Value> {
+        let mut options = serde_json::Map::new();
+        options.insert("format".to_string(), format.into());
+        options.insert("filter".to_string(), serde_json::Value::Null);
+        options
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("checkpoint-{}", std::process::id()));
+        let stats = ReadStats {
+            records: 14,
+            input_offset: 276121,
+        };
+        let checkpoint = Checkpoint::new(&stats, 12410)
+            .with_replaced_table_length(Some(522))
+            .with_options(options("Tsv"));
+        checkpoint.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path).unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, checkpoint);
+        assert_eq!(loaded.stats(), stats);
+        assert_eq!(Checkpoint::load(&path).unwrap(), None);
+    }
+
+    #[test]
+    fn test_check_options() {
+        let checkpoint = Checkpoint::new(&ReadStats::default(), 0).with_options(options("Tsv"));
+        assert!(checkpoint.check_options(&options("Tsv")).is_ok());
+        let error = checkpoint.check_options(&options("Vcf")).unwrap_err();
+        assert!(error.to_string().contains("(format)"));
+        let mut filtered = options("Tsv");
+        filtered.insert("filter".to_string(), "stars >= 1".into());
+        assert!(checkpoint.check_options(&filtered).is_err());
+        // Checkpoints saved without options can't be trusted
+        let older = Checkpoint::new(&ReadStats::default(), 0);
+        assert!(older.check_options(&options("Tsv")).is_err());
+    }
+}
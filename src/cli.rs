@@ -18,6 +18,10 @@ pub struct Cli {
     #[clap(short = 'o', long = "output")]
     output: Option<std::path::PathBuf>,
 
+    /// Compression level of the output, when its extension asks for compression (.gz, .bgz, .bz2, .xz, .zst)
+    #[clap(long = "compression-level")]
+    compression_level: Option<u32>,
+
     /// Genome build
     #[clap(flatten)]
     genome: GenomeOption,
@@ -26,16 +30,44 @@ pub struct Cli {
     command: Command,
 }
 
+// Parsed once, its size doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(clap::Subcommand, Debug)]
 pub enum Command {
     /// Convert XML Clinvar to VCF
     #[clap(name = "convert")]
     Convert(Convert),
 
-    /// Only print out the very first XML element of input
+    /// Print some ClinVarSets of the input (the first one by default)
     #[clap(name = "debug")]
     Debug(Debug),
 
+    /// Annotate a VCF (given with --input) with ClinVar
+    #[clap(name = "annotate")]
+    Annotate(Annotate),
+
+    /// Compare two XML releases, RCV by RCV
+    #[clap(name = "diff")]
+    Diff(Diff),
+
+    /// Count records by significance, review status, variant type, chromosome, status, submitter...
+    #[clap(name = "stats")]
+    Stats(Stats),
+
+    /// Print the ClinVarSets with the given accessions
+    #[clap(name = "get")]
+    Get(Get),
+
+    /// Write an extract of the input as a smaller, well-formed XML release
+    #[clap(name = "subset")]
+    Subset(Subset),
+
+    /// Write a sidecar index with the accessions and offset of every ClinVarSet
+    ///
+    /// The index is written to `<input>.idx` unless --output is given. `get --index` uses it to seek to the ClinVarSets.
+    #[clap(name = "index")]
+    Index(Index),
+
     /// Generate Autocompletion
     #[clap(name = "autocomplete")]
     AutoComplete(AutoComplete),
@@ -50,14 +82,443 @@ pub struct AutoComplete {
 
 #[derive(clap::Parser, Debug)]
 pub struct Convert {
+    /// Output format
+    #[clap(long = "format", value_enum, default_value_t = OutputFormat::Vcf)]
+    format: OutputFormat,
+
     /// Path to an existing VCF header with all the right contigs.
     /// This program will only add its own info fields.
     #[clap(long = "existing-vcf-header")]
     existing_vcf_header: Option<PathBuf>,
+
+    /// Save a checkpoint every N ClinVarSets (0 to disable).
+    /// Checkpoints are written next to the output (`<output>.checkpoint`), which must be an uncompressed file.
+    #[clap(long = "checkpoint-every", default_value_t = 10000)]
+    checkpoint_every: u64,
+
+    /// Comma-separated fields to output, instead of the default ones (tsv, jsonl and vcf INFO only).
+    /// Either built-in fields (rcv, vcv, clnsig, review_status, stars, chrom, pos, ref, alt, genes, conditions, hgvs...)
+    /// or paths in the ClinVarSet, such as `ReferenceClinVarAssertion/MeasureSet/Measure/CytogeneticLocation`
+    /// or `...SequenceLocation@Accession`. Use `NAME=field` to rename a field.
+    #[clap(long = "fields", conflicts_with = "fields_file")]
+    fields: Option<String>,
+
+    /// File with the fields to output, one per line (same syntax as `--fields`)
+    #[clap(long = "fields-file")]
+    fields_file: Option<PathBuf>,
+
+    /// Record statuses to convert (comma-separated, e.g. `current,replaced`), or `all`.
+    /// By default, only current records are converted, so that replaced ones don't show up next to their successors.
+    #[clap(
+        long = "include-status",
+        value_delimiter = ',',
+        default_value = "current"
+    )]
+    include_status: Vec<String>,
+
+    /// Write a TSV mapping replaced accessions to their replacements (REPLACED, REPLACED_BY, VCV, CLINVAR_ID) to this file
+    #[clap(long = "replaced-table")]
+    replaced_table: Option<PathBuf>,
+
+    /// Only write the records matching this expression, e.g.
+    /// `clnsig in (pathogenic, likely_pathogenic, pathogenic/likely_pathogenic) and stars >= 2`.
+    /// Fields are the same as for `--fields`; operators are `==`, `!=`, `<`, `<=`, `>`, `>=`, `in (...)`,
+    /// `~` and `!~` (regex match), combined with `and`, `or`, `not` and parentheses.
+    #[clap(long = "filter")]
+    filter: Option<String>,
+
+    /// Only write the records in one of the genes listed in this file (one symbol per line)
+    #[clap(long = "genes-file")]
+    genes_file: Option<PathBuf>,
+
+    /// Only write the records whose position is within one of the regions of this BED file
+    #[clap(long = "regions-bed")]
+    regions_bed: Option<PathBuf>,
+
+    /// Only write the records with at least this many review stars (0 to 4)
+    #[clap(long = "min-stars", value_parser = clap::value_parser!(u8).range(0..=4))]
+    min_stars: Option<u8>,
+
+    /// With the vcf format, write one line per variant (VCV) instead of one per ClinVarSet (RCV),
+    /// with the RCVs, conditions (CLNDN) and significances of every ClinVarSet pipe-joined, like NCBI's clinvar.vcf.
+    /// Every variant of the input is kept in memory (memory use grows with the release) and written sorted at the end,
+    /// so checkpoints are disabled.
+    #[clap(long = "aggregate-vcv")]
+    aggregate_vcv: bool,
+
+    /// With the vcf format, check REF against this reference genome FASTA (indexed with `samtools faidx`,
+    /// optionally bgzipped with its `.gzi` index), then left-align and trim the alleles
+    #[clap(long = "reference")]
+    reference: Option<PathBuf>,
+
+    /// What to do with variants whose REF doesn't match the reference
+    #[clap(long = "on-ref-mismatch", value_enum, default_value_t = OnRefMismatch::Flag)]
+    on_ref_mismatch: OnRefMismatch,
+
+    /// How chromosomes are named (only used by the bed format for now)
+    #[clap(long = "chrom-style", value_enum, default_value_t = ChromStyle::Ucsc)]
+    chrom_style: ChromStyle,
+
+    /// Resume an interrupted conversion from the checkpoint next to the output
+    #[clap(long = "resume")]
+    resume: bool,
+
+    /// With the sqlite format, replace the database given with `--output` if it already exists
+    #[clap(long = "overwrite")]
+    overwrite: bool,
+
+    /// Report progress as JSON lines on stderr (by default, progress is only shown when stderr is a terminal)
+    #[clap(long = "progress-json")]
+    progress_json: bool,
+
+    /// Write one file per chromosome, gene or CLNSIG in `--output-dir`, each with its own header
+    /// (records in several genes are written in each of their files, those without a value in `none`)
+    #[clap(
+        long = "split-by",
+        value_enum,
+        requires = "output_dir",
+        conflicts_with_all = ["resume", "aggregate_vcv"]
+    )]
+    split_by: Option<SplitByOption>,
+
+    /// Directory of the files written with `--split-by`
+    #[clap(long = "output-dir", requires = "split_by")]
+    output_dir: Option<PathBuf>,
+
+    /// Name of the files written with `--split-by`: `{key}` is replaced by the chromosome, gene or CLNSIG,
+    /// `{ext}` by the extension of the format. Compressed according to their extension (`.gz`, `.zst`...).
+    #[clap(
+        long = "output-template",
+        default_value = "clinvar_{key}.{ext}",
+        requires = "split_by"
+    )]
+    output_template: String,
+
+    /// Keep at most N files open with `--split-by`, closing the least recently used one to open another.
+    /// Files are written uncompressed in a temporary directory of `--output-dir` until the end.
+    #[clap(long = "max-open-files", default_value_t = 64, requires = "split_by")]
+    max_open_files: usize,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitByOption {
+    /// Chromosome in the selected assembly
+    Chrom,
+    /// Gene symbol
+    Gene,
+    /// Clinical significance
+    Clnsig,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// VCF, one line per ClinVarSet with coordinates in the selected assembly
+    Vcf,
+    /// Tab separated values, multi-valued fields are pipe-separated
+    Tsv,
+    /// JSON lines, one object per ClinVarSet
+    Jsonl,
+    /// JSON lines with the whole ClinVarSet: attributes as `@Name`, text as `#text`, repeated elements as arrays,
+    /// mixed content as a `#content` list
+    JsonFull,
+    /// Same as json-full, flattened to dotted keys (`ReferenceClinVarAssertion.MeasureSet.@Acc`)
+    JsonFlat,
+    /// Apache Parquet, with typed and list columns
+    Parquet,
+    /// Arrow IPC file, same schema as parquet
+    Arrow,
+    /// SQLite database with one table per entity (variants, rcv, submissions, ...); needs `--output`
+    Sqlite,
+    /// BED6+ intervals, one per sequence location (score is the star rating, then CLNSIG and GENE)
+    Bed,
+}
+
+impl OutputFormat {
+    /// Whether the output is written line by line, and can thus be truncated to resume a conversion
+    pub fn is_line_based(&self) -> bool {
+        matches!(
+            self,
+            Self::Vcf | Self::Tsv | Self::Jsonl | Self::JsonFull | Self::JsonFlat | Self::Bed
+        )
+    }
+
+    /// Extension of the files written in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Vcf => "vcf",
+            Self::Tsv => "tsv",
+            Self::Jsonl | Self::JsonFull | Self::JsonFlat => "jsonl",
+            Self::Parquet => "parquet",
+            Self::Arrow => "arrow",
+            Self::Sqlite => "sqlite",
+            Self::Bed => "bed",
+        }
+    }
+
+    /// Whether `--fields` can select what gets written
+    pub fn supports_fields(&self) -> bool {
+        matches!(self, Self::Vcf | Self::Tsv | Self::Jsonl)
+    }
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnRefMismatch {
+    /// Write them with the REF_MISMATCH filter
+    Flag,
+    /// Leave them out
+    Drop,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChromStyle {
+    /// chr1, ..., chrX, chrY, chrM
+    Ucsc,
+    /// 1, ..., X, Y, MT
+    Ensembl,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct Debug {
+    /// Skip the first N ClinVarSets
+    #[clap(long = "skip", default_value_t = 0)]
+    skip: u64,
+
+    /// Print at most N ClinVarSets (1 by default, unless --id or --random is given)
+    #[clap(long = "limit")]
+    limit: Option<u64>,
+
+    /// Only print the ClinVarSets with these accessions (RCV, VCV, SCV, ClinVarSet ID or rsID, comma-separated).
+    /// Without --limit, reading stops once each of them was found.
+    #[clap(long = "id", value_delimiter = ',')]
+    ids: Vec<String>,
+
+    /// Print K ClinVarSets picked at random over the whole input (after --skip and --id), in input order
+    #[clap(long = "random", value_name = "K")]
+    random: Option<usize>,
+
+    /// Seed of the random sampling, to pick the same ClinVarSets again (printed on stderr when not given)
+    #[clap(long = "seed", requires = "random")]
+    seed: Option<u64>,
+
+    /// How to print the ClinVarSets
+    #[clap(long = "as", value_enum, default_value_t = DebugFormatOption::Tree)]
+    format: DebugFormatOption,
+
+    /// Read every record, and write a census of their element paths instead: occurrences, records with the
+    /// path, max occurrences per record, attribute names and sample text values (TSV)
+    #[clap(
+        long = "census",
+        conflicts_with_all = ["skip", "limit", "ids", "random", "format"]
+    )]
+    census: bool,
+
+    /// Census saved from a previous release, to only write what changed since (new or removed paths and
+    /// attributes, paths that became repeated within a record or stopped being)
+    #[clap(long = "against", requires = "census")]
+    against: Option<PathBuf>,
 }
 
 #[derive(clap::Parser, Debug)]
-pub struct Debug {}
+pub struct Annotate {
+    /// ClinVar to annotate with: the XML release, or a VCF converted from it (or NCBI's clinvar.vcf).
+    /// Either can be compressed.
+    #[clap(long = "clinvar")]
+    clinvar: PathBuf,
+
+    /// How variants are matched
+    #[clap(long = "match", value_enum, default_value_t = MatchOption::Exact)]
+    match_mode: MatchOption,
+
+    /// Comma-separated INFO fields to add.
+    /// With the XML release, these are fields as for `convert --fields` (default: `CLNACC=rcv,CLNSIG=clnsig,CLNREVSTAT=review_status,CLNDN=conditions`);
+    /// with a VCF, INFO keys of its records (default: all of them).
+    #[clap(long = "fields")]
+    fields: Option<String>,
+
+    /// Prefix added to the INFO keys, to keep them apart from those already in the VCF
+    #[clap(long = "prefix", default_value = "")]
+    prefix: String,
+
+    /// Record statuses to load from the XML release (comma-separated), or `all`
+    #[clap(
+        long = "include-status",
+        value_delimiter = ',',
+        default_value = "current"
+    )]
+    include_status: Vec<String>,
+
+    /// Reference genome FASTA (indexed with `samtools faidx`), to left-align indels on both sides before matching them
+    #[clap(long = "reference")]
+    reference: Option<PathBuf>,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct Diff {
+    /// Previous XML release
+    #[clap(long = "old")]
+    old: PathBuf,
+
+    /// Current XML release
+    #[clap(long = "new")]
+    new: PathBuf,
+
+    /// Report format
+    #[clap(long = "format", value_enum, default_value_t = DiffFormatOption::Tsv)]
+    format: DiffFormatOption,
+
+    /// Record statuses to compare (comma-separated), or `all`.
+    /// By default, only current records are: a record that got replaced shows up as removed.
+    #[clap(
+        long = "include-status",
+        value_delimiter = ',',
+        default_value = "current"
+    )]
+    include_status: Vec<String>,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct Stats {
+    /// Output format
+    #[clap(long = "format", value_enum, default_value_t = StatsFormatOption::Table)]
+    format: StatsFormatOption,
+
+    /// Only show the N most frequent values of each category
+    #[clap(long = "top")]
+    top: Option<usize>,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct Get {
+    /// RCV, VCV or SCV accessions, ClinVarSet IDs or rsIDs (e.g. `RCV000000181`, `VCV000000158`, `92148140`, `rs121965064`)
+    #[clap(required = true)]
+    accessions: Vec<String>,
+
+    /// How to print the ClinVarSets found
+    #[clap(long = "as", value_enum, default_value_t = GetFormatOption::Xml)]
+    format: GetFormatOption,
+
+    /// Read the whole input, to print every ClinVarSet that matches (e.g. all the RCVs of a VCV),
+    /// instead of stopping once every accession was found
+    #[clap(long = "all")]
+    all: bool,
+
+    /// Index of the input written by the `index` subcommand, to seek to the ClinVarSets instead of reading
+    /// the whole input
+    #[clap(long = "index")]
+    index: Option<PathBuf>,
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct Index {}
+
+#[derive(clap::Parser, Debug)]
+pub struct Subset {
+    /// ClinVarSets with these accessions (RCV, VCV, SCV, ClinVarSet ID or rsID, comma-separated).
+    /// Without --limit, reading stops once each of them was found.
+    #[clap(long = "id", value_delimiter = ',')]
+    ids: Vec<String>,
+
+    /// File with accessions to extract, one per line
+    #[clap(long = "ids-file")]
+    ids_file: Option<PathBuf>,
+
+    /// ClinVarSets in any of these genes (comma-separated)
+    #[clap(long = "gene", value_delimiter = ',')]
+    genes: Vec<String>,
+
+    /// ClinVarSets in any of the genes listed in this file (one symbol per line)
+    #[clap(long = "genes-file")]
+    genes_file: Option<PathBuf>,
+
+    /// ClinVarSets whose position is in any of these regions (`chr17:43044295-43125483`, or `chrM`; comma-separated)
+    #[clap(long = "region", value_delimiter = ',', conflicts_with = "regions_bed")]
+    regions: Vec<String>,
+
+    /// ClinVarSets whose position falls in one of the regions of this BED file
+    #[clap(long = "regions-bed")]
+    regions_bed: Option<PathBuf>,
+
+    /// Only ClinVarSets matching this expression (same syntax as `convert --filter`)
+    #[clap(long = "filter")]
+    filter: Option<String>,
+
+    /// Record statuses to extract (comma-separated), or `all`
+    #[clap(long = "include-status", value_delimiter = ',', default_value = "all")]
+    include_status: Vec<String>,
+
+    /// Extract K ClinVarSets picked at random over the whole input (among those selected by the other options)
+    #[clap(long = "random", value_name = "K")]
+    random: Option<usize>,
+
+    /// Pick K ClinVarSets at random for every value of these (comma-separated), instead of K overall
+    #[clap(
+        long = "stratify",
+        value_enum,
+        value_delimiter = ',',
+        requires = "random"
+    )]
+    stratify: Vec<StratumOption>,
+
+    /// Seed of the random sampling, to extract the same ClinVarSets again (printed on stderr when not given)
+    #[clap(long = "seed", requires = "random")]
+    seed: Option<u64>,
+
+    /// Extract at most N ClinVarSets
+    #[clap(long = "limit")]
+    limit: Option<u64>,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StratumOption {
+    /// Clinical significance
+    Clnsig,
+    /// Type of the variant (single nucleotide variant, Deletion...)
+    VariantType,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugFormatOption {
+    /// The ClinVarSet element, as in the input
+    Xml,
+    /// Every node with its path, text and attributes
+    Tree,
+    /// The extracted record, as a JSON line (same as `convert --format jsonl`)
+    Json,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GetFormatOption {
+    /// The ClinVarSet element, as in the input
+    Xml,
+    /// Every node with its path, text and attributes, as `debug` prints them
+    Tree,
+    /// The extracted record, as a JSON line (same as `convert --format jsonl`)
+    Record,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsFormatOption {
+    /// Human-readable table
+    Table,
+    /// JSON object
+    Json,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffFormatOption {
+    /// Tab separated values: CHANGE, RCV, VCV, OLD, NEW
+    Tsv,
+    /// JSON lines, one object per change
+    Jsonl,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchOption {
+    /// Same chromosome, position, REF and ALT (after normalization)
+    Exact,
+    /// Any ClinVar variant overlapping the REF allele, whatever its ALT
+    Position,
+}
 
 /// Options for the genome build (mutually exclusive)
 #[derive(clap::Parser, std::fmt::Debug)]
@@ -79,7 +540,7 @@ pub enum Genome {
 
 impl Cli {
     pub fn command(&self) -> &Command {
-        return &self.command;
+        &self.command
     }
 
     pub fn input(&self) -> Option<&PathBuf> {
@@ -103,6 +564,10 @@ impl Cli {
     pub fn output(&self) -> Option<&std::path::PathBuf> {
         self.output.as_ref()
     }
+
+    pub fn compression_level(&self) -> Option<u32> {
+        self.compression_level
+    }
 }
 
 impl AutoComplete {
@@ -112,7 +577,251 @@ impl AutoComplete {
 }
 
 impl Convert {
+    pub fn format(&self) -> OutputFormat {
+        self.format
+    }
+
     pub fn existing_vcf_header(&self) -> Option<&PathBuf> {
         self.existing_vcf_header.as_ref()
     }
+
+    pub fn checkpoint_every(&self) -> u64 {
+        self.checkpoint_every
+    }
+
+    pub fn resume(&self) -> bool {
+        self.resume
+    }
+
+    pub fn overwrite(&self) -> bool {
+        self.overwrite
+    }
+
+    pub fn progress_json(&self) -> bool {
+        self.progress_json
+    }
+
+    pub fn split_by(&self) -> Option<SplitByOption> {
+        self.split_by
+    }
+
+    pub fn output_dir(&self) -> Option<&PathBuf> {
+        self.output_dir.as_ref()
+    }
+
+    pub fn output_template(&self) -> &str {
+        &self.output_template
+    }
+
+    pub fn max_open_files(&self) -> usize {
+        self.max_open_files
+    }
+
+    pub fn fields(&self) -> Option<&str> {
+        self.fields.as_deref()
+    }
+
+    pub fn fields_file(&self) -> Option<&PathBuf> {
+        self.fields_file.as_ref()
+    }
+
+    /// Record statuses to keep, `None` to keep them all
+    pub fn include_status(&self) -> Option<&[String]> {
+        (!self.include_status.iter().any(|s| s == "all")).then_some(self.include_status.as_slice())
+    }
+
+    pub fn replaced_table(&self) -> Option<&PathBuf> {
+        self.replaced_table.as_ref()
+    }
+
+    pub fn filter(&self) -> Option<&str> {
+        self.filter.as_deref()
+    }
+
+    pub fn genes_file(&self) -> Option<&PathBuf> {
+        self.genes_file.as_ref()
+    }
+
+    pub fn regions_bed(&self) -> Option<&PathBuf> {
+        self.regions_bed.as_ref()
+    }
+
+    pub fn min_stars(&self) -> Option<u8> {
+        self.min_stars
+    }
+
+    pub fn aggregate_vcv(&self) -> bool {
+        self.aggregate_vcv
+    }
+
+    pub fn reference(&self) -> Option<&PathBuf> {
+        self.reference.as_ref()
+    }
+
+    pub fn on_ref_mismatch(&self) -> OnRefMismatch {
+        self.on_ref_mismatch
+    }
+
+    pub fn chrom_style(&self) -> ChromStyle {
+        self.chrom_style
+    }
+}
+
+impl Debug {
+    pub fn skip(&self) -> u64 {
+        self.skip
+    }
+
+    pub fn limit(&self) -> Option<u64> {
+        self.limit
+    }
+
+    pub fn ids(&self) -> &[String] {
+        &self.ids
+    }
+
+    pub fn random(&self) -> Option<usize> {
+        self.random
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    pub fn format(&self) -> DebugFormatOption {
+        self.format
+    }
+
+    pub fn census(&self) -> bool {
+        self.census
+    }
+
+    pub fn against(&self) -> Option<&PathBuf> {
+        self.against.as_ref()
+    }
+}
+
+impl Annotate {
+    pub fn clinvar(&self) -> &PathBuf {
+        &self.clinvar
+    }
+
+    pub fn match_mode(&self) -> MatchOption {
+        self.match_mode
+    }
+
+    pub fn fields(&self) -> Option<&str> {
+        self.fields.as_deref()
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Record statuses to keep, `None` to keep them all
+    pub fn include_status(&self) -> Option<&[String]> {
+        (!self.include_status.iter().any(|s| s == "all")).then_some(self.include_status.as_slice())
+    }
+
+    pub fn reference(&self) -> Option<&PathBuf> {
+        self.reference.as_ref()
+    }
+}
+
+impl Diff {
+    pub fn old_release(&self) -> &PathBuf {
+        &self.old
+    }
+
+    pub fn new_release(&self) -> &PathBuf {
+        &self.new
+    }
+
+    pub fn format(&self) -> DiffFormatOption {
+        self.format
+    }
+
+    /// Record statuses to keep, `None` to keep them all
+    pub fn include_status(&self) -> Option<&[String]> {
+        (!self.include_status.iter().any(|s| s == "all")).then_some(self.include_status.as_slice())
+    }
+}
+
+impl Stats {
+    pub fn format(&self) -> StatsFormatOption {
+        self.format
+    }
+
+    pub fn top(&self) -> Option<usize> {
+        self.top
+    }
+}
+
+impl Get {
+    pub fn accessions(&self) -> &[String] {
+        &self.accessions
+    }
+
+    pub fn format(&self) -> GetFormatOption {
+        self.format
+    }
+
+    pub fn all(&self) -> bool {
+        self.all
+    }
+
+    pub fn index(&self) -> Option<&PathBuf> {
+        self.index.as_ref()
+    }
+}
+
+impl Subset {
+    pub fn ids(&self) -> &[String] {
+        &self.ids
+    }
+
+    pub fn ids_file(&self) -> Option<&PathBuf> {
+        self.ids_file.as_ref()
+    }
+
+    pub fn genes(&self) -> &[String] {
+        &self.genes
+    }
+
+    pub fn genes_file(&self) -> Option<&PathBuf> {
+        self.genes_file.as_ref()
+    }
+
+    pub fn regions(&self) -> &[String] {
+        &self.regions
+    }
+
+    pub fn regions_bed(&self) -> Option<&PathBuf> {
+        self.regions_bed.as_ref()
+    }
+
+    pub fn filter(&self) -> Option<&str> {
+        self.filter.as_deref()
+    }
+
+    /// Record statuses to keep, `None` to keep them all
+    pub fn include_status(&self) -> Option<&[String]> {
+        (!self.include_status.iter().any(|s| s == "all")).then_some(self.include_status.as_slice())
+    }
+
+    pub fn random(&self) -> Option<usize> {
+        self.random
+    }
+
+    pub fn stratify(&self) -> &[StratumOption] {
+        &self.stratify
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    pub fn limit(&self) -> Option<u64> {
+        self.limit
+    }
 }
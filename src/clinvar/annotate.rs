@@ -0,0 +1,588 @@
+//! Annotation of a VCF with ClinVar, without going through an intermediate file.
+//!
+//! ClinVar variants (from the XML release, or from a VCF converted beforehand) are loaded into a
+//! [`ClinVarIndex`], keyed by normalized CHROM/POS/REF/ALT: chromosomes are named without `chr` (`MT` for the
+//! mitochondria) and alleles are trimmed of their shared bases, or left-aligned when a reference genome is given.
+//! The sample VCF is then streamed, and the ClinVar values of matching variants are added to its INFO column.
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, Write};
+
+use noodles_vcf as vcf;
+
+use crate::clinvar::fields::FieldSpec;
+use crate::clinvar::filter::{normalize_chromosome, Filter};
+use crate::clinvar::record::ClinVarRecord;
+use crate::error::ClinvarXMLTabError;
+use crate::reference::{check_and_normalize, Checked, ReferenceGenome};
+use crate::xml::handler::{ClinVarSetEvent, EventHandler};
+
+/// INFO fields added when none are selected
+pub const DEFAULT_FIELDS: &str =
+    "CLNACC=rcv,CLNSIG=clnsig,CLNREVSTAT=review_status,CLNDN=conditions";
+
+/// How sample variants are matched with ClinVar ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchMode {
+    /// Same chromosome, position and alleles (after normalization), values are given per ALT allele
+    Exact,
+    /// ClinVar variants overlapping the REF allele of the sample variant, whatever their ALT
+    Position,
+}
+
+/// Normalized variant
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct VariantKey {
+    chromosome: String,
+    position: usize,
+    reference: String,
+    alternate: String,
+}
+
+/// Removes the bases shared by both alleles, at their end then at their start, keeping at least one base in each.
+/// This is what normalization boils down to without a reference genome (indels are not left-aligned).
+fn trim(position: usize, reference: &str, alternate: &str) -> (usize, String, String) {
+    let mut reference = reference.as_bytes();
+    let mut alternate = alternate.as_bytes();
+    while reference.len() > 1 && alternate.len() > 1 && reference.last() == alternate.last() {
+        reference = &reference[..reference.len() - 1];
+        alternate = &alternate[..alternate.len() - 1];
+    }
+    let mut position = position;
+    while reference.len() > 1 && alternate.len() > 1 && reference[0] == alternate[0] {
+        reference = &reference[1..];
+        alternate = &alternate[1..];
+        position += 1;
+    }
+    (
+        position,
+        String::from_utf8_lossy(reference).into_owned(),
+        String::from_utf8_lossy(alternate).into_owned(),
+    )
+}
+
+/// Decodes the `%XX` escapes of a raw VCF INFO value
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// ClinVar variants with the values to annotate with, held in memory
+pub struct ClinVarIndex {
+    /// INFO keys, with their description
+    keys: Vec<(String, String)>,
+    /// Values of every variant, one per key (missing at the end for keys found after the variant was read)
+    annotations: Vec<Vec<Option<String>>>,
+    exact: HashMap<VariantKey, Vec<usize>>,
+    /// Per chromosome, variants by start position, with their end position
+    starts: HashMap<String, BTreeMap<usize, Vec<(usize, usize)>>>,
+    /// Per chromosome, the longest REF allele, to find the variants that start upstream of a position but overlap it
+    longest: HashMap<String, usize>,
+    reference_genome: Option<ReferenceGenome>,
+}
+
+impl ClinVarIndex {
+    fn new(keys: Vec<(String, String)>, reference_genome: Option<ReferenceGenome>) -> Self {
+        Self {
+            keys,
+            annotations: Vec::new(),
+            exact: HashMap::new(),
+            starts: HashMap::new(),
+            longest: HashMap::new(),
+            reference_genome,
+        }
+    }
+
+    /// Reads a ClinVar VCF (such as one written by `convert --format vcf`, or NCBI's clinvar.vcf).
+    /// `keys` selects the INFO fields to annotate with, by default all of those found in the header or the records.
+    pub fn from_vcf<R: BufRead>(
+        reader: R,
+        keys: Option<&[String]>,
+        reference_genome: Option<ReferenceGenome>,
+    ) -> Result<Self, ClinvarXMLTabError> {
+        use vcf::header::record::value::map::info::Type;
+
+        let mut reader = vcf::io::Reader::new(reader);
+        let header = reader.read_header()?;
+        let discover_keys = keys.is_none();
+        let keys: Vec<(String, String)> = match keys {
+            Some(keys) => keys
+                .iter()
+                .map(|key| {
+                    let description = header.infos().get(key).map_or_else(
+                        || format!("ClinVar {key}"),
+                        |info| info.description().to_string(),
+                    );
+                    (key.clone(), description)
+                })
+                .collect(),
+            // Flags have no value to copy
+            None => header
+                .infos()
+                .iter()
+                .filter(|(_, info)| info.ty() != Type::Flag)
+                .map(|(key, info)| (key.clone(), info.description().to_string()))
+                .collect(),
+        };
+        let mut index = Self::new(keys, reference_genome);
+        for record in reader.records() {
+            let record = record?;
+            let Some(position) = record.variant_start().transpose()? else {
+                continue;
+            };
+            let info = record.info();
+            let fields: Vec<(&str, &str)> = info
+                .as_ref()
+                .split(';')
+                .filter_map(|field| field.split_once('='))
+                .collect();
+            // Our own VCFs don't declare their INFO fields
+            if discover_keys {
+                for (key, _) in &fields {
+                    if !index.keys.iter().any(|(k, _)| k == key) {
+                        index.keys.push((key.to_string(), format!("ClinVar {key}")));
+                    }
+                }
+            }
+            let values: Vec<Option<String>> = index
+                .keys
+                .iter()
+                .map(|(key, _)| {
+                    fields
+                        .iter()
+                        .find(|(k, _)| k == key)
+                        .map(|(_, v)| percent_decode(v))
+                })
+                .collect();
+            for alternate in record.alternate_bases().as_ref().split(',') {
+                index.insert(
+                    record.reference_sequence_name(),
+                    usize::from(position),
+                    record.reference_bases(),
+                    alternate,
+                    values.clone(),
+                )?;
+            }
+        }
+        Ok(index)
+    }
+
+    /// Number of ClinVar variants loaded
+    pub fn len(&self) -> usize {
+        self.annotations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.annotations.is_empty()
+    }
+
+    /// INFO keys, with their description
+    pub fn keys(&self) -> &[(String, String)] {
+        &self.keys
+    }
+
+    fn normalize(
+        &mut self,
+        chromosome: &str,
+        position: usize,
+        reference: &str,
+        alternate: &str,
+    ) -> Result<VariantKey, ClinvarXMLTabError> {
+        let chromosome = normalize_chromosome(chromosome).to_string();
+        let checked = match &mut self.reference_genome {
+            Some(genome) => {
+                check_and_normalize(genome, &chromosome, position, reference, alternate)?
+            }
+            None => Checked::UnknownContig,
+        };
+        // Variants that can't be checked against the reference are still matched, as they are
+        let (position, reference, alternate) = match checked {
+            Checked::Valid {
+                position,
+                reference,
+                alternate,
+            } => (position, reference, alternate),
+            Checked::Mismatch | Checked::UnknownContig => trim(
+                position,
+                &reference.to_ascii_uppercase(),
+                &alternate.to_ascii_uppercase(),
+            ),
+        };
+        Ok(VariantKey {
+            chromosome,
+            position,
+            reference,
+            alternate,
+        })
+    }
+
+    fn insert(
+        &mut self,
+        chromosome: &str,
+        position: usize,
+        reference: &str,
+        alternate: &str,
+        values: Vec<Option<String>>,
+    ) -> Result<(), ClinvarXMLTabError> {
+        let key = self.normalize(chromosome, position, reference, alternate)?;
+        let index = self.annotations.len();
+        self.annotations.push(values);
+        let end = key.position + key.reference.len().max(1) - 1;
+        let longest = self.longest.entry(key.chromosome.clone()).or_default();
+        *longest = (*longest).max(end - key.position + 1);
+        self.starts
+            .entry(key.chromosome.clone())
+            .or_default()
+            .entry(key.position)
+            .or_default()
+            .push((end, index));
+        self.exact.entry(key).or_default().push(index);
+        Ok(())
+    }
+
+    /// Variants with the same normalized alleles
+    fn exact_matches(
+        &mut self,
+        chromosome: &str,
+        position: usize,
+        reference: &str,
+        alternate: &str,
+    ) -> Result<Vec<usize>, ClinvarXMLTabError> {
+        let key = self.normalize(chromosome, position, reference, alternate)?;
+        Ok(self.exact.get(&key).cloned().unwrap_or_default())
+    }
+
+    /// Variants overlapping `start..=end`
+    fn overlapping(&self, chromosome: &str, start: usize, end: usize) -> Vec<usize> {
+        let chromosome = normalize_chromosome(chromosome);
+        let (Some(starts), Some(longest)) =
+            (self.starts.get(chromosome), self.longest.get(chromosome))
+        else {
+            return Vec::new();
+        };
+        starts
+            .range(start.saturating_sub(longest - 1)..=end)
+            .flat_map(|(_, variants)| variants)
+            .filter(|(variant_end, _)| *variant_end >= start)
+            .map(|(_, index)| *index)
+            .collect()
+    }
+
+    /// Values of the `key`-th field of `variants`, pipe-joined (`.` for variants without a value).
+    /// `None` if none of them has one.
+    fn joined(&self, variants: &[usize], key: usize) -> Option<String> {
+        let values: Vec<Option<&str>> = variants
+            .iter()
+            .map(|&v| self.annotations[v].get(key).and_then(Option::as_deref))
+            .collect();
+        values.iter().any(Option::is_some).then(|| {
+            values
+                .iter()
+                .map(|v| v.unwrap_or("."))
+                .collect::<Vec<_>>()
+                .join("|")
+        })
+    }
+}
+
+/// Loads the ClinVarSets of the XML release into a [`ClinVarIndex`]
+pub struct ClinVarIndexBuilder {
+    index: ClinVarIndex,
+    fields: FieldSpec,
+    filter: Filter,
+    assembly: &'static str,
+}
+
+impl ClinVarIndexBuilder {
+    /// INFO keys are those of `fields`, and only the ClinVarSets matching `filter` are loaded
+    pub fn new(
+        fields: FieldSpec,
+        filter: Filter,
+        assembly: &'static str,
+        reference_genome: Option<ReferenceGenome>,
+    ) -> Self {
+        let keys = fields
+            .fields()
+            .iter()
+            .map(|f| (f.info_key(), format!("ClinVar {}", f.name())))
+            .collect();
+        Self {
+            index: ClinVarIndex::new(keys, reference_genome),
+            fields,
+            filter,
+            assembly,
+        }
+    }
+
+    pub fn into_index(self) -> ClinVarIndex {
+        self.index
+    }
+}
+
+impl EventHandler for ClinVarIndexBuilder {
+    fn handle_clinvar_set(&mut self, event: &ClinVarSetEvent) -> Result<(), ClinvarXMLTabError> {
+        let node = event.node();
+        if !self.filter.matches_status(node) {
+            return Ok(());
+        }
+        let record = ClinVarRecord::from_clinvar_set(event.set()?, Some(self.assembly));
+        if !self.filter.matches(node, &record) {
+            return Ok(());
+        }
+        let values = self
+            .fields
+            .values(node, &record)
+            .into_iter()
+            .map(|values| (!values.is_empty()).then(|| values.join("&")))
+            .collect();
+        if let (Some(chromosome), Some(position), Some(reference), Some(alternate)) = (
+            record.chromosome(),
+            record.position(),
+            record.reference(),
+            record.alternate(),
+        ) {
+            self.index
+                .insert(chromosome, position, reference, alternate, values)?;
+        }
+        Ok(())
+    }
+
+    fn end_record(&mut self) -> Result<(), ClinvarXMLTabError> {
+        Ok(())
+    }
+
+    fn records_written(&self) -> Option<u64> {
+        Some(self.index.len() as u64)
+    }
+}
+
+/// How many sample variants were read and annotated
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AnnotateCounts {
+    pub records: u64,
+    pub annotated: u64,
+}
+
+/// Copies the sample VCF from `reader` to `writer`, with the values of the matching ClinVar variants added to INFO
+/// (their keys prefixed with `prefix`). Values of several ClinVar variants are pipe-joined; in exact mode, they are
+/// given per ALT allele (`Number=A`).
+pub fn annotate<R: BufRead, W: Write>(
+    index: &mut ClinVarIndex,
+    mode: MatchMode,
+    prefix: &str,
+    reader: R,
+    writer: W,
+) -> Result<AnnotateCounts, ClinvarXMLTabError> {
+    use vcf::header::record::value::map::info::{Number, Type};
+    use vcf::header::record::value::{map::Info, Map};
+    use vcf::variant::io::Write as _;
+    use vcf::variant::record_buf::info::field::value::{Array, Value};
+
+    let mut reader = vcf::io::Reader::new(reader);
+    let input_header = reader.read_header()?;
+    let mut header = input_header.clone();
+    let number = match mode {
+        MatchMode::Exact => Number::AlternateBases,
+        MatchMode::Position => Number::Unknown,
+    };
+    let keys: Vec<String> = index
+        .keys()
+        .iter()
+        .map(|(key, _)| format!("{prefix}{key}"))
+        .collect();
+    for (key, (_, description)) in keys.iter().zip(index.keys()) {
+        header.infos_mut().insert(
+            key.clone(),
+            Map::<Info>::new(number, Type::String, description.as_str()),
+        );
+    }
+    let mut writer = vcf::io::Writer::new(writer);
+    writer.write_header(&header)?;
+
+    let mut counts = AnnotateCounts::default();
+    for record in reader.record_bufs(&input_header) {
+        let mut record = record?;
+        counts.records += 1;
+        let chromosome = record.reference_sequence_name().to_string();
+        let position = record.variant_start().map(usize::from);
+        let reference = record.reference_bases().to_string();
+        let mut info: Vec<(String, Value)> = Vec::new();
+        match (mode, position) {
+            (_, None) => {}
+            (MatchMode::Exact, Some(position)) => {
+                let mut matches = Vec::new();
+                for alternate in record.alternate_bases().as_ref() {
+                    matches.push(index.exact_matches(
+                        &chromosome,
+                        position,
+                        &reference,
+                        alternate,
+                    )?);
+                }
+                for (i, key) in keys.iter().enumerate() {
+                    let values: Vec<Option<String>> =
+                        matches.iter().map(|m| index.joined(m, i)).collect();
+                    if values.iter().any(Option::is_some) {
+                        info.push((key.clone(), Value::Array(Array::String(values))));
+                    }
+                }
+            }
+            (MatchMode::Position, Some(position)) => {
+                let end = position + reference.len().max(1) - 1;
+                let matches = index.overlapping(&chromosome, position, end);
+                for (i, key) in keys.iter().enumerate() {
+                    if let Some(value) = index.joined(&matches, i) {
+                        info.push((key.clone(), Value::String(value)));
+                    }
+                }
+            }
+        }
+        if !info.is_empty() {
+            counts.annotated += 1;
+        }
+        for (key, value) in info {
+            record.info_mut().insert(key, Some(value));
+        }
+        writer.write_variant_record(&header, &record)?;
+    }
+    writer.get_mut().flush()?;
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CLINVAR: &str = "##fileformat=VCFv4.3
+##INFO=<ID=CLNSIG,Number=.,Type=String,Description=\"Clinical significance\">
+#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO
+1\t100\t.\tA\tG\t.\t.\tCLNSIG=pathogenic;CLNACC=RCV1
+1\t200\t.\tACGT\tA\t.\t.\tCLNSIG=benign;CLNACC=RCV2
+1\t200\t.\ta\tt\t.\t.\tCLNACC=RCV3
+MT\t10\t.\tC\tT\t.\t.\tCLNACC=RCV4
+";
+
+    const SAMPLE: &str = "##fileformat=VCFv4.3
+##contig=<ID=chr1>
+##contig=<ID=chrM>
+#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO
+chr1\t100\t.\tA\tC,G\t.\t.\t.
+chr1\t199\t.\tGACGT\tGA\t.\t.\t.
+chr1\t202\t.\tG\tA\t.\t.\t.
+chrM\t10\t.\tC\tT\t.\t.\tDP=3
+";
+
+    /// INFO column of every record of the annotated sample, and the counts
+    fn annotated(mode: MatchMode) -> (Vec<String>, AnnotateCounts) {
+        let mut index = ClinVarIndex::from_vcf(CLINVAR.as_bytes(), None, None).unwrap();
+        let mut output = Vec::new();
+        let counts = annotate(&mut index, mode, "", SAMPLE.as_bytes(), &mut output).unwrap();
+        let info = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .filter(|l| !l.starts_with('#'))
+            .map(|l| l.split('\t').nth(7).unwrap().to_string())
+            .collect();
+        (info, counts)
+    }
+
+    #[test]
+    fn test_trim() {
+        assert_eq!(trim(199, "GACGT", "GA"), (200, "ACGT".into(), "A".into()));
+        assert_eq!(trim(10, "CTT", "CT"), (10, "CT".into(), "C".into()));
+        assert_eq!(trim(10, "ATG", "AG"), (10, "AT".into(), "A".into()));
+        assert_eq!(trim(10, "A", "G"), (10, "A".into(), "G".into()));
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("a%3Bb%2Cc%3D"), "a;b,c=");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+    }
+
+    #[test]
+    fn test_from_vcf() {
+        let index = ClinVarIndex::from_vcf(CLINVAR.as_bytes(), None, None).unwrap();
+        assert_eq!(index.len(), 4);
+        let keys: Vec<&str> = index.keys().iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, ["CLNSIG", "CLNACC"]);
+        assert_eq!(index.keys()[0].1, "Clinical significance");
+        let keys = ["CLNACC".to_string()];
+        let index = ClinVarIndex::from_vcf(CLINVAR.as_bytes(), Some(&keys), None).unwrap();
+        assert_eq!(index.keys().len(), 1);
+    }
+
+    #[test]
+    fn test_exact() {
+        let (info, counts) = annotated(MatchMode::Exact);
+        assert_eq!(
+            info,
+            [
+                "CLNSIG=.,pathogenic;CLNACC=.,RCV1",
+                "CLNSIG=benign;CLNACC=RCV2",
+                ".",
+                "DP=3;CLNACC=RCV4",
+            ]
+        );
+        assert_eq!(counts.records, 4);
+        assert_eq!(counts.annotated, 3);
+    }
+
+    #[test]
+    fn test_position() {
+        let (info, counts) = annotated(MatchMode::Position);
+        assert_eq!(
+            info,
+            [
+                "CLNSIG=pathogenic;CLNACC=RCV1",
+                // Upstream deletion and SNV within the REF allele
+                "CLNSIG=benign|.;CLNACC=RCV2|RCV3",
+                "CLNSIG=benign;CLNACC=RCV2",
+                "DP=3;CLNACC=RCV4",
+            ]
+        );
+        assert_eq!(counts.annotated, 4);
+    }
+
+    #[test]
+    fn test_from_xml() {
+        let xml = include_str!("../clinvarset_new.xml");
+        let fields = FieldSpec::parse("CLNACC=rcv,CLNDN=conditions").unwrap();
+        let mut builder = ClinVarIndexBuilder::new(fields, Filter::default(), "GRCh38", None);
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        builder
+            .handle_clinvar_set(&ClinVarSetEvent::new(doc.root_element()))
+            .unwrap();
+        let mut index = builder.into_index();
+        assert_eq!(index.len(), 1);
+        // The same deletion, with an extra shared base at the end
+        let matches = index
+            .exact_matches("chr10", 124408632, "CCACTCCTATG", "CG")
+            .unwrap();
+        assert_eq!(index.joined(&matches, 0).as_deref(), Some("RCV000000188"));
+        assert!(index
+            .exact_matches("10", 124408632, "CCACTCCTAT", "T")
+            .unwrap()
+            .is_empty());
+        assert_eq!(index.overlapping("10", 124408641, 124408641), [0]);
+        assert!(index.overlapping("10", 124408642, 124408650).is_empty());
+    }
+}
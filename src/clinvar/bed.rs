@@ -0,0 +1,210 @@
+//! BED6+ output: one interval per `SequenceLocation` of the selected assembly.
+//!
+//! Columns are chrom, chromStart, chromEnd, name (VCV, or rsID), score (star rating, 0 to 4), strand,
+//! then CLNSIG and GENE (pipe-separated).
+use crate::clinvar::model::{Measure, SequenceLocation};
+use crate::clinvar::record::{ChromosomeStyle, ClinVarRecord};
+use crate::error::ClinvarXMLTabError;
+use crate::xml::handler::{ClinVarSetEvent, EventHandler};
+
+const HEADER: &str = "#chrom\tchromStart\tchromEnd\tname\tscore\tstrand\tCLNSIG\tGENE\n";
+
+/// 0-based, half-open interval covered by `location`.
+/// Small variants have VCF alleles, that give their exact extent. Larger ones (SVs, CNVs...) only have
+/// `start`/`stop`, or their inner and outer bounds when imprecise.
+fn interval(location: &SequenceLocation) -> Option<(u64, u64)> {
+    if let (Some(position), Some(reference)) =
+        (location.position_vcf, &location.reference_allele_vcf)
+    {
+        let start = position.saturating_sub(1);
+        return Some((start, start + reference.len() as u64));
+    }
+    let start = location
+        .start
+        .or(location.inner_start)
+        .or(location.outer_start)?;
+    let stop = location
+        .stop
+        .or(location.inner_stop)
+        .or(location.outer_stop)?;
+    Some((start.saturating_sub(1), stop))
+}
+
+fn rsid(measure: &Measure) -> Option<String> {
+    measure
+        .xrefs
+        .iter()
+        .find(|x| x.db == "dbSNP")
+        .map(|x| format!("rs{}", x.id))
+}
+
+pub struct BedRecordHandler<W: std::io::Write> {
+    /// Lines of the current ClinVarSet
+    lines: Vec<String>,
+    writer: W,
+    written: u64,
+    assembly: &'static str,
+    chromosome_style: ChromosomeStyle,
+}
+
+impl<W: std::io::Write> EventHandler for BedRecordHandler<W> {
+    fn handle_clinvar_set(&mut self, event: &ClinVarSetEvent) -> Result<(), ClinvarXMLTabError> {
+        let set = event.set()?;
+        let Some(measure_set) = &set.reference_clinvar_assertion.measure_set else {
+            return Ok(());
+        };
+        let record = ClinVarRecord::from_clinvar_set(set, None);
+        let score = record.stars().unwrap_or(0);
+        let clnsig = record.clnsig().unwrap_or(".");
+        let genes = if record.genes().is_empty() {
+            ".".to_string()
+        } else {
+            record.genes().join("|")
+        };
+
+        for measure in &measure_set.measures {
+            let name = record
+                .vcv()
+                .map(str::to_string)
+                .or_else(|| rsid(measure))
+                .unwrap_or_else(|| ".".to_string());
+            for location in measure
+                .sequence_locations
+                .iter()
+                .filter(|l| l.assembly == self.assembly)
+            {
+                let Some((start, end)) = interval(location) else {
+                    continue;
+                };
+                let strand = location
+                    .strand
+                    .as_deref()
+                    .filter(|s| matches!(*s, "+" | "-"))
+                    .unwrap_or(".");
+                let line = format!(
+                    "{}\t{start}\t{end}\t{name}\t{score}\t{strand}\t{clnsig}\t{genes}\n",
+                    self.chromosome_style.name(&location.chr),
+                );
+                // The same location is sometimes given once per accession (e.g. chromosome and contig)
+                if !self.lines.contains(&line) {
+                    self.lines.push(line);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn end_record(&mut self) -> Result<(), ClinvarXMLTabError> {
+        for line in &self.lines {
+            self.writer.write_all(line.as_bytes())?;
+        }
+        if !self.lines.is_empty() {
+            self.written += 1;
+        }
+        self.lines.clear();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), ClinvarXMLTabError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn records_written(&self) -> Option<u64> {
+        Some(self.written)
+    }
+}
+
+impl<W: std::io::Write> BedRecordHandler<W> {
+    /// `header` writes a `#`-commented line naming the columns first
+    pub fn new_from_writer(
+        mut writer: W,
+        assembly: &'static str,
+        chromosome_style: ChromosomeStyle,
+        header: bool,
+    ) -> Result<Self, ClinvarXMLTabError> {
+        if header {
+            writer.write_all(HEADER.as_bytes())?;
+        }
+        Ok(Self {
+            lines: Vec::new(),
+            writer,
+            written: 0,
+            assembly,
+            chromosome_style,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const XML: &str = include_str!("../clinvarset_new.xml");
+
+    fn location(attributes: &str) -> SequenceLocation {
+        quick_xml::de::from_str(&format!(
+            r#"<SequenceLocation Assembly="GRCh38" Chr="1" {attributes}/>"#
+        ))
+        .unwrap()
+    }
+
+    fn convert(xml: &str, assembly: &'static str, chromosome_style: ChromosomeStyle) -> String {
+        let mut handler =
+            BedRecordHandler::new_from_writer(Vec::new(), assembly, chromosome_style, false)
+                .unwrap();
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        handler
+            .handle_clinvar_set(&ClinVarSetEvent::new(doc.root_element()))
+            .unwrap();
+        handler.end_record().unwrap();
+        String::from_utf8(handler.writer).unwrap()
+    }
+
+    #[test]
+    fn test_interval() {
+        // An SNV and a deletion cover their VCF REF allele, whatever start/stop say
+        let snv = location(r#"start="100" stop="100" positionVCF="100" referenceAlleleVCF="A""#);
+        assert_eq!(interval(&snv), Some((99, 100)));
+        let deletion =
+            location(r#"start="101" stop="109" positionVCF="100" referenceAlleleVCF="CCACTCCTAT""#);
+        assert_eq!(interval(&deletion), Some((99, 109)));
+
+        // SVs have start/stop, else their inner bounds, else their outer ones
+        let sv = location(r#"start="1000" stop="5000" innerStart="900" innerStop="6000""#);
+        assert_eq!(interval(&sv), Some((999, 5000)));
+        let sv =
+            location(r#"innerStart="1000" innerStop="5000" outerStart="900" outerStop="6000""#);
+        assert_eq!(interval(&sv), Some((999, 5000)));
+        let sv = location(r#"outerStart="900" outerStop="6000""#);
+        assert_eq!(interval(&sv), Some((899, 6000)));
+        assert_eq!(interval(&location(r#"start="1000""#)), None);
+    }
+
+    #[test]
+    fn test_bed() {
+        // Only the deletion: the location of the gene it is in is not the variant's
+        assert_eq!(
+            convert(XML, "GRCh38", ChromosomeStyle::Ucsc),
+            "chr10\t124408631\t124408641\tVCV000000165\t0\t.\tpathogenic/likely_pathogenic\tOAT\n"
+        );
+        assert_eq!(
+            convert(XML, "GRCh37", ChromosomeStyle::Ensembl),
+            "10\t126097200\t126097210\tVCV000000165\t0\t.\tpathogenic/likely_pathogenic\tOAT\n"
+        );
+        // Locations on the other assembly only are skipped
+        let grch37 = XML.replace(r#"Assembly="GRCh38""#, r#"Assembly="NCBI36""#);
+        assert_eq!(convert(&grch37, "GRCh38", ChromosomeStyle::Ucsc), "");
+
+        // Without a VCV, the name is the rsID, and the score the number of stars
+        let xml = XML.replacen(r#" Acc="VCV000000165""#, "", 1).replacen(
+            "<ReviewStatus>no assertion criteria provided</ReviewStatus>",
+            "<ReviewStatus>reviewed by expert panel</ReviewStatus>",
+            1,
+        );
+        assert_eq!(
+            convert(&xml, "GRCh38", ChromosomeStyle::Ucsc),
+            "chr10\t124408631\t124408641\trs386833609\t3\t.\tpathogenic/likely_pathogenic\tOAT\n"
+        );
+    }
+}
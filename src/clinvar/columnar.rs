@@ -0,0 +1,318 @@
+//! Columnar output (Apache Parquet or Arrow IPC), with a fixed schema derived from `ClinVarRecord`.
+use std::sync::Arc;
+
+use arrow_array::builder::{ListBuilder, StringBuilder};
+use arrow_array::types::Int32Type;
+use arrow_array::{ArrayRef, DictionaryArray, RecordBatch, StringArray, UInt64Array};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+
+use crate::clinvar::record::ClinVarRecord;
+use crate::error::ClinvarXMLTabError;
+use crate::xml::handler::{ClinVarSetEvent, EventHandler};
+
+/// Number of records per batch, which is also the number of rows per Parquet row group
+const BATCH_SIZE: usize = 64 * 1024;
+
+/// Schema of the columnar outputs
+pub fn schema() -> SchemaRef {
+    let dictionary = DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8));
+    let list = DataType::List(Arc::new(Field::new("item", DataType::Utf8, true)));
+    Arc::new(Schema::new(vec![
+        Field::new("clinvar_id", DataType::UInt64, true),
+        Field::new("status", dictionary.clone(), true),
+        Field::new("replaces", DataType::Utf8, true),
+        Field::new("rcv", DataType::Utf8, true),
+        Field::new("vcv", DataType::Utf8, true),
+        Field::new("clnsig", dictionary.clone(), true),
+        Field::new("review_status", dictionary.clone(), true),
+        Field::new("description", DataType::Utf8, true),
+        Field::new("date_last_updated", DataType::Utf8, true),
+        Field::new("chromosome", dictionary, true),
+        Field::new("position", DataType::UInt64, true),
+        Field::new("reference", DataType::Utf8, true),
+        Field::new("alternate", DataType::Utf8, true),
+        Field::new("conditions", list.clone(), true),
+        Field::new("genes", list.clone(), true),
+        Field::new("hgvs", list.clone(), true),
+        Field::new("submissions", list, true),
+    ]))
+}
+
+fn list_array<'a>(values: impl Iterator<Item = &'a [String]>) -> ArrayRef {
+    let mut builder = ListBuilder::new(StringBuilder::new());
+    for list in values {
+        for value in list {
+            builder.values().append_value(value);
+        }
+        builder.append(true);
+    }
+    Arc::new(builder.finish())
+}
+
+fn dictionary_array<'a>(values: impl Iterator<Item = Option<&'a str>>) -> ArrayRef {
+    Arc::new(values.collect::<DictionaryArray<Int32Type>>())
+}
+
+/// Turns a batch of records into columns
+pub fn record_batch(
+    schema: SchemaRef,
+    records: &[ClinVarRecord],
+) -> Result<RecordBatch, ClinvarXMLTabError> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter(
+            records.iter().map(|r| r.clinvar_id().map(|id| id as u64)),
+        )),
+        dictionary_array(records.iter().map(|r| r.status())),
+        Arc::new(StringArray::from_iter(records.iter().map(|r| r.replaces()))),
+        Arc::new(StringArray::from_iter(records.iter().map(|r| r.rcv()))),
+        Arc::new(StringArray::from_iter(records.iter().map(|r| r.vcv()))),
+        dictionary_array(records.iter().map(|r| r.clnsig())),
+        dictionary_array(records.iter().map(|r| r.review_status())),
+        Arc::new(StringArray::from_iter(
+            records.iter().map(|r| r.description()),
+        )),
+        Arc::new(StringArray::from_iter(
+            records.iter().map(|r| r.date_last_updated()),
+        )),
+        dictionary_array(records.iter().map(|r| r.chromosome())),
+        Arc::new(UInt64Array::from_iter(
+            records.iter().map(|r| r.position().map(|p| p as u64)),
+        )),
+        Arc::new(StringArray::from_iter(
+            records.iter().map(|r| r.reference()),
+        )),
+        Arc::new(StringArray::from_iter(
+            records.iter().map(|r| r.alternate()),
+        )),
+        list_array(records.iter().map(|r| r.conditions())),
+        list_array(records.iter().map(|r| r.genes())),
+        list_array(records.iter().map(|r| r.hgvs())),
+        list_array(records.iter().map(|r| r.submissions())),
+    ];
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+enum ColumnarWriter<W: std::io::Write + Send> {
+    Parquet(ArrowWriter<W>),
+    ArrowIpc(arrow_ipc::writer::FileWriter<W>),
+}
+
+/// Buffers records and writes them in batches, as Parquet row groups or Arrow IPC record batches.
+/// The output is only complete (and readable) once `finish` was called.
+pub struct ColumnarRecordHandler<W: std::io::Write + Send> {
+    record: ClinVarRecord,
+    records: Vec<ClinVarRecord>,
+    schema: SchemaRef,
+    writer: Option<ColumnarWriter<W>>,
+    assembly: &'static str,
+}
+
+impl<W: std::io::Write + Send> ColumnarRecordHandler<W> {
+    /// Parquet output, compressed with zstd at `compression_level` (3 if `None`)
+    pub fn new_parquet_from_writer(
+        writer: W,
+        assembly: &'static str,
+        compression_level: Option<u32>,
+    ) -> Result<Self, ClinvarXMLTabError> {
+        let schema = schema();
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::try_new(
+                compression_level.unwrap_or(3) as i32,
+            )?))
+            .set_max_row_group_size(BATCH_SIZE)
+            .build();
+        let writer = ArrowWriter::try_new(writer, schema.clone(), Some(properties))?;
+        Ok(Self::new(ColumnarWriter::Parquet(writer), schema, assembly))
+    }
+
+    /// Arrow IPC file output
+    pub fn new_arrow_ipc_from_writer(
+        writer: W,
+        assembly: &'static str,
+    ) -> Result<Self, ClinvarXMLTabError> {
+        let schema = schema();
+        let writer = arrow_ipc::writer::FileWriter::try_new(writer, &schema)?;
+        Ok(Self::new(
+            ColumnarWriter::ArrowIpc(writer),
+            schema,
+            assembly,
+        ))
+    }
+
+    fn new(writer: ColumnarWriter<W>, schema: SchemaRef, assembly: &'static str) -> Self {
+        Self {
+            record: ClinVarRecord::default(),
+            records: Vec::with_capacity(BATCH_SIZE),
+            schema,
+            writer: Some(writer),
+            assembly,
+        }
+    }
+
+    fn write_batch(&mut self) -> Result<(), ClinvarXMLTabError> {
+        if self.records.is_empty() {
+            return Ok(());
+        }
+        let batch = record_batch(self.schema.clone(), &self.records)?;
+        match &mut self.writer {
+            Some(ColumnarWriter::Parquet(writer)) => writer.write(&batch)?,
+            Some(ColumnarWriter::ArrowIpc(writer)) => writer.write(&batch)?,
+            None => {}
+        }
+        self.records.clear();
+        Ok(())
+    }
+}
+
+impl<W: std::io::Write + Send> EventHandler for ColumnarRecordHandler<W> {
+    fn handle_clinvar_set(&mut self, event: &ClinVarSetEvent) -> Result<(), ClinvarXMLTabError> {
+        self.record = ClinVarRecord::from_clinvar_set(event.set()?, Some(self.assembly));
+        Ok(())
+    }
+
+    fn end_record(&mut self) -> Result<(), ClinvarXMLTabError> {
+        self.records.push(std::mem::take(&mut self.record));
+        if self.records.len() >= BATCH_SIZE {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), ClinvarXMLTabError> {
+        self.write_batch()?;
+        match self.writer.take() {
+            Some(ColumnarWriter::Parquet(writer)) => {
+                writer.close()?;
+            }
+            Some(ColumnarWriter::ArrowIpc(mut writer)) => {
+                writer.finish()?;
+                writer.get_mut().flush()?;
+            }
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use arrow_array::{Array, ListArray};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    const XML: &str = include_str!("../clinvarset_new.xml");
+
+    fn sample_record() -> ClinVarRecord {
+        let doc = roxmltree::Document::parse(XML).unwrap();
+        let event = ClinVarSetEvent::new(doc.root_element());
+        ClinVarRecord::from_clinvar_set(event.set().unwrap(), Some("GRCh38"))
+    }
+
+    /// Writes `n` copies of the sample record, the first one through the whole handler
+    fn write(mut handler: ColumnarRecordHandler<std::fs::File>, n: usize) {
+        let doc = roxmltree::Document::parse(XML).unwrap();
+        handler
+            .handle_clinvar_set(&ClinVarSetEvent::new(doc.root_element()))
+            .unwrap();
+        handler.end_record().unwrap();
+        let record = sample_record();
+        for _ in 1..n {
+            handler.record = record.clone();
+            handler.end_record().unwrap();
+        }
+        handler.finish().unwrap();
+    }
+
+    /// Checks the schema and the first row of `batch`
+    fn check_batch(batch: &RecordBatch) {
+        let schema = batch.schema();
+        assert_eq!(schema.fields(), super::schema().fields());
+        let field = |name: &str| schema.field_with_name(name).unwrap().data_type().clone();
+        assert_eq!(field("clinvar_id"), DataType::UInt64);
+        assert_eq!(field("position"), DataType::UInt64);
+        for name in ["clnsig", "review_status", "status", "chromosome"] {
+            assert!(matches!(field(name), DataType::Dictionary(_, _)), "{name}");
+        }
+        for name in ["conditions", "genes", "hgvs", "submissions"] {
+            assert!(matches!(field(name), DataType::List(_)), "{name}");
+        }
+
+        let column = |name: &str| batch.column_by_name(name).unwrap();
+        let clinvar_id = column("clinvar_id").as_any().downcast_ref::<UInt64Array>();
+        assert_eq!(clinvar_id.unwrap().value(0), 92148146);
+        let position = column("position").as_any().downcast_ref::<UInt64Array>();
+        assert_eq!(position.unwrap().value(0), 124408632);
+        let rcv = column("rcv")
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(rcv.value(0), "RCV000000188");
+        let clnsig = column("clnsig")
+            .as_any()
+            .downcast_ref::<DictionaryArray<Int32Type>>()
+            .unwrap();
+        let values = clnsig
+            .values()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(
+            values.value(clnsig.keys().value(0) as usize),
+            "pathogenic/likely_pathogenic"
+        );
+        let genes = column("genes")
+            .as_any()
+            .downcast_ref::<ListArray>()
+            .unwrap();
+        let genes = genes.value(0);
+        let genes = genes.as_any().downcast_ref::<StringArray>().unwrap();
+        assert_eq!(genes.iter().collect::<Vec<_>>(), [Some("OAT")]);
+        let submissions = column("submissions").as_any().downcast_ref::<ListArray>();
+        assert_eq!(submissions.unwrap().value(0).len(), 2);
+    }
+
+    #[test]
+    fn test_parquet() {
+        let path = std::env::temp_dir().join(format!("columnar-{}.parquet", std::process::id()));
+        let file = std::fs::File::create(&path).unwrap();
+        let handler = ColumnarRecordHandler::new_parquet_from_writer(file, "GRCh38", None).unwrap();
+        // A full batch, then a partial one
+        write(handler, BATCH_SIZE + 1);
+
+        let reader =
+            ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path).unwrap()).unwrap();
+        let row_groups: Vec<i64> = reader
+            .metadata()
+            .row_groups()
+            .iter()
+            .map(|group| group.num_rows())
+            .collect();
+        assert_eq!(row_groups, [BATCH_SIZE as i64, 1]);
+        let batches: Vec<RecordBatch> = reader.build().unwrap().map(Result::unwrap).collect();
+        check_batch(&batches[0]);
+        let rows: usize = batches.iter().map(RecordBatch::num_rows).sum();
+        assert_eq!(rows, BATCH_SIZE + 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_arrow_ipc() {
+        let path = std::env::temp_dir().join(format!("columnar-{}.arrow", std::process::id()));
+        let file = std::fs::File::create(&path).unwrap();
+        let handler = ColumnarRecordHandler::new_arrow_ipc_from_writer(file, "GRCh38").unwrap();
+        write(handler, 3);
+
+        let reader =
+            arrow_ipc::reader::FileReader::try_new(std::fs::File::open(&path).unwrap(), None)
+                .unwrap();
+        assert_eq!(reader.schema().fields(), schema().fields());
+        let batches: Vec<RecordBatch> = reader.map(Result::unwrap).collect();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].num_rows(), 3);
+        check_batch(&batches[0]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
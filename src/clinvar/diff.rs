@@ -0,0 +1,526 @@
+//! Differences between two ClinVar releases, RCV by RCV.
+//!
+//! The old release is loaded first into a temporary SQLite database (on disk, so that memory stays bounded
+//! whatever the size of the release), then the new one is streamed and compared to it record by record.
+//! Records of the old release that were not found in the new one are reported last, as removed.
+//! An RCV found several times in a release is matched occurrence by occurrence, in order, so that its extra
+//! occurrences are reported as added or removed rather than silently dropped.
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+use crate::clinvar::filter::Filter;
+use crate::clinvar::record::ClinVarRecord;
+use crate::error::ClinvarXMLTabError;
+use crate::xml::handler::{ClinVarSetEvent, EventHandler};
+
+/// Number of ClinVarSets handled per transaction
+const BATCH_SIZE: u64 = 10_000;
+
+const SCHEMA: &str = "
+CREATE TABLE old (
+    rcv TEXT NOT NULL,
+    vcv TEXT,
+    status TEXT,
+    clnsig TEXT,
+    review_status TEXT,
+    coordinates TEXT
+);
+CREATE INDEX old_rcv ON old(rcv);
+";
+
+const HEADER: [&str; 5] = ["CHANGE", "RCV", "VCV", "OLD", "NEW"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffFormat {
+    Tsv,
+    Jsonl,
+}
+
+/// What is compared of a record
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Snapshot {
+    rcv: String,
+    vcv: String,
+    /// Record status (`current`, `replaced`...)
+    status: String,
+    clnsig: String,
+    review_status: String,
+    /// `CHROM:POS:REF:ALT` in the selected assembly, empty if there are none
+    coordinates: String,
+}
+
+impl Snapshot {
+    fn from_record(record: &ClinVarRecord) -> Option<Self> {
+        let coordinates = match (
+            record.chromosome(),
+            record.position(),
+            record.reference(),
+            record.alternate(),
+        ) {
+            (Some(chromosome), Some(position), Some(reference), Some(alternate)) => {
+                format!("{chromosome}:{position}:{reference}:{alternate}")
+            }
+            _ => String::new(),
+        };
+        Some(Self {
+            rcv: record.rcv()?.to_string(),
+            vcv: record.vcv().unwrap_or_default().to_string(),
+            status: record.status().unwrap_or_default().to_string(),
+            clnsig: record.clnsig().unwrap_or_default().to_string(),
+            review_status: record.review_status().unwrap_or_default().to_string(),
+            coordinates,
+        })
+    }
+}
+
+/// Snapshot of the ClinVarSet, if it passes `filter` and has an RCV accession
+fn snapshot(
+    event: &ClinVarSetEvent,
+    filter: &Filter,
+    assembly: &'static str,
+) -> Result<Option<Snapshot>, ClinvarXMLTabError> {
+    let node = event.node();
+    if !filter.matches_status(node) {
+        return Ok(None);
+    }
+    let record = ClinVarRecord::from_clinvar_set(event.set()?, Some(assembly));
+    if !filter.matches(node, &record) {
+        return Ok(None);
+    }
+    Ok(Snapshot::from_record(&record))
+}
+
+/// Loads the old release in a temporary database
+pub struct OldReleaseLoader {
+    connection: Connection,
+    filter: Filter,
+    assembly: &'static str,
+    pending: u64,
+    loaded: u64,
+}
+
+impl OldReleaseLoader {
+    /// Only the ClinVarSets matching `filter` are compared (the same filter should be given to [`DiffHandler`])
+    pub fn new(filter: Filter, assembly: &'static str) -> Result<Self, ClinvarXMLTabError> {
+        // An empty path is a private database on disk, deleted once closed
+        let connection = Connection::open("")?;
+        connection.execute_batch("PRAGMA journal_mode = OFF; PRAGMA synchronous = OFF;")?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self {
+            connection,
+            filter,
+            assembly,
+            pending: 0,
+            loaded: 0,
+        })
+    }
+}
+
+impl EventHandler for OldReleaseLoader {
+    fn handle_clinvar_set(&mut self, event: &ClinVarSetEvent) -> Result<(), ClinvarXMLTabError> {
+        let Some(snapshot) = snapshot(event, &self.filter, self.assembly)? else {
+            return Ok(());
+        };
+        if self.pending == 0 {
+            self.connection.execute_batch("BEGIN")?;
+        }
+        self.connection
+            .prepare_cached("INSERT INTO old VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?
+            .execute(params![
+                snapshot.rcv,
+                snapshot.vcv,
+                snapshot.status,
+                snapshot.clnsig,
+                snapshot.review_status,
+                snapshot.coordinates,
+            ])?;
+        self.pending += 1;
+        self.loaded += 1;
+        if self.pending >= BATCH_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn end_record(&mut self) -> Result<(), ClinvarXMLTabError> {
+        Ok(())
+    }
+
+    /// Commits the current transaction
+    fn flush(&mut self) -> Result<(), ClinvarXMLTabError> {
+        if self.pending > 0 {
+            self.connection.execute_batch("COMMIT")?;
+            self.pending = 0;
+        }
+        Ok(())
+    }
+
+    fn records_written(&self) -> Option<u64> {
+        Some(self.loaded)
+    }
+}
+
+/// How many records changed, by kind of change
+#[derive(Debug, Default, Clone, Copy)]
+struct DiffCounts {
+    added: u64,
+    removed: u64,
+    status: u64,
+    reclassified: u64,
+    review_status: u64,
+    coordinates: u64,
+}
+
+/// A line of the JSON report, with the keys in the order of the TSV columns
+#[derive(Serialize)]
+struct Change<'a> {
+    change: &'a str,
+    rcv: &'a str,
+    vcv: &'a str,
+    old: &'a str,
+    new: &'a str,
+}
+
+enum DiffWriter<W: std::io::Write> {
+    Tsv(Box<csv::Writer<W>>),
+    Jsonl(W),
+}
+
+impl<W: std::io::Write> DiffWriter<W> {
+    fn write(
+        &mut self,
+        change: &str,
+        rcv: &str,
+        vcv: &str,
+        old: &str,
+        new: &str,
+    ) -> Result<(), ClinvarXMLTabError> {
+        match self {
+            Self::Tsv(tsv) => tsv.write_record([change, rcv, vcv, old, new])?,
+            Self::Jsonl(writer) => {
+                let value = Change {
+                    change,
+                    rcv,
+                    vcv,
+                    old,
+                    new,
+                };
+                serde_json::to_writer(&mut *writer, &value)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), ClinvarXMLTabError> {
+        match self {
+            Self::Tsv(tsv) => tsv.flush()?,
+            Self::Jsonl(writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// Compares the new release to the old one loaded by [`OldReleaseLoader`], and writes the changes:
+/// `added`, `removed`, `status` (record status), `reclassified` (CLNSIG), `review_status` and `coordinates`,
+/// one per line
+pub struct DiffHandler<W: std::io::Write> {
+    connection: Connection,
+    filter: Filter,
+    assembly: &'static str,
+    writer: DiffWriter<W>,
+    pending: u64,
+    counts: DiffCounts,
+}
+
+impl<W: std::io::Write> DiffHandler<W> {
+    pub fn new(
+        mut old_release: OldReleaseLoader,
+        writer: W,
+        format: DiffFormat,
+    ) -> Result<Self, ClinvarXMLTabError> {
+        old_release.flush()?;
+        let OldReleaseLoader {
+            connection,
+            filter,
+            assembly,
+            ..
+        } = old_release;
+        let writer = match format {
+            DiffFormat::Tsv => {
+                let mut tsv = csv::WriterBuilder::new()
+                    .delimiter(b'\t')
+                    .from_writer(writer);
+                tsv.write_record(HEADER)?;
+                DiffWriter::Tsv(Box::new(tsv))
+            }
+            DiffFormat::Jsonl => DiffWriter::Jsonl(writer),
+        };
+        Ok(Self {
+            connection,
+            filter,
+            assembly,
+            writer,
+            pending: 0,
+            counts: DiffCounts::default(),
+        })
+    }
+
+    fn compare(&mut self, new: &Snapshot) -> Result<(), ClinvarXMLTabError> {
+        // The first occurrence of the RCV in the old release that is not matched yet
+        let old = self
+            .connection
+            .prepare_cached(
+                "SELECT rowid, rcv, vcv, status, clnsig, review_status, coordinates FROM old
+                WHERE rcv = ?1 ORDER BY rowid LIMIT 1",
+            )?
+            .query_row([&new.rcv], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    Snapshot {
+                        rcv: row.get(1)?,
+                        vcv: row.get(2)?,
+                        status: row.get(3)?,
+                        clnsig: row.get(4)?,
+                        review_status: row.get(5)?,
+                        coordinates: row.get(6)?,
+                    },
+                ))
+            })
+            .optional()?;
+        let Some((rowid, old)) = old else {
+            self.counts.added += 1;
+            return self
+                .writer
+                .write("added", &new.rcv, &new.vcv, "", &new.clnsig);
+        };
+        // What is left once the new release is read has been removed
+        self.connection
+            .prepare_cached("DELETE FROM old WHERE rowid = ?1")?
+            .execute([rowid])?;
+        if old.status != new.status {
+            self.counts.status += 1;
+            self.writer
+                .write("status", &new.rcv, &new.vcv, &old.status, &new.status)?;
+        }
+        if old.clnsig != new.clnsig {
+            self.counts.reclassified += 1;
+            self.writer
+                .write("reclassified", &new.rcv, &new.vcv, &old.clnsig, &new.clnsig)?;
+        }
+        if old.review_status != new.review_status {
+            self.counts.review_status += 1;
+            self.writer.write(
+                "review_status",
+                &new.rcv,
+                &new.vcv,
+                &old.review_status,
+                &new.review_status,
+            )?;
+        }
+        if old.coordinates != new.coordinates {
+            self.counts.coordinates += 1;
+            self.writer.write(
+                "coordinates",
+                &new.rcv,
+                &new.vcv,
+                &old.coordinates,
+                &new.coordinates,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: std::io::Write> EventHandler for DiffHandler<W> {
+    fn handle_clinvar_set(&mut self, event: &ClinVarSetEvent) -> Result<(), ClinvarXMLTabError> {
+        let Some(snapshot) = snapshot(event, &self.filter, self.assembly)? else {
+            return Ok(());
+        };
+        if self.pending == 0 {
+            self.connection.execute_batch("BEGIN")?;
+        }
+        self.compare(&snapshot)?;
+        self.pending += 1;
+        if self.pending >= BATCH_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn end_record(&mut self) -> Result<(), ClinvarXMLTabError> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), ClinvarXMLTabError> {
+        if self.pending > 0 {
+            self.connection.execute_batch("COMMIT")?;
+            self.pending = 0;
+        }
+        self.writer.flush()
+    }
+
+    /// Writes the records of the old release that were not in the new one
+    fn finish(&mut self) -> Result<(), ClinvarXMLTabError> {
+        self.flush()?;
+        let mut statement = self
+            .connection
+            .prepare("SELECT rcv, vcv, clnsig FROM old ORDER BY rcv, rowid")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let (rcv, vcv, clnsig): (String, String, String) =
+                (row.get(0)?, row.get(1)?, row.get(2)?);
+            self.writer.write("removed", &rcv, &vcv, &clnsig, "")?;
+            self.counts.removed += 1;
+        }
+        self.writer.flush()
+    }
+
+    fn records_written(&self) -> Option<u64> {
+        let counts = self.counts;
+        Some(
+            counts.added
+                + counts.removed
+                + counts.status
+                + counts.reclassified
+                + counts.review_status
+                + counts.coordinates,
+        )
+    }
+
+    fn counters(&self) -> Vec<(&'static str, u64)> {
+        let counts = self.counts;
+        vec![
+            ("added", counts.added),
+            ("removed", counts.removed),
+            ("status", counts.status),
+            ("reclassified", counts.reclassified),
+            ("review_status", counts.review_status),
+            ("coordinates", counts.coordinates),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const XML: &str = include_str!("../clinvarset_new.xml");
+
+    fn feed(handler: &mut dyn EventHandler, releases: &[&str]) {
+        for xml in releases {
+            let doc = roxmltree::Document::parse(xml).unwrap();
+            let event = ClinVarSetEvent::new(doc.root_element());
+            handler.handle_clinvar_set(&event).unwrap();
+            handler.end_record().unwrap();
+        }
+    }
+
+    /// Changes from the `old` to the `new` ClinVarSets, and their counts
+    fn diff(old: &[&str], new: &[&str]) -> (Vec<Vec<String>>, Vec<(&'static str, u64)>) {
+        let mut loader = OldReleaseLoader::new(Filter::default(), "GRCh38").unwrap();
+        feed(&mut loader, old);
+        let mut output = Vec::new();
+        let counters = {
+            let mut handler = DiffHandler::new(loader, &mut output, DiffFormat::Tsv).unwrap();
+            feed(&mut handler, new);
+            handler.finish().unwrap();
+            handler.counters()
+        };
+        let lines = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|l| l.split('\t').map(str::to_string).collect())
+            .collect();
+        (lines, counters)
+    }
+
+    fn count(counters: &[(&'static str, u64)], name: &str) -> u64 {
+        counters.iter().find(|(n, _)| *n == name).unwrap().1
+    }
+
+    #[test]
+    fn test_unchanged() {
+        let (lines, counters) = diff(&[XML], &[XML]);
+        assert!(lines.is_empty());
+        assert!(counters.iter().all(|(_, count)| *count == 0));
+    }
+
+    #[test]
+    fn test_changes() {
+        let reclassified = XML.replace(
+            ">Pathogenic/Likely pathogenic</Description>",
+            ">Benign</Description>",
+        );
+        let replaced = XML.replacen(
+            "<RecordStatus>current</RecordStatus>",
+            "<RecordStatus>replaced</RecordStatus>",
+            1,
+        );
+        let moved = XML.replace(r#"positionVCF="124408632""#, r#"positionVCF="124408633""#);
+        let other = XML.replace("RCV000000188", "RCV000000999");
+
+        let (lines, counters) = diff(&[XML], &[&reclassified]);
+        assert_eq!(
+            lines,
+            [[
+                "reclassified",
+                "RCV000000188",
+                "VCV000000165",
+                "pathogenic/likely_pathogenic",
+                "benign"
+            ]]
+        );
+        assert_eq!(count(&counters, "reclassified"), 1);
+
+        let (lines, counters) = diff(&[XML], &[&replaced]);
+        assert_eq!(
+            lines[0][..],
+            [
+                "status",
+                "RCV000000188",
+                "VCV000000165",
+                "current",
+                "replaced"
+            ]
+        );
+        assert_eq!(count(&counters, "status"), 1);
+
+        let (lines, _) = diff(&[XML], &[&moved]);
+        assert_eq!(lines[0][0], "coordinates");
+        assert_eq!(lines[0][3], "chr10:124408632:CCACTCCTAT:C");
+        assert_eq!(lines[0][4], "chr10:124408633:CCACTCCTAT:C");
+
+        // Added ones come as they are read, removed ones last
+        let (lines, counters) = diff(&[XML], &[&other]);
+        let changes: Vec<(&str, &str)> = lines
+            .iter()
+            .map(|l| (l[0].as_str(), l[1].as_str()))
+            .collect();
+        assert_eq!(
+            changes,
+            [("added", "RCV000000999"), ("removed", "RCV000000188")]
+        );
+        assert_eq!(count(&counters, "added"), 1);
+        assert_eq!(count(&counters, "removed"), 1);
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let reclassified = XML.replace(
+            ">Pathogenic/Likely pathogenic</Description>",
+            ">Benign</Description>",
+        );
+        // Matched in order: the second occurrence is compared to the second one, the third is removed
+        let (lines, counters) = diff(&[XML, &reclassified, XML], &[XML, XML]);
+        let changes: Vec<&str> = lines.iter().map(|l| l[0].as_str()).collect();
+        assert_eq!(changes, ["reclassified", "removed"]);
+        assert_eq!(lines[0][3..], ["benign", "pathogenic/likely_pathogenic"]);
+        assert_eq!(count(&counters, "removed"), 1);
+
+        let (lines, _) = diff(&[XML], &[XML, XML]);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0][0], "added");
+    }
+}
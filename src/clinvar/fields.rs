@@ -0,0 +1,481 @@
+//! User-selected output columns.
+//!
+//! A field spec is a comma-separated list of fields (or one per line in a fields file), each being either
+//! a built-in field of [`ClinVarRecord`] (`rcv`, `vcv`, `clnsig`, `chrom`, `pos`...) or a path expression
+//! evaluated on the ClinVarSet element:
+//!
+//! - `ReferenceClinVarAssertion/MeasureSet/Measure/CytogeneticLocation`: text of the matching elements
+//! - `ReferenceClinVarAssertion/ClinVarAccession@Acc`: value of an attribute of the matching elements
+//! - `...SequenceLocation@Accession` (or `//SequenceLocation@Accession`): the first element can be at any depth
+//!
+//! A field can be given another name with `NAME=field`. Repeated matches are pipe-joined.
+use std::path::Path;
+
+use crate::clinvar::record::{ClinVarRecord, JsonObject};
+use crate::error::ClinvarXMLTabError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Builtin {
+    ClinvarId,
+    Status,
+    Replaces,
+    Rcv,
+    Vcv,
+    Clnsig,
+    ReviewStatus,
+    Stars,
+    Description,
+    DateLastUpdated,
+    Chrom,
+    Pos,
+    Ref,
+    Alt,
+    Conditions,
+    Genes,
+    Hgvs,
+    Submissions,
+}
+
+impl Builtin {
+    /// Accepted names, case-insensitive
+    const NAMES: [(Builtin, &'static [&'static str]); 18] = [
+        (Builtin::ClinvarId, &["clinvar_id", "id"]),
+        (Builtin::Status, &["status"]),
+        (Builtin::Replaces, &["replaces"]),
+        (Builtin::Rcv, &["rcv"]),
+        (Builtin::Vcv, &["vcv"]),
+        (Builtin::Clnsig, &["clnsig"]),
+        (Builtin::ReviewStatus, &["review_status"]),
+        (Builtin::Stars, &["stars"]),
+        (Builtin::Description, &["description", "title"]),
+        (Builtin::DateLastUpdated, &["date_last_updated"]),
+        (Builtin::Chrom, &["chrom", "chromosome"]),
+        (Builtin::Pos, &["pos", "position"]),
+        (Builtin::Ref, &["ref", "reference"]),
+        (Builtin::Alt, &["alt", "alternate"]),
+        (Builtin::Conditions, &["conditions"]),
+        (Builtin::Genes, &["genes", "gene"]),
+        (Builtin::Hgvs, &["hgvs"]),
+        (Builtin::Submissions, &["submissions", "scv"]),
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        Self::NAMES
+            .iter()
+            .find(|(_, names)| names.contains(&name.as_str()))
+            .map(|(builtin, _)| *builtin)
+    }
+
+    /// Whether the value is an integer
+    pub fn is_integer(&self) -> bool {
+        matches!(self, Self::ClinvarId | Self::Stars | Self::Pos)
+    }
+
+    pub fn values(&self, record: &ClinVarRecord) -> Vec<String> {
+        let single = |value: Option<&str>| value.map(str::to_string).into_iter().collect();
+        match self {
+            Self::ClinvarId => record
+                .clinvar_id()
+                .map(|v| v.to_string())
+                .into_iter()
+                .collect(),
+            Self::Status => single(record.status()),
+            Self::Replaces => single(record.replaces()),
+            Self::Rcv => single(record.rcv()),
+            Self::Vcv => single(record.vcv()),
+            Self::Clnsig => single(record.clnsig()),
+            Self::ReviewStatus => single(record.review_status()),
+            Self::Stars => record.stars().map(|v| v.to_string()).into_iter().collect(),
+            Self::Description => single(record.description()),
+            Self::DateLastUpdated => single(record.date_last_updated()),
+            Self::Chrom => single(record.chromosome()),
+            Self::Pos => record
+                .position()
+                .map(|v| v.to_string())
+                .into_iter()
+                .collect(),
+            Self::Ref => single(record.reference()),
+            Self::Alt => single(record.alternate()),
+            Self::Conditions => record.conditions().to_vec(),
+            Self::Genes => record.genes().to_vec(),
+            Self::Hgvs => record.hgvs().to_vec(),
+            Self::Submissions => record.submissions().to_vec(),
+        }
+    }
+
+    /// Whether the field can have several values (and is then written as an array in JSON)
+    pub fn is_multi_valued(&self) -> bool {
+        matches!(
+            self,
+            Self::Conditions | Self::Genes | Self::Hgvs | Self::Submissions
+        )
+    }
+}
+
+/// Path expression evaluated on a ClinVarSet element
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathExpr {
+    /// Whether the first step can match at any depth
+    descendant: bool,
+    steps: Vec<String>,
+    attribute: Option<String>,
+}
+
+impl PathExpr {
+    pub fn parse(expression: &str) -> Result<Self, ClinvarXMLTabError> {
+        let invalid = || ClinvarXMLTabError::FieldSpecError(expression.to_string());
+        let (descendant, rest) = if let Some(rest) = expression
+            .strip_prefix("...")
+            .or_else(|| expression.strip_prefix("//"))
+        {
+            (true, rest)
+        } else {
+            (false, expression)
+        };
+        let (path, attribute) = match rest.split_once('@') {
+            Some((path, attribute)) => (path, Some(attribute.to_string())),
+            None => (rest, None),
+        };
+        let steps: Vec<String> = if path.is_empty() {
+            Vec::new()
+        } else {
+            path.split('/').map(str::to_string).collect()
+        };
+        let valid_name = |s: &str| !s.is_empty() && !s.contains(['@', '/', ' ']);
+        if !steps.iter().all(|s| valid_name(s))
+            || !attribute.as_deref().is_none_or(valid_name)
+            || (steps.is_empty() && (descendant || attribute.is_none()))
+        {
+            return Err(invalid());
+        }
+        Ok(Self {
+            descendant,
+            steps,
+            attribute,
+        })
+    }
+
+    /// Values matched in `clinvar_set`, deduplicated but in document order
+    pub fn values(&self, clinvar_set: &roxmltree::Node) -> Vec<String> {
+        let mut steps = self.steps.iter().map(String::as_str);
+        let mut nodes: Vec<roxmltree::Node> = vec![*clinvar_set];
+        if !self.descendant && self.steps.first().map(String::as_str) == Some("ClinVarSet") {
+            steps.next();
+        }
+        for (i, step) in steps.enumerate() {
+            nodes = nodes
+                .iter()
+                .flat_map(|n| {
+                    let candidates: Box<dyn Iterator<Item = roxmltree::Node>> =
+                        if i == 0 && self.descendant {
+                            Box::new(n.descendants().skip(1))
+                        } else {
+                            Box::new(n.children())
+                        };
+                    candidates.filter(|c| c.has_tag_name(step))
+                })
+                .collect();
+        }
+        let mut values: Vec<String> = Vec::new();
+        for node in nodes {
+            let value = match &self.attribute {
+                Some(attribute) => node.attribute(attribute.as_str()),
+                None => node.text().map(str::trim),
+            };
+            if let Some(value) = value.filter(|v| !v.is_empty()) {
+                if !values.iter().any(|v| v == value) {
+                    values.push(value.to_string());
+                }
+            }
+        }
+        values
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldKind {
+    Builtin(Builtin),
+    Path(PathExpr),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    name: String,
+    kind: FieldKind,
+}
+
+impl Field {
+    /// Parses `[NAME=]field`
+    pub fn parse(spec: &str) -> Result<Self, ClinvarXMLTabError> {
+        let (name, expression) = match spec.split_once('=') {
+            Some((name, expression)) => (Some(name.trim()), expression.trim()),
+            None => (None, spec.trim()),
+        };
+        let kind = match Builtin::from_name(expression) {
+            Some(builtin) => FieldKind::Builtin(builtin),
+            None => FieldKind::Path(PathExpr::parse(expression)?),
+        };
+        let name = match (name, &kind) {
+            (Some(name), _) => name.to_string(),
+            (None, FieldKind::Builtin(_)) => expression.to_uppercase(),
+            (None, FieldKind::Path(_)) => expression.to_string(),
+        };
+        Ok(Self { name, kind })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn kind(&self) -> &FieldKind {
+        &self.kind
+    }
+
+    /// Name usable as a VCF INFO key: leading non-letters are dropped, then anything but letters, digits,
+    /// `_` and `.` becomes `_`
+    pub fn info_key(&self) -> String {
+        self.name
+            .trim_start_matches(|c: char| !c.is_ascii_alphabetic())
+            .chars()
+            .map(|c| match c {
+                c if c.is_ascii_alphanumeric() || c == '.' => c,
+                _ => '_',
+            })
+            .collect()
+    }
+
+    pub fn values(&self, clinvar_set: &roxmltree::Node, record: &ClinVarRecord) -> Vec<String> {
+        match &self.kind {
+            FieldKind::Builtin(builtin) => builtin.values(record),
+            FieldKind::Path(path) => path.values(clinvar_set),
+        }
+    }
+
+    /// JSON value of this field: arrays for multi-valued built-ins, numbers for integer ones,
+    /// pipe-joined strings otherwise (`null` when there is no value)
+    pub fn json(&self, values: &[String]) -> serde_json::Value {
+        use serde_json::Value;
+        match &self.kind {
+            FieldKind::Builtin(builtin) if builtin.is_multi_valued() => values.into(),
+            FieldKind::Builtin(builtin) if builtin.is_integer() => values
+                .first()
+                .and_then(|v| v.parse::<u64>().ok())
+                .map_or(Value::Null, Value::from),
+            _ if values.is_empty() => Value::Null,
+            _ => Value::String(values.join("|")),
+        }
+    }
+}
+
+/// Ordered list of fields to output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldSpec {
+    fields: Vec<Field>,
+}
+
+impl FieldSpec {
+    /// Parses a comma-separated list of fields
+    pub fn parse(spec: &str) -> Result<Self, ClinvarXMLTabError> {
+        Self::from_fields(spec.split(','))
+    }
+
+    /// Reads a fields file: one field per line, blank lines and lines starting with `#` are ignored
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ClinvarXMLTabError> {
+        let content = std::fs::read_to_string(path)?;
+        Self::from_fields(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty() && !l.starts_with('#')),
+        )
+    }
+
+    fn from_fields<'a>(specs: impl Iterator<Item = &'a str>) -> Result<Self, ClinvarXMLTabError> {
+        let fields = specs
+            .filter(|s| !s.trim().is_empty())
+            .map(Field::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        if fields.is_empty() {
+            return Err(ClinvarXMLTabError::FieldSpecError(
+                "no fields given".to_string(),
+            ));
+        }
+        Ok(Self { fields })
+    }
+
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.fields.iter().map(Field::name)
+    }
+
+    /// Declares an INFO field per field in `header`, for the VCF output: as they may be repeated, with any
+    /// number of string values
+    pub fn add_info_headers(&self, header: &mut noodles_vcf::Header) {
+        use noodles_vcf::header::record::value::map::info::{Number, Type};
+        use noodles_vcf::header::record::value::{map::Info, Map};
+        for field in &self.fields {
+            header.infos_mut().insert(
+                field.info_key(),
+                Map::<Info>::new(
+                    Number::Unknown,
+                    Type::String,
+                    format!("ClinVar {}", field.name()),
+                ),
+            );
+        }
+    }
+
+    /// Values of every field, in order
+    pub fn values(
+        &self,
+        clinvar_set: &roxmltree::Node,
+        record: &ClinVarRecord,
+    ) -> Vec<Vec<String>> {
+        self.fields
+            .iter()
+            .map(|f| f.values(clinvar_set, record))
+            .collect()
+    }
+
+    /// JSON object with every field, in order
+    pub fn json(&self, values: &[Vec<String>]) -> JsonObject {
+        self.fields
+            .iter()
+            .zip(values)
+            .map(|(field, values)| (field.name.clone(), field.json(values)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const XML: &str = include_str!("../clinvarset_new.xml");
+
+    fn values(expression: &str) -> Vec<String> {
+        let doc = roxmltree::Document::parse(XML).unwrap();
+        PathExpr::parse(expression)
+            .unwrap()
+            .values(&doc.root_element())
+    }
+
+    #[test]
+    fn test_path_parse() {
+        assert_eq!(
+            PathExpr::parse("...SequenceLocation@Accession").unwrap(),
+            PathExpr {
+                descendant: true,
+                steps: vec!["SequenceLocation".to_string()],
+                attribute: Some("Accession".to_string()),
+            }
+        );
+        assert_eq!(
+            PathExpr::parse("//SequenceLocation@Accession").unwrap(),
+            PathExpr::parse("...SequenceLocation@Accession").unwrap()
+        );
+        assert_eq!(
+            PathExpr::parse("ReferenceClinVarAssertion/ClinVarAccession").unwrap(),
+            PathExpr {
+                descendant: false,
+                steps: vec![
+                    "ReferenceClinVarAssertion".to_string(),
+                    "ClinVarAccession".to_string()
+                ],
+                attribute: None,
+            }
+        );
+        // Attribute of the ClinVarSet itself
+        assert!(PathExpr::parse("@ID").is_ok());
+        for invalid in ["", "...", "//@ID", "A//B", "A/", "A@", "A@B@C", "A B", "@"] {
+            assert!(PathExpr::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_path_values() {
+        assert_eq!(values("@ID"), ["92148146"]);
+        assert_eq!(
+            values("ReferenceClinVarAssertion/ClinVarAccession@Acc"),
+            ["RCV000000188"]
+        );
+        // Starting from the ClinVarSet is the same
+        assert_eq!(
+            values("ClinVarSet/ReferenceClinVarAssertion/ClinVarAccession@Acc"),
+            ["RCV000000188"]
+        );
+        assert_eq!(
+            values("ReferenceClinVarAssertion/MeasureSet/Measure/CytogeneticLocation"),
+            ["10q26.13"]
+        );
+        // Repeats are deduplicated, in document order
+        assert_eq!(
+            values("ClinVarAssertion/ClinVarAccession@Acc"),
+            ["SCV000020331", "SCV000081973"]
+        );
+        assert_eq!(
+            values("...SequenceLocation@Accession"),
+            ["NC_000010.11", "NC_000010.10"]
+        );
+        assert_eq!(values("//Citation/ID"), ["2220818"]);
+        // Only children match without `...`
+        assert!(values("SequenceLocation@Accession").is_empty());
+        assert!(values("...SequenceLocation@Missing").is_empty());
+    }
+
+    #[test]
+    fn test_field() {
+        let field = Field::parse("clnsig").unwrap();
+        assert_eq!(field.name(), "CLNSIG");
+        assert_eq!(field.kind(), &FieldKind::Builtin(Builtin::Clnsig));
+        let field = Field::parse(" Significance = CLNSIG ").unwrap();
+        assert_eq!(field.name(), "Significance");
+        assert_eq!(field.kind(), &FieldKind::Builtin(Builtin::Clnsig));
+        let field = Field::parse("...SequenceLocation@Accession").unwrap();
+        assert_eq!(field.name(), "...SequenceLocation@Accession");
+        assert!(matches!(field.kind(), FieldKind::Path(_)));
+        assert!(Field::parse("not a field").is_err());
+
+        assert_eq!(field.info_key(), "SequenceLocation_Accession");
+        assert_eq!(Field::parse("1kg.af=rcv").unwrap().info_key(), "kg.af");
+        assert_eq!(
+            Field::parse("review_status").unwrap().info_key(),
+            "REVIEW_STATUS"
+        );
+
+        let values = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(field.json(&values), serde_json::json!("a|b"));
+        assert_eq!(field.json(&[]), serde_json::Value::Null);
+        let genes = Field::parse("genes").unwrap();
+        assert_eq!(genes.json(&values), serde_json::json!(["a", "b"]));
+        let pos = Field::parse("pos").unwrap();
+        assert_eq!(pos.json(&["12".to_string()]), serde_json::json!(12));
+    }
+
+    #[test]
+    fn test_field_spec() {
+        let path = std::env::temp_dir().join(format!("fields-{}.txt", std::process::id()));
+        std::fs::write(
+            &path,
+            "# Columns\nrcv\n\n  CLNSIG=clnsig  \nLoc=...SequenceLocation@Accession\n",
+        )
+        .unwrap();
+        let spec = FieldSpec::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(spec.names().collect::<Vec<_>>(), ["RCV", "CLNSIG", "Loc"]);
+        assert_eq!(
+            spec,
+            FieldSpec::parse("rcv,CLNSIG=clnsig,Loc=...SequenceLocation@Accession").unwrap()
+        );
+        assert!(FieldSpec::parse(" , ").is_err());
+
+        let mut header = noodles_vcf::Header::default();
+        spec.add_info_headers(&mut header);
+        let keys: Vec<&String> = header.infos().keys().collect();
+        assert_eq!(keys, ["RCV", "CLNSIG", "Loc"]);
+    }
+}
@@ -0,0 +1,742 @@
+//! Record filtering, done while streaming so that rejected ClinVarSets never reach the output handler.
+//!
+//! Filter expressions combine conditions over fields (see [`crate::clinvar::fields`] for their names):
+//!
+//! - comparisons: `stars >= 2`, `clnsig == pathogenic`, `chrom != "chrX"`
+//! - lists: `clnsig in (pathogenic, likely_pathogenic, pathogenic/likely_pathogenic)`
+//! - regular expressions: `conditions ~ "(?i)cardiomyopathy"`, `hgvs !~ "^NC_"`
+//! - presence of a value: `pos`
+//! - `and`, `or`, `not` (or `&&`, `||`, `!`) and parentheses
+//!
+//! Multi-valued fields match if any of their values does (and `!=`, `!~` if none does).
+//! Comparisons are numeric when both sides are numbers.
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use regex::Regex;
+
+use crate::clinvar::fields::Field;
+use crate::clinvar::record::ClinVarRecord;
+use crate::error::ClinvarXMLTabError;
+use crate::xml::handler::{ClinVarSetEvent, EventHandler};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    Comma,
+    And,
+    Or,
+    Not,
+    In,
+    Op(CmpOp),
+    Match { negated: bool },
+    Word(String),
+    Quoted(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, ClinvarXMLTabError> {
+    let invalid = |msg: &str| ClinvarXMLTabError::FilterError(format!("{msg} in `{expression}`"));
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        let two = |chars: &mut std::iter::Peekable<std::str::Chars>, next: char| {
+            chars.next();
+            chars.next_if_eq(&next).is_some()
+        };
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::LParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::RParen);
+            }
+            ',' => {
+                chars.next();
+                tokens.push(Token::Comma);
+            }
+            '~' => {
+                chars.next();
+                tokens.push(Token::Match { negated: false });
+            }
+            '=' => {
+                // Both `=` and `==` are accepted
+                two(&mut chars, '=');
+                tokens.push(Token::Op(CmpOp::Eq));
+            }
+            '!' => tokens.push(if two(&mut chars, '=') {
+                Token::Op(CmpOp::Ne)
+            } else if chars.next_if_eq(&'~').is_some() {
+                Token::Match { negated: true }
+            } else {
+                Token::Not
+            }),
+            '<' => tokens.push(Token::Op(if two(&mut chars, '=') {
+                CmpOp::Le
+            } else {
+                CmpOp::Lt
+            })),
+            '>' => tokens.push(Token::Op(if two(&mut chars, '=') {
+                CmpOp::Ge
+            } else {
+                CmpOp::Gt
+            })),
+            '&' | '|' => {
+                if !two(&mut chars, c) {
+                    return Err(invalid(&format!("lone `{c}`")));
+                }
+                tokens.push(if c == '&' { Token::And } else { Token::Or });
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => value.extend(chars.next()),
+                        Some(q) if q == c => break,
+                        Some(other) => value.push(other),
+                        None => return Err(invalid("unterminated string")),
+                    }
+                }
+                tokens.push(Token::Quoted(value));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(c) =
+                    chars.next_if(|c| !c.is_whitespace() && !"()=!<>~,\"'&|".contains(*c))
+                {
+                    word.push(c);
+                }
+                tokens.push(match word.to_lowercase().as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    "in" => Token::In,
+                    _ => Token::Word(word),
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Field, CmpOp, String),
+    In(Field, Vec<String>),
+    Match(Field, Regex, bool),
+    Exists(Field),
+}
+
+/// Recursive descent parser, from the lowest precedence (`or`) to the highest (`not`)
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    expression: &'a str,
+}
+
+impl Parser<'_> {
+    fn error(&self, msg: &str) -> ClinvarXMLTabError {
+        ClinvarXMLTabError::FilterError(format!("{msg} in `{}`", self.expression))
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        self.position += 1;
+        self.tokens.get(self.position - 1)
+    }
+
+    fn or(&mut self) -> Result<Expr, ClinvarXMLTabError> {
+        let mut left = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, ClinvarXMLTabError> {
+        let mut left = self.not()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            left = Expr::And(Box::new(left), Box::new(self.not()?));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expr, ClinvarXMLTabError> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.primary()
+    }
+
+    fn value(&mut self) -> Result<String, ClinvarXMLTabError> {
+        match self.next() {
+            Some(Token::Word(value) | Token::Quoted(value)) => Ok(value.clone()),
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, ClinvarXMLTabError> {
+        let field = match self.next() {
+            Some(Token::LParen) => {
+                let expr = self.or()?;
+                if self.next() != Some(&Token::RParen) {
+                    return Err(self.error("expected `)`"));
+                }
+                return Ok(expr);
+            }
+            Some(Token::Word(field)) => Field::parse(&field.clone())?,
+            _ => return Err(self.error("expected a field")),
+        };
+        match self.peek().cloned() {
+            Some(Token::Op(op)) => {
+                self.next();
+                Ok(Expr::Compare(field, op, self.value()?))
+            }
+            Some(Token::Match { negated }) => {
+                self.next();
+                let pattern = self.value()?;
+                let regex = Regex::new(&pattern)
+                    .map_err(|e| ClinvarXMLTabError::FilterError(e.to_string()))?;
+                Ok(Expr::Match(field, regex, negated))
+            }
+            Some(Token::In) => {
+                self.next();
+                if self.next() != Some(&Token::LParen) {
+                    return Err(self.error("expected `(` after `in`"));
+                }
+                let mut values = vec![self.value()?];
+                loop {
+                    match self.next() {
+                        Some(Token::Comma) => values.push(self.value()?),
+                        Some(Token::RParen) => break,
+                        _ => return Err(self.error("expected `,` or `)`")),
+                    }
+                }
+                Ok(Expr::In(field, values))
+            }
+            _ => Ok(Expr::Exists(field)),
+        }
+    }
+}
+
+fn compare(value: &str, other: &str) -> std::cmp::Ordering {
+    match (value.parse::<f64>(), other.parse::<f64>()) {
+        (Ok(value), Ok(other)) => value.total_cmp(&other),
+        _ => value.cmp(other),
+    }
+}
+
+impl Expr {
+    fn parse(expression: &str) -> Result<Self, ClinvarXMLTabError> {
+        let tokens = tokenize(expression)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+            expression,
+        };
+        let expr = parser.or()?;
+        if parser.position != tokens.len() {
+            return Err(parser.error("unexpected trailing input"));
+        }
+        Ok(expr)
+    }
+
+    fn matches(&self, clinvar_set: &roxmltree::Node, record: &ClinVarRecord) -> bool {
+        use std::cmp::Ordering;
+        match self {
+            Expr::And(left, right) => {
+                left.matches(clinvar_set, record) && right.matches(clinvar_set, record)
+            }
+            Expr::Or(left, right) => {
+                left.matches(clinvar_set, record) || right.matches(clinvar_set, record)
+            }
+            Expr::Not(expr) => !expr.matches(clinvar_set, record),
+            Expr::Compare(field, op, other) => {
+                let values = field.values(clinvar_set, record);
+                let mut orderings = values.iter().map(|v| compare(v, other));
+                match op {
+                    CmpOp::Eq => orderings.any(|o| o == Ordering::Equal),
+                    CmpOp::Ne => !orderings.any(|o| o == Ordering::Equal),
+                    CmpOp::Lt => orderings.any(|o| o == Ordering::Less),
+                    CmpOp::Le => orderings.any(|o| o != Ordering::Greater),
+                    CmpOp::Gt => orderings.any(|o| o == Ordering::Greater),
+                    CmpOp::Ge => orderings.any(|o| o != Ordering::Less),
+                }
+            }
+            Expr::In(field, others) => field
+                .values(clinvar_set, record)
+                .iter()
+                .any(|v| others.iter().any(|o| compare(v, o).is_eq())),
+            Expr::Match(field, regex, negated) => {
+                field
+                    .values(clinvar_set, record)
+                    .iter()
+                    .any(|v| regex.is_match(v))
+                    != *negated
+            }
+            Expr::Exists(field) => !field.values(clinvar_set, record).is_empty(),
+        }
+    }
+}
+
+/// `chr1` and `1`, `chrM` and `MT` name the same chromosomes
+pub(crate) fn normalize_chromosome(chrom: &str) -> &str {
+    match chrom.strip_prefix("chr").unwrap_or(chrom) {
+        "M" => "MT",
+        chrom => chrom,
+    }
+}
+
+/// Lines of a text file, without blank lines and `#` comments
+fn read_lines<P: AsRef<Path>>(path: P) -> Result<Vec<String>, ClinvarXMLTabError> {
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Merged intervals from a BED file, by chromosome
+#[derive(Debug, Clone, Default)]
+struct Regions {
+    intervals: HashMap<String, Vec<(u64, u64)>>,
+}
+
+impl Regions {
+    fn from_bed<P: AsRef<Path>>(path: P) -> Result<Self, ClinvarXMLTabError> {
+        let mut intervals = Vec::new();
+        for line in read_lines(path)? {
+            if line.starts_with("track") || line.starts_with("browser") {
+                continue;
+            }
+            let mut columns = line.split('\t');
+            let (Some(chrom), Some(start), Some(end)) =
+                (columns.next(), columns.next(), columns.next())
+            else {
+                return Err(ClinvarXMLTabError::FilterError(format!(
+                    "invalid BED line `{line}`"
+                )));
+            };
+            let parse = |v: &str| {
+                v.parse::<u64>().map_err(|_| {
+                    ClinvarXMLTabError::FilterError(format!("invalid BED line `{line}`"))
+                })
+            };
+            intervals.push((chrom.to_string(), parse(start)?, parse(end)?));
+        }
+        Ok(Self::from_intervals(intervals))
+    }
+
+    /// Regions written `chrom:start-end` (1-based, inclusive), or `chrom` for a whole chromosome
+    fn from_strings(regions: &[String]) -> Result<Self, ClinvarXMLTabError> {
+        let mut intervals = Vec::new();
+        for region in regions {
+            let invalid = || ClinvarXMLTabError::FilterError(format!("invalid region `{region}`"));
+            let interval = match region.rsplit_once(':') {
+                Some((chrom, range)) => {
+                    let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+                    let parse = |v: &str| v.replace(',', "").parse::<u64>().map_err(|_| invalid());
+                    (
+                        chrom.to_string(),
+                        parse(start)?.saturating_sub(1),
+                        parse(end)?,
+                    )
+                }
+                None => (region.to_string(), 0, u64::MAX),
+            };
+            intervals.push(interval);
+        }
+        Ok(Self::from_intervals(intervals))
+    }
+
+    /// Merges 0-based, half-open intervals
+    fn from_intervals(list: Vec<(String, u64, u64)>) -> Self {
+        let mut intervals: HashMap<String, Vec<(u64, u64)>> = HashMap::new();
+        for (chrom, start, end) in list {
+            intervals
+                .entry(normalize_chromosome(&chrom).to_string())
+                .or_default()
+                .push((start, end));
+        }
+        for chrom_intervals in intervals.values_mut() {
+            chrom_intervals.sort_unstable();
+            let mut merged: Vec<(u64, u64)> = Vec::with_capacity(chrom_intervals.len());
+            for &(start, end) in chrom_intervals.iter() {
+                match merged.last_mut() {
+                    Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                    _ => merged.push((start, end)),
+                }
+            }
+            *chrom_intervals = merged;
+        }
+        Self { intervals }
+    }
+
+    /// Whether the 1-based `position` is within a region
+    fn contains(&self, chrom: &str, position: usize) -> bool {
+        let Some(intervals) = self.intervals.get(normalize_chromosome(chrom)) else {
+            return false;
+        };
+        let position = position.saturating_sub(1) as u64;
+        let i = intervals.partition_point(|&(start, _)| start <= position);
+        i > 0 && position < intervals[i - 1].1
+    }
+}
+
+/// All the conditions a record has to meet to be written
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    /// Record statuses (`current`, `replaced`, `removed`...) to keep
+    statuses: Option<Vec<String>>,
+    expression: Option<Expr>,
+    genes: Option<HashSet<String>>,
+    regions: Option<Regions>,
+    min_stars: Option<u8>,
+}
+
+impl Filter {
+    /// Keeps the ClinVarSets whose `RecordStatus` is one of `statuses`
+    pub fn with_statuses(mut self, statuses: Vec<String>) -> Self {
+        self.statuses = Some(statuses);
+        self
+    }
+
+    pub fn with_expression(mut self, expression: &str) -> Result<Self, ClinvarXMLTabError> {
+        self.expression = Some(Expr::parse(expression)?);
+        Ok(self)
+    }
+
+    /// Keeps records in any of the genes listed in `path`, one symbol per line
+    pub fn with_genes_file<P: AsRef<Path>>(mut self, path: P) -> Result<Self, ClinvarXMLTabError> {
+        self.genes = Some(read_lines(path)?.into_iter().collect());
+        Ok(self)
+    }
+
+    /// Keeps records in any of the `genes`, or of those already given
+    pub fn with_genes(mut self, genes: Vec<String>) -> Self {
+        self.genes.get_or_insert_with(HashSet::new).extend(genes);
+        self
+    }
+
+    /// Keeps records whose VCF position is in any of the regions of the BED file at `path`
+    pub fn with_regions_bed<P: AsRef<Path>>(mut self, path: P) -> Result<Self, ClinvarXMLTabError> {
+        self.regions = Some(Regions::from_bed(path)?);
+        Ok(self)
+    }
+
+    /// Keeps records whose VCF position is in any of the `regions`, written `chrom:start-end` (1-based, inclusive)
+    /// or `chrom`
+    pub fn with_regions(mut self, regions: &[String]) -> Result<Self, ClinvarXMLTabError> {
+        self.regions = Some(Regions::from_strings(regions)?);
+        Ok(self)
+    }
+
+    pub fn with_min_stars(mut self, min_stars: u8) -> Self {
+        self.min_stars = Some(min_stars);
+        self
+    }
+
+    /// Whether this filter keeps everything
+    pub fn is_empty(&self) -> bool {
+        self.statuses.is_none()
+            && self.expression.is_none()
+            && self.genes.is_none()
+            && self.regions.is_none()
+            && self.min_stars.is_none()
+    }
+
+    /// Whether the record status of `clinvar_set` is one of those kept.
+    /// It is cheap to check, as it doesn't need the ClinVarSet to be deserialized.
+    pub fn matches_status(&self, clinvar_set: &roxmltree::Node) -> bool {
+        let Some(statuses) = &self.statuses else {
+            return true;
+        };
+        let status = clinvar_set
+            .children()
+            .find(|c| c.has_tag_name("RecordStatus"))
+            .and_then(|c| c.text())
+            .map(str::trim)
+            .unwrap_or_default();
+        statuses.iter().any(|s| s == status)
+    }
+
+    /// Whether any condition other than the record status is set
+    pub(crate) fn needs_record(&self) -> bool {
+        self.expression.is_some()
+            || self.genes.is_some()
+            || self.regions.is_some()
+            || self.min_stars.is_some()
+    }
+
+    pub fn matches(&self, clinvar_set: &roxmltree::Node, record: &ClinVarRecord) -> bool {
+        if !self.matches_status(clinvar_set) {
+            return false;
+        }
+        if let Some(min_stars) = self.min_stars {
+            if record.stars().unwrap_or(0) < min_stars {
+                return false;
+            }
+        }
+        if let Some(genes) = &self.genes {
+            if !record.genes().iter().any(|g| genes.contains(g)) {
+                return false;
+            }
+        }
+        if let Some(regions) = &self.regions {
+            match (record.chromosome(), record.position()) {
+                (Some(chrom), Some(position)) if regions.contains(chrom, position) => {}
+                _ => return false,
+            }
+        }
+        self.expression
+            .as_ref()
+            .is_none_or(|e| e.matches(clinvar_set, record))
+    }
+}
+
+/// Only forwards the ClinVarSets that pass `filter` to the wrapped handler
+pub struct FilteredHandler {
+    inner: Box<dyn EventHandler>,
+    filter: Filter,
+    assembly: &'static str,
+    /// Whether the current ClinVarSet passed the filter
+    keep: bool,
+    kept: u64,
+}
+
+impl FilteredHandler {
+    pub fn new(inner: Box<dyn EventHandler>, filter: Filter, assembly: &'static str) -> Self {
+        Self {
+            inner,
+            filter,
+            assembly,
+            keep: false,
+            kept: 0,
+        }
+    }
+}
+
+impl EventHandler for FilteredHandler {
+    fn handle_clinvar_set(&mut self, event: &ClinVarSetEvent) -> Result<(), ClinvarXMLTabError> {
+        let node = event.node();
+        self.keep = if !self.filter.matches_status(node) {
+            false
+        } else if self.filter.needs_record() {
+            // The typed ClinVarSet is then shared with the wrapped handler
+            let record = ClinVarRecord::from_clinvar_set(event.set()?, Some(self.assembly));
+            self.filter.matches(node, &record)
+        } else {
+            true
+        };
+        if self.keep {
+            self.inner.handle_clinvar_set(event)?;
+        }
+        Ok(())
+    }
+
+    fn end_record(&mut self) -> Result<(), ClinvarXMLTabError> {
+        if self.keep {
+            self.inner.end_record()?;
+            self.kept += 1;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), ClinvarXMLTabError> {
+        self.inner.flush()
+    }
+
+    fn finish(&mut self) -> Result<(), ClinvarXMLTabError> {
+        self.inner.finish()
+    }
+
+    /// What the wrapped handler wrote, or else what passed the filter
+    fn records_written(&self) -> Option<u64> {
+        self.inner.records_written().or(Some(self.kept))
+    }
+
+    fn counters(&self) -> Vec<(&'static str, u64)> {
+        self.inner.counters()
+    }
+
+    fn done(&self) -> bool {
+        self.inner.done()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clinvar::model::ClinVarSet;
+
+    const XML: &str = include_str!("../clinvarset_new.xml");
+
+    /// Whether the ClinVarSet of the test file matches `expression`
+    fn matches(expression: &str) -> bool {
+        let doc = roxmltree::Document::parse(XML).unwrap();
+        let set = ClinVarSet::from_xml(XML).unwrap();
+        let record = ClinVarRecord::from_clinvar_set(&set, Some("GRCh38"));
+        Expr::parse(expression)
+            .unwrap()
+            .matches(&doc.root_element(), &record)
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(tokenize("a = 1").unwrap(), tokenize("a == 1").unwrap());
+        assert_eq!(
+            tokenize("!a && b || c").unwrap(),
+            tokenize("not a and b or c").unwrap()
+        );
+        assert_eq!(
+            tokenize(r#"x !~ "a\"b" "#).unwrap(),
+            [
+                Token::Word("x".to_string()),
+                Token::Match { negated: true },
+                Token::Quoted("a\"b".to_string()),
+            ]
+        );
+        assert!(tokenize("a & b").is_err());
+        assert!(tokenize("a == 'b").is_err());
+    }
+
+    #[test]
+    fn test_precedence() {
+        // `and` binds tighter than `or`, `not` tighter than `and`
+        let expr = Expr::parse("stars >= 1 or not pos and gene == OAT").unwrap();
+        let Expr::Or(_, right) = expr else {
+            panic!("{expr:?}")
+        };
+        let Expr::And(left, _) = *right else {
+            panic!("{right:?}")
+        };
+        assert!(matches!(*left, Expr::Not(_)));
+
+        assert!(matches("stars >= 1 or gene == OAT"));
+        assert!(!matches("(stars >= 1 or gene == OAT) and not pos"));
+        assert!(matches("stars >= 1 or gene == OAT and pos"));
+        assert!(!matches("not not stars >= 1"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for expression in [
+            "",
+            "stars >=",
+            "(pos",
+            "pos)",
+            "stars 2",
+            "clnsig in pathogenic",
+        ] {
+            assert!(Expr::parse(expression).is_err(), "{expression}");
+        }
+        assert!(Expr::parse("rcv ~ '('").is_err());
+        // Other names are paths in the ClinVarSet
+        assert!(!matches("Nonexistent == 1"));
+    }
+
+    #[test]
+    fn test_in_and_regex() {
+        assert!(matches("clnsig in (benign, pathogenic/likely_pathogenic)"));
+        assert!(!matches("clnsig in (benign, likely_benign)"));
+        assert!(matches(r#"rcv ~ "^RCV0+188$""#));
+        assert!(!matches(r#"rcv !~ "^RCV0+188$""#));
+        assert!(matches("chrom ~ '(?i)^CHR10$'"));
+        // Multi-valued fields: any value matches, and `!=` if none does
+        assert!(matches("scv == SCV000081973"));
+        assert!(!matches("scv != SCV000081973"));
+        assert!(matches("scv != SCV000000000"));
+    }
+
+    #[test]
+    fn test_compare() {
+        use std::cmp::Ordering;
+        assert_eq!(compare("10", "9"), Ordering::Greater);
+        assert_eq!(compare("1e3", "999.5"), Ordering::Greater);
+        assert_eq!(compare("10a", "9a"), Ordering::Less);
+        // A string comparison would put "124408632" before "99999999"
+        assert!(matches("pos > 99999999"));
+        assert!(matches("pos == 124408632.0"));
+        assert!(matches("chrom < chr9"));
+        assert!(matches("pos"));
+        assert!(!matches(
+            "ReferenceClinVarAssertion/MeasureSet/Measure/CytogeneticLocation == 1p36"
+        ));
+        assert!(matches(
+            "ReferenceClinVarAssertion/ClinVarAccession@Acc == RCV000000188"
+        ));
+    }
+
+    #[test]
+    fn test_regions_merge() {
+        let regions = Regions::from_intervals(vec![
+            ("chr1".to_string(), 50, 60),
+            ("1".to_string(), 0, 10),
+            ("1".to_string(), 10, 20),
+            ("1".to_string(), 5, 15),
+            ("chrM".to_string(), 0, 100),
+        ]);
+        assert_eq!(regions.intervals["1"], [(0, 20), (50, 60)]);
+        assert_eq!(regions.intervals["MT"], [(0, 100)]);
+        assert!(regions.contains("MT", 1));
+        assert!(regions.contains("chrM", 100));
+        assert!(!regions.contains("chrM", 101));
+        assert!(!regions.contains("2", 1));
+    }
+
+    #[test]
+    fn test_regions_boundaries() {
+        // 1-based and inclusive, like 0-based and half-open `1\t99\t200`
+        let regions =
+            Regions::from_strings(&["chr1:100-200".to_string(), "X".to_string()]).unwrap();
+        assert_eq!(regions.intervals["1"], [(99, 200)]);
+        assert!(!regions.contains("1", 99));
+        assert!(regions.contains("1", 100));
+        assert!(regions.contains("chr1", 200));
+        assert!(!regions.contains("1", 201));
+        assert!(regions.contains("chrX", 1));
+        assert!(regions.contains("X", usize::MAX));
+
+        let path = std::env::temp_dir().join(format!("regions_{}.bed", std::process::id()));
+        std::fs::write(&path, "track name=test\n# comment\n1\t99\t200\tname\n").unwrap();
+        let bed = Regions::from_bed(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            bed.intervals,
+            Regions::from_strings(&["1:100-200".to_string()])
+                .unwrap()
+                .intervals
+        );
+
+        let commas = Regions::from_strings(&["1:1,000-2,000".to_string()]).unwrap();
+        assert_eq!(commas.intervals["1"], [(999, 2000)]);
+        assert!(Regions::from_strings(&["1:100".to_string()]).is_err());
+        assert!(Regions::from_strings(&["1:a-b".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,253 @@
+//! Lookup of ClinVarSets by accession: RCV, VCV, SCV, ClinVarSet ID or rsID.
+//!
+//! Accessions are matched without reading the whole ClinVarSet into the typed model, and reading stops
+//! as soon as every accession was found (unless all the matching ClinVarSets are asked for).
+use crate::clinvar::record::ClinVarRecord;
+use crate::error::ClinvarXMLTabError;
+use crate::xml::handler::{BasicNodeWriter, ClinVarSetEvent, EventHandler};
+
+/// Something to look for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Accession {
+    Rcv(String),
+    Vcv(String),
+    Scv(String),
+    /// ID attribute of the ClinVarSet
+    ClinVarSetId(String),
+    /// dbSNP ID, without its `rs` prefix
+    Rsid(String),
+}
+
+/// Child elements of `node` named `name`
+pub(crate) fn children<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &'static str,
+) -> impl Iterator<Item = roxmltree::Node<'a, 'input>> {
+    node.children().filter(move |c| c.has_tag_name(name))
+}
+
+impl Accession {
+    /// Recognizes the kind of accession from its prefix. Versions (`RCV000000181.4`) are ignored.
+    pub fn parse(accession: &str) -> Result<Self, ClinvarXMLTabError> {
+        let accession = accession.trim();
+        let unversioned = accession
+            .split_once('.')
+            .map_or(accession, |(acc, _)| acc)
+            .to_uppercase();
+        let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+        let parsed = match unversioned.get(..3) {
+            Some("RCV") => Some(Self::Rcv(unversioned)),
+            Some("VCV") => Some(Self::Vcv(unversioned)),
+            Some("SCV") => Some(Self::Scv(unversioned)),
+            _ => match unversioned.strip_prefix("RS") {
+                Some(id) if is_number(id) => Some(Self::Rsid(id.to_string())),
+                _ if is_number(&unversioned) => Some(Self::ClinVarSetId(unversioned.clone())),
+                _ => None,
+            },
+        };
+        parsed.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("not an RCV, VCV, SCV, ClinVarSet ID or rsID: {accession}"),
+            )
+            .into()
+        })
+    }
+
+    /// Whether the ClinVarSet element `clinvar_set` has this accession
+    pub(crate) fn matches(&self, clinvar_set: &roxmltree::Node) -> bool {
+        let same_accession = |node: roxmltree::Node, accession: &str| {
+            node.attribute("Acc")
+                .is_some_and(|acc| acc.eq_ignore_ascii_case(accession))
+        };
+        let reference_assertions = || children(*clinvar_set, "ReferenceClinVarAssertion");
+        match self {
+            Self::ClinVarSetId(id) => clinvar_set.attribute("ID") == Some(id.as_str()),
+            Self::Rcv(rcv) => reference_assertions()
+                .flat_map(|a| children(a, "ClinVarAccession"))
+                .any(|a| same_accession(a, rcv)),
+            Self::Vcv(vcv) => reference_assertions()
+                .flat_map(|a| a.descendants())
+                .filter(|n| n.has_tag_name("MeasureSet") || n.has_tag_name("GenotypeSet"))
+                .any(|n| same_accession(n, vcv)),
+            Self::Scv(scv) => children(*clinvar_set, "ClinVarAssertion")
+                .flat_map(|a| children(a, "ClinVarAccession"))
+                .any(|a| same_accession(a, scv)),
+            Self::Rsid(id) => reference_assertions()
+                .flat_map(|a| a.descendants())
+                .filter(|n| n.has_tag_name("XRef") && n.attribute("DB") == Some("dbSNP"))
+                .any(|n| n.attribute("ID") == Some(id.as_str())),
+        }
+    }
+}
+
+impl std::fmt::Display for Accession {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Rcv(acc) | Self::Vcv(acc) | Self::Scv(acc) | Self::ClinVarSetId(acc) => {
+                write!(f, "{acc}")
+            }
+            Self::Rsid(id) => write!(f, "rs{id}"),
+        }
+    }
+}
+
+/// How the ClinVarSets found are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GetFormat {
+    /// The ClinVarSet element, as found in the input
+    Xml,
+    /// Every node with its path, text and attributes (see [`BasicNodeWriter`])
+    Tree,
+    /// The extracted [`ClinVarRecord`], as a JSON line
+    Record,
+}
+
+/// Writes the ClinVarSets that have any of the accessions looked for
+pub struct GetHandler<W: std::io::Write> {
+    writer: W,
+    format: GetFormat,
+    assembly: &'static str,
+    accessions: Vec<Accession>,
+    found: Vec<bool>,
+    /// Keep reading until the end of the input, for every ClinVarSet of a VCV or rsID
+    all: bool,
+    written: u64,
+}
+
+impl<W: std::io::Write> GetHandler<W> {
+    pub fn new_from_writer(
+        writer: W,
+        format: GetFormat,
+        assembly: &'static str,
+        accessions: Vec<Accession>,
+        all: bool,
+    ) -> Self {
+        Self {
+            writer,
+            format,
+            assembly,
+            found: vec![false; accessions.len()],
+            accessions,
+            all,
+            written: 0,
+        }
+    }
+
+    pub fn accessions(&self) -> &[Accession] {
+        &self.accessions
+    }
+
+    /// Accessions that matched no ClinVarSet (so far)
+    pub fn not_found(&self) -> impl Iterator<Item = &Accession> {
+        self.accessions
+            .iter()
+            .zip(&self.found)
+            .filter(|(_, found)| !**found)
+            .map(|(accession, _)| accession)
+    }
+
+    fn write(&mut self, event: &ClinVarSetEvent) -> Result<(), ClinvarXMLTabError> {
+        let node = event.node();
+        match self.format {
+            GetFormat::Xml => {
+                let xml = &node.document().input_text()[node.range()];
+                self.writer.write_all(xml.as_bytes())?;
+                self.writer.write_all(b"\n")?;
+            }
+            GetFormat::Record => {
+                let record = ClinVarRecord::from_clinvar_set(event.set()?, Some(self.assembly));
+                serde_json::to_writer(&mut self.writer, &record.to_json()?)?;
+                self.writer.write_all(b"\n")?;
+            }
+            GetFormat::Tree => BasicNodeWriter::new(&mut self.writer).handle_clinvar_set(event)?,
+        }
+        Ok(())
+    }
+}
+
+impl<W: std::io::Write> EventHandler for GetHandler<W> {
+    fn handle_clinvar_set(&mut self, event: &ClinVarSetEvent) -> Result<(), ClinvarXMLTabError> {
+        let mut matched = false;
+        for (accession, found) in self.accessions.iter().zip(self.found.iter_mut()) {
+            if accession.matches(event.node()) {
+                *found = true;
+                matched = true;
+            }
+        }
+        if matched {
+            self.written += 1;
+            self.write(event)?;
+        }
+        Ok(())
+    }
+
+    fn end_record(&mut self) -> Result<(), ClinvarXMLTabError> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), ClinvarXMLTabError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), ClinvarXMLTabError> {
+        self.flush()
+    }
+
+    fn records_written(&self) -> Option<u64> {
+        Some(self.written)
+    }
+
+    fn done(&self) -> bool {
+        !self.all && self.found.iter().all(|found| *found)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let parse = |accession| Accession::parse(accession).unwrap();
+        assert_eq!(parse("RCV000000188"), Accession::Rcv("RCV000000188".into()));
+        assert_eq!(
+            parse(" rcv000000188.5 "),
+            Accession::Rcv("RCV000000188".into())
+        );
+        assert_eq!(
+            parse("VCV000000165.1"),
+            Accession::Vcv("VCV000000165".into())
+        );
+        assert_eq!(parse("scv000081973"), Accession::Scv("SCV000081973".into()));
+        assert_eq!(parse("rs386833609"), Accession::Rsid("386833609".into()));
+        assert_eq!(
+            parse("92148146"),
+            Accession::ClinVarSetId("92148146".into())
+        );
+        for invalid in ["", "rs", "rsABC", "NM_000274.4", "BRCA1"] {
+            assert!(Accession::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let xml = include_str!("../clinvarset_new.xml");
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        let clinvar_set = doc.root_element();
+        for accession in [
+            "RCV000000188",
+            "VCV000000165",
+            "SCV000020331",
+            "rs386833609",
+            "92148146",
+        ] {
+            assert!(Accession::parse(accession).unwrap().matches(&clinvar_set));
+        }
+        // The RCV this one replaces is not its own
+        for accession in ["RCV000049536", "VCV000000166", "rs1", "1"] {
+            assert!(!Accession::parse(accession).unwrap().matches(&clinvar_set));
+        }
+    }
+}
@@ -0,0 +1,560 @@
+//! Sidecar index of a ClinVar XML release: the accessions of every ClinVarSet, and where it is in the file.
+//!
+//! The index is a TSV file whose first line tells how offsets are to be read:
+//! - `#offsets=bytes` for uncompressed XML: OFFSET is the position of the `<ClinVarSet` tag in the file
+//! - `#offsets=bgzf` for BGZF-compressed XML (`bgzip`): OFFSET is a virtual offset, the compressed offset of
+//!   the block in the upper 48 bits and the offset within the uncompressed block in the lower 16 bits
+//!
+//! followed by the size and modification time of the indexed file (`size=...`, `mtime=...` in nanoseconds since
+//! the epoch, tab-separated), so that an index isn't used with another file than its own.
+//!
+//! LENGTH is always the length of the (uncompressed) ClinVarSet element. Other compressions can't be seeked
+//! into, so they can't be indexed.
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use crate::clinvar::get::{children, Accession};
+use crate::error::ClinvarXMLTabError;
+use crate::reader;
+use crate::utils::InputCompression;
+
+const HEADER: [&str; 7] = [
+    "OFFSET",
+    "LENGTH",
+    "CLINVARSET_ID",
+    "RCV",
+    "VCV",
+    "SCV",
+    "RSID",
+];
+
+const BYTES_OFFSETS: &str = "#offsets=bytes";
+const BGZF_OFFSETS: &str = "#offsets=bgzf";
+
+/// Separator of the accessions, when a ClinVarSet has several of a kind
+const SEPARATOR: char = '|';
+
+/// Length of a BGZF block header, up to and including BSIZE
+const BGZF_HEADER_LEN: usize = 18;
+
+/// Size and modification time of an indexed file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputStamp {
+    pub size: u64,
+    /// Nanoseconds since the epoch
+    pub mtime: u128,
+}
+
+impl InputStamp {
+    pub fn of<P: AsRef<Path>>(path: P) -> Result<Self, ClinvarXMLTabError> {
+        let metadata = std::fs::metadata(path)?;
+        let mtime = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        Ok(Self {
+            size: metadata.len(),
+            mtime,
+        })
+    }
+
+    /// `size=...\tmtime=...`, as written in the first line of the index
+    fn to_header(self) -> String {
+        format!("size={}\tmtime={}", self.size, self.mtime)
+    }
+
+    fn from_header(fields: &[&str]) -> Option<Self> {
+        let value = |key: &str| {
+            fields
+                .iter()
+                .find_map(|field| field.strip_prefix(key)?.strip_prefix('='))
+        };
+        Some(Self {
+            size: value("size")?.parse().ok()?,
+            mtime: value("mtime")?.parse().ok()?,
+        })
+    }
+}
+
+/// Where the BGZF blocks of a file start, to turn offsets in the uncompressed data into virtual offsets
+#[derive(Debug, Clone, Default)]
+pub struct BgzfBlocks {
+    /// Compressed and uncompressed offsets of the start of each non-empty block
+    starts: Vec<(u64, u64)>,
+}
+
+impl BgzfBlocks {
+    /// Blocks of the file at `path`, from their headers (nothing is decompressed).
+    /// `None` if the file is not compressed, and an error if it can't be seeked into.
+    pub fn for_input<P: AsRef<Path>>(path: P) -> Result<Option<Self>, ClinvarXMLTabError> {
+        let path = path.as_ref();
+        let mut magic = Vec::new();
+        File::open(path)?.take(6).read_to_end(&mut magic)?;
+        let unindexable = |message: &str| -> ClinvarXMLTabError {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{}: {message}", path.display()),
+            )
+            .into()
+        };
+        match InputCompression::detect(&magic) {
+            InputCompression::None => Ok(None),
+            InputCompression::Gzip => match Self::from_path(path)? {
+                Some(blocks) => Ok(Some(blocks)),
+                None => Err(unindexable(
+                    "gzip input is not BGZF, recompress it with `bgzip` to index it",
+                )),
+            },
+            _ => Err(unindexable(
+                "only uncompressed or BGZF-compressed input can be indexed",
+            )),
+        }
+    }
+
+    /// Reads every block header, `None` if a block is not a BGZF block
+    fn from_path(path: &Path) -> Result<Option<Self>, ClinvarXMLTabError> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut blocks = Self::default();
+        let (mut compressed, mut uncompressed) = (0u64, 0u64);
+        let mut header = [0u8; BGZF_HEADER_LEN];
+        while !file.fill_buf()?.is_empty() {
+            file.read_exact(&mut header)?;
+            // gzip member with extra fields (FLG.FEXTRA), the first being BC (block size)
+            let is_bgzf = header[..3] == [0x1f, 0x8b, 0x08]
+                && header[3] & 0x04 != 0
+                && header[12..15] == [b'B', b'C', 2];
+            if !is_bgzf {
+                return Ok(None);
+            }
+            let block_size = u64::from(u16::from_le_bytes([header[16], header[17]])) + 1;
+            // Skip the compressed data and CRC32, up to ISIZE
+            file.seek_relative(block_size as i64 - BGZF_HEADER_LEN as i64 - 4)?;
+            let mut isize = [0u8; 4];
+            file.read_exact(&mut isize)?;
+            let isize = u64::from(u32::from_le_bytes(isize));
+            if isize > 0 {
+                blocks.starts.push((compressed, uncompressed));
+            }
+            compressed += block_size;
+            uncompressed += isize;
+        }
+        Ok(Some(blocks))
+    }
+
+    /// Virtual offset of `offset` in the uncompressed data
+    fn virtual_offset(&self, offset: u64) -> u64 {
+        let block = self
+            .starts
+            .partition_point(|(_, start)| *start <= offset)
+            .saturating_sub(1);
+        let (compressed, uncompressed) = self.starts.get(block).copied().unwrap_or_default();
+        (compressed << 16) | (offset - uncompressed)
+    }
+}
+
+/// A line of the index
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexEntry {
+    /// Byte offset or BGZF virtual offset, depending on the input
+    pub offset: u64,
+    /// Length of the uncompressed ClinVarSet element
+    pub length: u64,
+    pub clinvar_set_id: String,
+    pub rcv: Vec<String>,
+    pub vcv: Vec<String>,
+    pub scv: Vec<String>,
+    /// dbSNP IDs, without their `rs` prefix
+    pub rsid: Vec<String>,
+}
+
+/// Pushes `value` to `values`, unless it's already there
+fn push_new(values: &mut Vec<String>, value: Option<&str>) {
+    if let Some(value) = value {
+        if !values.iter().any(|v| v == value) {
+            values.push(value.to_string());
+        }
+    }
+}
+
+fn split(field: &str) -> Vec<String> {
+    field
+        .split(SEPARATOR)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+impl IndexEntry {
+    fn from_node(clinvar_set: &roxmltree::Node, offset: u64, length: u64) -> Self {
+        let mut entry = Self {
+            offset,
+            length,
+            clinvar_set_id: clinvar_set.attribute("ID").unwrap_or_default().to_string(),
+            ..Self::default()
+        };
+        for assertion in children(*clinvar_set, "ReferenceClinVarAssertion") {
+            for accession in children(assertion, "ClinVarAccession") {
+                push_new(&mut entry.rcv, accession.attribute("Acc"));
+            }
+            for node in assertion.descendants() {
+                if node.has_tag_name("MeasureSet") || node.has_tag_name("GenotypeSet") {
+                    push_new(&mut entry.vcv, node.attribute("Acc"));
+                } else if node.has_tag_name("XRef") && node.attribute("DB") == Some("dbSNP") {
+                    push_new(&mut entry.rsid, node.attribute("ID"));
+                }
+            }
+        }
+        for assertion in children(*clinvar_set, "ClinVarAssertion") {
+            for accession in children(assertion, "ClinVarAccession") {
+                push_new(&mut entry.scv, accession.attribute("Acc"));
+            }
+        }
+        entry
+    }
+
+    fn from_record(record: &csv::StringRecord) -> Result<Self, ClinvarXMLTabError> {
+        let field = |i: usize| record.get(i).unwrap_or_default();
+        let number = |i: usize| {
+            field(i).parse::<u64>().map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("invalid {} in index: {}", HEADER[i], field(i)),
+                )
+            })
+        };
+        Ok(Self {
+            offset: number(0)?,
+            length: number(1)?,
+            clinvar_set_id: field(2).to_string(),
+            rcv: split(field(3)),
+            vcv: split(field(4)),
+            scv: split(field(5)),
+            rsid: split(field(6)),
+        })
+    }
+
+    fn to_record(&self) -> [String; 7] {
+        let join = |values: &[String]| values.join(&SEPARATOR.to_string());
+        [
+            self.offset.to_string(),
+            self.length.to_string(),
+            self.clinvar_set_id.clone(),
+            join(&self.rcv),
+            join(&self.vcv),
+            join(&self.scv),
+            join(&self.rsid),
+        ]
+    }
+
+    /// Whether the ClinVarSet has this accession
+    pub fn has(&self, accession: &Accession) -> bool {
+        let any = |values: &[String], accession: &str| {
+            values.iter().any(|v| v.eq_ignore_ascii_case(accession))
+        };
+        match accession {
+            Accession::Rcv(rcv) => any(&self.rcv, rcv),
+            Accession::Vcv(vcv) => any(&self.vcv, vcv),
+            Accession::Scv(scv) => any(&self.scv, scv),
+            Accession::ClinVarSetId(id) => self.clinvar_set_id == *id,
+            Accession::Rsid(id) => any(&self.rsid, id),
+        }
+    }
+}
+
+/// Indexes the (decompressed) `input`, and writes the index. `blocks` are those of the input file if it is
+/// BGZF-compressed (see [`BgzfBlocks::for_input`]), and `stamp` identifies it. Returns the number of ClinVarSets
+/// indexed.
+pub fn write_index<W: std::io::Write>(
+    input: impl BufRead,
+    blocks: Option<&BgzfBlocks>,
+    stamp: InputStamp,
+    mut writer: W,
+) -> Result<u64, ClinvarXMLTabError> {
+    let comment = if blocks.is_some() {
+        BGZF_OFFSETS
+    } else {
+        BYTES_OFFSETS
+    };
+    writeln!(writer, "{comment}\t{}", stamp.to_header())?;
+    let mut tsv = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .from_writer(writer);
+    tsv.write_record(HEADER)?;
+    let mut indexed = 0;
+    reader::scan_clinvar_sets(input, |range, elem_bytes| {
+        let xml = std::str::from_utf8(elem_bytes)?;
+        let doc = roxmltree::Document::parse(xml)?;
+        let offset = match blocks {
+            Some(blocks) => blocks.virtual_offset(range.start),
+            None => range.start,
+        };
+        let entry = IndexEntry::from_node(&doc.root_element(), offset, range.end - range.start);
+        tsv.write_record(entry.to_record())?;
+        indexed += 1;
+        Ok(true)
+    })?;
+    tsv.flush()?;
+    Ok(indexed)
+}
+
+/// What the offsets of an index are
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Offsets {
+    /// Positions in the uncompressed file
+    Bytes,
+    /// BGZF virtual offsets
+    Bgzf,
+}
+
+impl Offsets {
+    /// Reads the ClinVarSet of `entry` from `input`, the indexed file
+    pub fn read_clinvar_set<I: Read + Seek>(
+        self,
+        input: &mut I,
+        entry: &IndexEntry,
+    ) -> Result<Vec<u8>, ClinvarXMLTabError> {
+        let mut elem_bytes = Vec::with_capacity(entry.length as usize);
+        match self {
+            Self::Bytes => {
+                input.seek(SeekFrom::Start(entry.offset))?;
+                input.take(entry.length).read_to_end(&mut elem_bytes)?;
+            }
+            Self::Bgzf => {
+                let mut bgzf = noodles_bgzf::Reader::new(input);
+                bgzf.seek(noodles_bgzf::VirtualPosition::from(entry.offset))?;
+                bgzf.take(entry.length).read_to_end(&mut elem_bytes)?;
+            }
+        }
+        if elem_bytes.len() as u64 != entry.length {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "input is shorter than the index says, is it the indexed file?",
+            )
+            .into());
+        }
+        Ok(elem_bytes)
+    }
+}
+
+/// Reads an index written by [`write_index`], entry by entry
+pub struct IndexReader<R: BufRead> {
+    offsets: Offsets,
+    stamp: InputStamp,
+    records: csv::StringRecordsIntoIter<R>,
+}
+
+impl<R: BufRead> IndexReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, ClinvarXMLTabError> {
+        let mut comment = String::new();
+        reader.read_line(&mut comment)?;
+        let fields: Vec<&str> = comment.trim_end().split('\t').collect();
+        let offsets = match fields[0] {
+            BYTES_OFFSETS => Some(Offsets::Bytes),
+            BGZF_OFFSETS => Some(Offsets::Bgzf),
+            _ => None,
+        };
+        let (Some(offsets), Some(stamp)) = (offsets, InputStamp::from_header(&fields[1..])) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "not an index written by the index subcommand (or by an older version), index the input again",
+            )
+            .into());
+        };
+        let records = csv::ReaderBuilder::new()
+            .delimiter(b'\t')
+            .from_reader(reader)
+            .into_records();
+        Ok(Self {
+            offsets,
+            stamp,
+            records,
+        })
+    }
+
+    pub fn offsets(&self) -> Offsets {
+        self.offsets
+    }
+
+    /// Size and modification time of the indexed file
+    pub fn stamp(&self) -> InputStamp {
+        self.stamp
+    }
+
+    /// Checks that `path` is the file that was indexed, as it was then
+    pub fn check_input<P: AsRef<Path>>(&self, path: P) -> Result<(), ClinvarXMLTabError> {
+        let path = path.as_ref();
+        if InputStamp::of(path)? != self.stamp {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "{} has changed since it was indexed (size or modification time), index it again",
+                    path.display()
+                ),
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Iterator for IndexReader<R> {
+    type Item = Result<IndexEntry, ClinvarXMLTabError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = self.records.next()?;
+        Some(
+            record
+                .map_err(Into::into)
+                .and_then(|r| IndexEntry::from_record(&r)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    const XML: &str = include_str!("../clinvarset_new.xml");
+
+    /// ClinVarSets of a small release, and its header
+    fn clinvar_sets() -> (&'static str, Vec<String>) {
+        let sets = (0..3)
+            .map(|i| {
+                XML.trim_end()
+                    .replace("92148146", &format!("9214814{i}"))
+                    .replace("RCV000000188", &format!("RCV00000018{i}"))
+            })
+            .collect();
+        (
+            "<?xml version=\"1.0\"?>\n<ReleaseSet Type=\"full\">\n",
+            sets,
+        )
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("index-{}-{name}", std::process::id()))
+    }
+
+    /// Indexes `path`, and checks that every ClinVarSet is read back from its offset
+    fn check_round_trip(path: &Path, sets: &[String]) -> Offsets {
+        let blocks = BgzfBlocks::for_input(path).unwrap();
+        let mut index = Vec::new();
+        let input = crate::utils::file_reader(Some(path)).unwrap();
+        let stamp = InputStamp::of(path).unwrap();
+        assert_eq!(
+            write_index(input, blocks.as_ref(), stamp, &mut index).unwrap(),
+            3
+        );
+        let reader = IndexReader::new(index.as_slice()).unwrap();
+        assert_eq!(reader.stamp(), stamp);
+        reader.check_input(path).unwrap();
+        let offsets = reader.offsets();
+        let mut file = File::open(path).unwrap();
+        for (i, (entry, set)) in reader.zip(sets).enumerate() {
+            let entry = entry.unwrap();
+            let elem_bytes = offsets.read_clinvar_set(&mut file, &entry).unwrap();
+            assert_eq!(std::str::from_utf8(&elem_bytes).unwrap(), set);
+            assert!(entry.has(&Accession::Rcv(format!("RCV00000018{i}"))));
+        }
+        offsets
+    }
+
+    #[test]
+    fn test_bytes() {
+        let (header, sets) = clinvar_sets();
+        let path = temp_path("bytes.xml");
+        let content = format!("{header}{}\n</ReleaseSet>\n", sets.join("\n"));
+        std::fs::write(&path, content).unwrap();
+        assert_eq!(check_round_trip(&path, &sets), Offsets::Bytes);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_bgzf() {
+        let (header, sets) = clinvar_sets();
+        let path = temp_path("bgzf.xml.gz");
+        let mut writer = noodles_bgzf::Writer::new(File::create(&path).unwrap());
+        writer.write_all(header.as_bytes()).unwrap();
+        for set in &sets {
+            // Blocks end in the middle of every ClinVarSet, and the next one starts within a block
+            let (start, end) = set.split_at(set.len() / 3);
+            writer.write_all(start.as_bytes()).unwrap();
+            writer.flush().unwrap();
+            writer.write_all(end.as_bytes()).unwrap();
+            writer.write_all(b"\n").unwrap();
+        }
+        writer.write_all(b"</ReleaseSet>\n").unwrap();
+        writer.finish().unwrap();
+
+        let blocks = BgzfBlocks::for_input(&path).unwrap().unwrap();
+        // The final empty block is left out
+        assert_eq!(blocks.starts.len(), 4);
+        assert_eq!(blocks.starts[0], (0, 0));
+        assert_eq!(check_round_trip(&path, &sets), Offsets::Bgzf);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_virtual_offset() {
+        let blocks = BgzfBlocks {
+            starts: vec![(0, 0), (1000, 65280), (1900, 130560)],
+        };
+        assert_eq!(blocks.virtual_offset(0), 0);
+        assert_eq!(blocks.virtual_offset(65279), 65279);
+        assert_eq!(blocks.virtual_offset(65280), 1000 << 16);
+        assert_eq!(blocks.virtual_offset(65290), (1000 << 16) | 10);
+        assert_eq!(blocks.virtual_offset(130561), (1900 << 16) | 1);
+    }
+
+    #[test]
+    fn test_not_indexable() {
+        let path = temp_path("plain.xml.gz");
+        let mut encoder =
+            flate2::write::GzEncoder::new(File::create(&path).unwrap(), Default::default());
+        encoder.write_all(XML.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+        let error = BgzfBlocks::for_input(&path).unwrap_err();
+        assert!(error.to_string().contains("bgzip"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_index_reader() {
+        let entry = IndexEntry {
+            offset: 12,
+            length: 34,
+            clinvar_set_id: "92148146".to_string(),
+            rcv: vec!["RCV000000188".to_string()],
+            vcv: vec!["VCV000000165".to_string()],
+            scv: vec!["SCV000020331".to_string(), "SCV000081973".to_string()],
+            rsid: Vec::new(),
+        };
+        let mut index = format!(
+            "{BYTES_OFFSETS}\tsize=100\tmtime=5\n{}\n",
+            HEADER.join("\t")
+        );
+        index.push_str(&entry.to_record().join("\t"));
+        let reader = IndexReader::new(index.as_bytes()).unwrap();
+        assert_eq!(
+            reader.stamp(),
+            InputStamp {
+                size: 100,
+                mtime: 5
+            }
+        );
+        let entries: Vec<IndexEntry> = reader.map(Result::unwrap).collect();
+        assert_eq!(entries, [entry]);
+
+        // Without the stamp of the input
+        let old = format!("{BYTES_OFFSETS}\n{}\n", HEADER.join("\t"));
+        assert!(IndexReader::new(old.as_bytes()).is_err());
+        let path = temp_path("changed.xml");
+        std::fs::write(&path, "x").unwrap();
+        let reader = IndexReader::new(index.as_bytes()).unwrap();
+        assert!(reader.check_input(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1 +1,14 @@
+pub mod annotate;
+pub mod bed;
+pub mod columnar;
+pub mod diff;
+pub mod fields;
+pub mod filter;
+pub mod get;
+pub mod index;
+pub mod model;
 pub mod record;
+pub mod select;
+pub mod split;
+pub mod sqlite;
+pub mod stats;
@@ -0,0 +1,575 @@
+//! Typed model of a `ClinVarSet`, following the ClinVar XSD (`clinvar_public.xsd`).
+//!
+//! Only the elements this crate uses are modelled. Parsing is lenient: elements and attributes the model doesn't
+//! know are skipped rather than rejected (`deny_unknown_fields` would fail on every release that adds one), and
+//! [`ClinVarSet::unmodelled`] lists them instead, so that the census reports what a release has beyond the model.
+//! Elements the schema marks as mandatory are not wrapped in `Option`, so their disappearance
+//! from a release is reported as a deserialisation error instead of silently producing empty fields.
+//! XML attributes are prefixed with `@`, and text content is `$text` (quick-xml's serde conventions).
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::ClinvarXMLTabError;
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ClinVarSet {
+    #[serde(rename = "@ID")]
+    pub id: u64,
+    #[serde(rename = "RecordStatus")]
+    pub record_status: String,
+    #[serde(rename = "Replaces", default)]
+    pub replaces: Vec<String>,
+    #[serde(rename = "Title", default)]
+    pub title: Option<String>,
+    #[serde(rename = "ReferenceClinVarAssertion")]
+    pub reference_clinvar_assertion: ReferenceClinVarAssertion,
+    #[serde(rename = "ClinVarAssertion", default)]
+    pub clinvar_assertions: Vec<ClinVarAssertion>,
+}
+
+impl ClinVarSet {
+    /// Deserialises a single `<ClinVarSet>` element
+    pub fn from_xml(xml: &str) -> Result<Self, ClinvarXMLTabError> {
+        Ok(quick_xml::de::from_str(xml)?)
+    }
+
+    /// Deserialises the `<ClinVarSet>` element `node` points to
+    pub fn from_node(node: &roxmltree::Node) -> Result<Self, ClinvarXMLTabError> {
+        Self::from_xml(&node.document().input_text()[node.range()])
+    }
+
+    /// Paths of the elements and attributes of `node`, this ClinVarSet's element, that the model skipped, from the
+    /// ClinVarSet: elements as `ClinVarSet.ReferenceClinVarAssertion.ObservedIn`, attributes as `ClinVarSet.Title@Lang`
+    pub fn unmodelled(
+        &self,
+        node: &roxmltree::Node,
+    ) -> Result<BTreeSet<String>, ClinvarXMLTabError> {
+        // What is modelled is what the model writes back
+        let xml = quick_xml::se::to_string_with_root("ClinVarSet", self)?;
+        let doc = roxmltree::Document::parse(&xml)?;
+        let mut modelled = BTreeSet::new();
+        collect_paths(&doc.root_element(), "", &mut modelled);
+        let mut found = BTreeSet::new();
+        collect_paths(node, "", &mut found);
+        Ok(found.difference(&modelled).cloned().collect())
+    }
+}
+
+fn collect_paths(node: &roxmltree::Node, parent: &str, paths: &mut BTreeSet<String>) {
+    let path = if parent.is_empty() {
+        node.tag_name().name().to_string()
+    } else {
+        format!("{parent}.{}", node.tag_name().name())
+    };
+    for attribute in node.attributes() {
+        paths.insert(format!("{path}@{}", attribute.name()));
+    }
+    for child in node.children().filter(|child| child.is_element()) {
+        collect_paths(&child, &path, paths);
+    }
+    paths.insert(path);
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ReferenceClinVarAssertion {
+    #[serde(rename = "@ID")]
+    pub id: u64,
+    #[serde(rename = "@DateCreated", default)]
+    pub date_created: Option<String>,
+    #[serde(rename = "@DateLastUpdated", default)]
+    pub date_last_updated: Option<String>,
+    #[serde(rename = "ClinVarAccession")]
+    pub clinvar_accession: ClinVarAccession,
+    #[serde(rename = "RecordStatus")]
+    pub record_status: String,
+    #[serde(rename = "Classifications", default)]
+    pub classifications: Option<Classifications>,
+    #[serde(rename = "Assertion")]
+    pub assertion: Assertion,
+    #[serde(rename = "ObservedIn", default)]
+    pub observed_in: Vec<ObservedIn>,
+    #[serde(rename = "MeasureSet", default)]
+    pub measure_set: Option<MeasureSet>,
+    #[serde(rename = "GenotypeSet", default)]
+    pub genotype_set: Option<GenotypeSet>,
+    #[serde(rename = "TraitSet", default)]
+    pub trait_set: Option<TraitSet>,
+    #[serde(rename = "Citation", default)]
+    pub citations: Vec<Citation>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ClinVarAssertion {
+    #[serde(rename = "@ID")]
+    pub id: u64,
+    #[serde(rename = "@SubmissionName", default)]
+    pub submission_name: Option<String>,
+    #[serde(rename = "ClinVarSubmissionID")]
+    pub submission_id: ClinVarSubmissionId,
+    #[serde(rename = "ClinVarAccession")]
+    pub clinvar_accession: ClinVarAccession,
+    #[serde(rename = "RecordStatus", default)]
+    pub record_status: Option<String>,
+    #[serde(rename = "Classification", default)]
+    pub classification: Option<SubmitterClassification>,
+    #[serde(rename = "Assertion")]
+    pub assertion: Assertion,
+    #[serde(rename = "ObservedIn", default)]
+    pub observed_in: Vec<ObservedIn>,
+    #[serde(rename = "MeasureSet", default)]
+    pub measure_set: Option<MeasureSet>,
+    #[serde(rename = "GenotypeSet", default)]
+    pub genotype_set: Option<GenotypeSet>,
+    #[serde(rename = "TraitSet", default)]
+    pub trait_set: Option<TraitSet>,
+    #[serde(rename = "Citation", default)]
+    pub citations: Vec<Citation>,
+    #[serde(rename = "Comment", default)]
+    pub comments: Vec<Comment>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ClinVarAccession {
+    #[serde(rename = "@Acc")]
+    pub acc: String,
+    #[serde(rename = "@Version", default)]
+    pub version: Option<u32>,
+    #[serde(rename = "@Type")]
+    pub accession_type: String,
+    #[serde(rename = "@DateCreated", default)]
+    pub date_created: Option<String>,
+    #[serde(rename = "@DateUpdated", default)]
+    pub date_updated: Option<String>,
+    #[serde(rename = "@OrgID", default)]
+    pub org_id: Option<String>,
+    #[serde(rename = "@OrganizationCategory", default)]
+    pub organization_category: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ClinVarSubmissionId {
+    #[serde(rename = "@localKey", default)]
+    pub local_key: Option<String>,
+    #[serde(rename = "@submitter", default)]
+    pub submitter: Option<String>,
+    #[serde(rename = "@submitterDate", default)]
+    pub submitter_date: Option<String>,
+    #[serde(rename = "@title", default)]
+    pub title: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Assertion {
+    #[serde(rename = "@Type")]
+    pub assertion_type: String,
+}
+
+/// Aggregate classifications of a `ReferenceClinVarAssertion`
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
+pub struct Classifications {
+    #[serde(rename = "GermlineClassification", default)]
+    pub germline: Option<AggregateClassification>,
+    #[serde(rename = "SomaticClinicalImpact", default)]
+    pub somatic_clinical_impact: Option<AggregateClassification>,
+    #[serde(rename = "OncogenicityClassification", default)]
+    pub oncogenicity: Option<AggregateClassification>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct AggregateClassification {
+    #[serde(rename = "ReviewStatus")]
+    pub review_status: String,
+    #[serde(rename = "Description", default)]
+    pub description: Option<ClassificationDescription>,
+    #[serde(rename = "Citation", default)]
+    pub citations: Vec<Citation>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ClassificationDescription {
+    #[serde(rename = "@DateLastEvaluated", default)]
+    pub date_last_evaluated: Option<String>,
+    #[serde(rename = "@SubmissionCount", default)]
+    pub submission_count: Option<u32>,
+    #[serde(rename = "$text")]
+    pub value: String,
+}
+
+/// Classification of a single submission (SCV)
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct SubmitterClassification {
+    #[serde(rename = "@DateLastEvaluated", default)]
+    pub date_last_evaluated: Option<String>,
+    #[serde(rename = "ReviewStatus", default)]
+    pub review_status: Option<String>,
+    #[serde(rename = "GermlineClassification", default)]
+    pub germline: Option<String>,
+    #[serde(rename = "Comment", default)]
+    pub comments: Vec<Comment>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ObservedIn {
+    #[serde(rename = "Sample")]
+    pub sample: Sample,
+    #[serde(rename = "Method", default)]
+    pub methods: Vec<Method>,
+    #[serde(rename = "ObservedData", default)]
+    pub observed_data: Vec<ObservedData>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Sample {
+    #[serde(rename = "Origin", default)]
+    pub origin: Option<String>,
+    #[serde(rename = "Species", default)]
+    pub species: Option<Species>,
+    #[serde(rename = "AffectedStatus", default)]
+    pub affected_status: Option<String>,
+    #[serde(rename = "NumberTested", default)]
+    pub number_tested: Option<u32>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Species {
+    #[serde(rename = "@TaxonomyId", default)]
+    pub taxonomy_id: Option<u32>,
+    #[serde(rename = "$text")]
+    pub name: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Method {
+    #[serde(rename = "MethodType", default)]
+    pub method_type: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ObservedData {
+    #[serde(rename = "@ID", default)]
+    pub id: Option<u64>,
+    #[serde(rename = "Attribute", default)]
+    pub attribute: Option<Attribute>,
+    #[serde(rename = "Citation", default)]
+    pub citations: Vec<Citation>,
+    #[serde(rename = "XRef", default)]
+    pub xrefs: Vec<XRef>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Attribute {
+    #[serde(rename = "@Type")]
+    pub attribute_type: String,
+    #[serde(rename = "@Accession", default)]
+    pub accession: Option<String>,
+    #[serde(rename = "@Version", default)]
+    pub version: Option<u32>,
+    #[serde(rename = "@Change", default)]
+    pub change: Option<String>,
+    #[serde(rename = "@integerValue", default)]
+    pub integer_value: Option<i64>,
+    #[serde(rename = "@MANESelect", default)]
+    pub mane_select: Option<bool>,
+    #[serde(rename = "$text", default)]
+    pub value: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct AttributeSet {
+    #[serde(rename = "Attribute")]
+    pub attribute: Attribute,
+    #[serde(rename = "XRef", default)]
+    pub xrefs: Vec<XRef>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Citation {
+    #[serde(rename = "@Type", default)]
+    pub citation_type: Option<String>,
+    #[serde(rename = "ID", default)]
+    pub ids: Vec<CitationId>,
+    #[serde(rename = "URL", default)]
+    pub url: Option<String>,
+    #[serde(rename = "CitationText", default)]
+    pub text: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct CitationId {
+    #[serde(rename = "@Source")]
+    pub source: String,
+    #[serde(rename = "$text")]
+    pub value: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct XRef {
+    #[serde(rename = "@DB")]
+    pub db: String,
+    #[serde(rename = "@ID")]
+    pub id: String,
+    #[serde(rename = "@Type", default)]
+    pub xref_type: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Comment {
+    #[serde(rename = "@Type", default)]
+    pub comment_type: Option<String>,
+    #[serde(rename = "@DataSource", default)]
+    pub data_source: Option<String>,
+    #[serde(rename = "$text", default)]
+    pub text: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ElementValue {
+    #[serde(rename = "@Type")]
+    pub value_type: String,
+    #[serde(rename = "$text")]
+    pub value: String,
+}
+
+/// A `Name` or a `Symbol` element
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Name {
+    #[serde(rename = "ElementValue")]
+    pub element_value: ElementValue,
+    #[serde(rename = "XRef", default)]
+    pub xrefs: Vec<XRef>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct MeasureSet {
+    #[serde(rename = "@Type")]
+    pub measure_set_type: String,
+    #[serde(rename = "@ID", default)]
+    pub id: Option<u64>,
+    #[serde(rename = "@Acc", default)]
+    pub acc: Option<String>,
+    #[serde(rename = "@Version", default)]
+    pub version: Option<u32>,
+    #[serde(rename = "Measure", default)]
+    pub measures: Vec<Measure>,
+    #[serde(rename = "Name", default)]
+    pub names: Vec<Name>,
+    #[serde(rename = "XRef", default)]
+    pub xrefs: Vec<XRef>,
+}
+
+/// Set of measure sets, used for compound heterozygotes and haplotypes
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct GenotypeSet {
+    #[serde(rename = "@Type")]
+    pub genotype_set_type: String,
+    #[serde(rename = "@ID", default)]
+    pub id: Option<u64>,
+    #[serde(rename = "@Acc", default)]
+    pub acc: Option<String>,
+    #[serde(rename = "MeasureSet", default)]
+    pub measure_sets: Vec<MeasureSet>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Measure {
+    #[serde(rename = "@Type")]
+    pub measure_type: String,
+    #[serde(rename = "@ID", default)]
+    pub id: Option<u64>,
+    #[serde(rename = "Name", default)]
+    pub names: Vec<Name>,
+    #[serde(rename = "CanonicalSPDI", default)]
+    pub canonical_spdi: Option<String>,
+    #[serde(rename = "AttributeSet", default)]
+    pub attribute_sets: Vec<AttributeSet>,
+    #[serde(rename = "CytogeneticLocation", default)]
+    pub cytogenetic_locations: Vec<String>,
+    #[serde(rename = "SequenceLocation", default)]
+    pub sequence_locations: Vec<SequenceLocation>,
+    #[serde(rename = "MeasureRelationship", default)]
+    pub measure_relationships: Vec<MeasureRelationship>,
+    #[serde(rename = "Citation", default)]
+    pub citations: Vec<Citation>,
+    #[serde(rename = "XRef", default)]
+    pub xrefs: Vec<XRef>,
+    #[serde(rename = "Comment", default)]
+    pub comments: Vec<Comment>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct SequenceLocation {
+    #[serde(rename = "@Assembly")]
+    pub assembly: String,
+    #[serde(rename = "@AssemblyAccessionVersion", default)]
+    pub assembly_accession_version: Option<String>,
+    #[serde(rename = "@AssemblyStatus", default)]
+    pub assembly_status: Option<String>,
+    #[serde(rename = "@Chr")]
+    pub chr: String,
+    #[serde(rename = "@Accession", default)]
+    pub accession: Option<String>,
+    #[serde(rename = "@start", default)]
+    pub start: Option<u64>,
+    #[serde(rename = "@stop", default)]
+    pub stop: Option<u64>,
+    #[serde(rename = "@innerStart", default)]
+    pub inner_start: Option<u64>,
+    #[serde(rename = "@innerStop", default)]
+    pub inner_stop: Option<u64>,
+    #[serde(rename = "@outerStart", default)]
+    pub outer_start: Option<u64>,
+    #[serde(rename = "@outerStop", default)]
+    pub outer_stop: Option<u64>,
+    #[serde(rename = "@display_start", default)]
+    pub display_start: Option<u64>,
+    #[serde(rename = "@display_stop", default)]
+    pub display_stop: Option<u64>,
+    #[serde(rename = "@Strand", default)]
+    pub strand: Option<String>,
+    #[serde(rename = "@variantLength", default)]
+    pub variant_length: Option<u64>,
+    #[serde(rename = "@positionVCF", default)]
+    pub position_vcf: Option<u64>,
+    #[serde(rename = "@referenceAlleleVCF", default)]
+    pub reference_allele_vcf: Option<String>,
+    #[serde(rename = "@alternateAlleleVCF", default)]
+    pub alternate_allele_vcf: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct MeasureRelationship {
+    #[serde(rename = "@Type")]
+    pub relationship_type: String,
+    #[serde(rename = "Name", default)]
+    pub names: Vec<Name>,
+    #[serde(rename = "Symbol", default)]
+    pub symbols: Vec<Name>,
+    #[serde(rename = "SequenceLocation", default)]
+    pub sequence_locations: Vec<SequenceLocation>,
+    #[serde(rename = "XRef", default)]
+    pub xrefs: Vec<XRef>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct TraitSet {
+    #[serde(rename = "@Type")]
+    pub trait_set_type: String,
+    #[serde(rename = "@ID", default)]
+    pub id: Option<u64>,
+    #[serde(rename = "Trait", default)]
+    pub traits: Vec<Trait>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Trait {
+    #[serde(rename = "@Type")]
+    pub trait_type: String,
+    #[serde(rename = "@ID", default)]
+    pub id: Option<u64>,
+    #[serde(rename = "Name", default)]
+    pub names: Vec<Name>,
+    #[serde(rename = "Symbol", default)]
+    pub symbols: Vec<Name>,
+    #[serde(rename = "XRef", default)]
+    pub xrefs: Vec<XRef>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clinvar::record::ClinVarRecord;
+    use crate::handler::ClinVarSetEvent;
+
+    const XML: &str = include_str!("../clinvarset_new.xml");
+
+    #[test]
+    fn test_from_xml() {
+        let set = ClinVarSet::from_xml(XML).unwrap();
+        assert_eq!(set.id, 92148146);
+        assert_eq!(set.record_status, "current");
+        assert_eq!(set.replaces, ["RCV000049536"]);
+        let rca = &set.reference_clinvar_assertion;
+        assert_eq!(rca.clinvar_accession.acc, "RCV000000188");
+        let germline = rca.classifications.as_ref().unwrap().germline.as_ref();
+        assert_eq!(
+            germline.unwrap().review_status,
+            "no assertion criteria provided"
+        );
+        let measure_set = rca.measure_set.as_ref().unwrap();
+        assert_eq!(measure_set.acc.as_deref(), Some("VCV000000165"));
+        assert_eq!(measure_set.measures[0].measure_type, "Deletion");
+        let grch38 = measure_set.measures[0]
+            .sequence_locations
+            .iter()
+            .find(|l| l.assembly == "GRCh38")
+            .unwrap();
+        assert_eq!(grch38.position_vcf, Some(124408632));
+        assert_eq!(set.clinvar_assertions.len(), 2);
+        assert_eq!(
+            set.clinvar_assertions[1].clinvar_accession.acc,
+            "SCV000081973"
+        );
+    }
+
+    #[test]
+    fn test_from_node() {
+        let doc = roxmltree::Document::parse(XML).unwrap();
+        let event = ClinVarSetEvent::new(doc.root_element());
+        let set = event.set().unwrap();
+        assert_eq!(*set, ClinVarSet::from_xml(XML).unwrap());
+        // Deserialized once, then shared
+        assert!(std::ptr::eq(set, event.set().unwrap()));
+    }
+
+    #[test]
+    fn test_unmodelled() {
+        let unmodelled = |xml: &str| {
+            let doc = roxmltree::Document::parse(xml).unwrap();
+            let set = ClinVarSet::from_node(&doc.root_element()).unwrap();
+            set.unmodelled(&doc.root_element()).unwrap()
+        };
+        let skipped = unmodelled(XML);
+        assert_eq!(
+            skipped,
+            [
+                "ClinVarSet.ClinVarAssertion.ClinVarAccession@OrgType",
+                "ClinVarSet.ClinVarAssertion.ExternalID",
+                "ClinVarSet.ClinVarAssertion.ExternalID@DB",
+                "ClinVarSet.ClinVarAssertion.ExternalID@ID",
+                "ClinVarSet.ClinVarAssertion.ExternalID@Type",
+            ]
+            .map(String::from)
+            .into()
+        );
+
+        let drifted = XML
+            .replacen(
+                "<RecordStatus>current</RecordStatus>",
+                "<RecordStatus>current</RecordStatus><NewElement/>",
+                1,
+            )
+            .replace(
+                r#"<ClinVarSet ID="92148146">"#,
+                r#"<ClinVarSet ID="92148146" NewAttribute="1">"#,
+            );
+        let new: Vec<String> = unmodelled(&drifted).difference(&skipped).cloned().collect();
+        assert_eq!(new, ["ClinVarSet.NewElement", "ClinVarSet@NewAttribute"]);
+    }
+
+    #[test]
+    fn test_record() {
+        let set = ClinVarSet::from_xml(XML).unwrap();
+        let record = ClinVarRecord::from_clinvar_set(&set, Some("GRCh37"));
+        assert_eq!(record.rcv(), Some("RCV000000188"));
+        assert_eq!(record.clnsig(), Some("pathogenic/likely_pathogenic"));
+        assert_eq!(record.chromosome(), Some("chr10"));
+        assert_eq!(record.position(), Some(126097201));
+        assert_eq!(record.reference(), Some("CCACTCCTAT"));
+        assert_eq!(record.alternate(), Some("C"));
+        assert_eq!(record.genes(), ["OAT"]);
+        let no_coordinates = ClinVarRecord::from_clinvar_set(&set, None);
+        assert_eq!(no_coordinates.position(), None);
+    }
+}
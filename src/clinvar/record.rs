@@ -1,7 +1,14 @@
+use std::collections::HashMap;
 use std::mem::take;
+use std::sync::{Arc, Mutex, PoisonError};
 
+use crate::clinvar::fields::{FieldKind, FieldSpec};
+use crate::clinvar::model::ClinVarSet;
 use crate::error::ClinvarXMLTabError;
-use crate::xml::handler::EventHandler;
+use crate::reference::{
+    check_and_normalize, Checked, RefMismatch, ReferenceGenome, REF_MISMATCH_FILTER,
+};
+use crate::xml::handler::{ClinVarSetEvent, EventHandler};
 use noodles_core::Position;
 use noodles_vcf::variant::io::Write;
 use serde::Serialize;
@@ -19,6 +26,7 @@ pub struct ClinVarRecord {
     rcv: Option<String>,
     vcv: Option<String>,
     clnsig: Option<String>,
+    review_status: Option<String>,
     #[serde(rename = "DESCRIPTION")]
     description: Option<String>,
     date_last_updated: Option<String>,
@@ -27,11 +35,315 @@ pub struct ClinVarRecord {
     position: Option<usize>,
     reference: Option<String>,
     alternate: Option<String>,
+
+    /// Preferred names of the conditions (traits)
+    #[serde(serialize_with = "vec_as_string_pipe")]
+    conditions: Vec<String>,
+    /// Preferred symbols of the genes the variant is in
+    #[serde(serialize_with = "vec_as_string_pipe")]
+    genes: Vec<String>,
+    #[serde(rename = "HGVS", serialize_with = "vec_as_string_pipe")]
+    hgvs: Vec<String>,
+    /// SCV accessions of the submissions
+    #[serde(serialize_with = "vec_as_string_pipe")]
+    submissions: Vec<String>,
+}
+
+/// Keys of a record in JSON, in the order of its fields
+const JSON_KEYS: [&str; 17] = [
+    "CLINVAR_ID",
+    "STATUS",
+    "REPLACES",
+    "RCV",
+    "VCV",
+    "CLNSIG",
+    "REVIEW_STATUS",
+    "DESCRIPTION",
+    "DATE_LAST_UPDATED",
+    "CHROMOSOME",
+    "POSITION",
+    "REFERENCE",
+    "ALTERNATE",
+    "CONDITIONS",
+    "GENES",
+    "HGVS",
+    "SUBMISSIONS",
+];
+
+/// A JSON object that keeps its keys in insertion order (serde_json's `Map` sorts them)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JsonObject(Vec<(String, serde_json::Value)>);
+
+impl JsonObject {
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(|(key, _)| key.as_str())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&serde_json::Value> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value)
+    }
+}
+
+impl FromIterator<(String, serde_json::Value)> for JsonObject {
+    fn from_iter<I: IntoIterator<Item = (String, serde_json::Value)>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl Serialize for JsonObject {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in &self.0 {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+/// How chromosomes are named in the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChromosomeStyle {
+    /// `chr1`, ..., `chrX`, `chrY`, `chrM`
+    #[default]
+    Ucsc,
+    /// `1`, ..., `X`, `Y`, `MT`, as ClinVar itself names them
+    Ensembl,
+}
+
+impl ChromosomeStyle {
+    /// Names ClinVar's chromosome `chr` (as found in `SequenceLocation@Chr`) in this style
+    pub fn name(&self, chr: &str) -> String {
+        match (self, chr) {
+            (Self::Ucsc, "MT") => "chrM".to_string(),
+            (Self::Ucsc, chr) => format!("chr{chr}"),
+            (Self::Ensembl, chr) => chr.to_string(),
+        }
+    }
+}
+
+/// Star rating (0 to 4) of an aggregate review status, as shown on the ClinVar website
+pub fn review_status_stars(review_status: &str) -> u8 {
+    match review_status {
+        "practice guideline" => 4,
+        "reviewed by expert panel" => 3,
+        "criteria provided, multiple submitters, no conflicts" => 2,
+        "criteria provided, single submitter"
+        | "criteria provided, conflicting classifications"
+        | "criteria provided, conflicting interpretations" => 1,
+        _ => 0,
+    }
+}
+
+/// Pushes `value` to `values` unless it is already there (keeps the original order)
+fn push_unique(values: &mut Vec<String>, value: &str) {
+    if !values.iter().any(|v| v == value) {
+        values.push(value.to_string());
+    }
+}
+
+impl ClinVarRecord {
+    /// Extracts a flat record out of a typed ClinVarSet.
+    /// `assembly` selects which `SequenceLocation` gives the VCF coordinates. If `None`, no coordinates are extracted.
+    pub fn from_clinvar_set(set: &ClinVarSet, assembly: Option<&str>) -> Self {
+        let rca = &set.reference_clinvar_assertion;
+        let mut record = Self {
+            clinvar_id: Some(set.id as usize),
+            status: Some(set.record_status.clone()),
+            replaces: (!set.replaces.is_empty()).then(|| set.replaces.join("|")),
+            description: set.title.clone(),
+            date_last_updated: rca.date_last_updated.clone(),
+            ..Default::default()
+        };
+        if rca.clinvar_accession.accession_type == "RCV" {
+            record.rcv = Some(rca.clinvar_accession.acc.clone());
+        }
+        let germline = rca
+            .classifications
+            .as_ref()
+            .and_then(|c| c.germline.as_ref());
+        record.clnsig = germline
+            .and_then(|g| g.description.as_ref())
+            .map(|d| d.value.replace(" ", "_").to_lowercase());
+        record.review_status = germline.map(|g| g.review_status.clone());
+        if let Some(measure_set) = &rca.measure_set {
+            record.vcv = measure_set.acc.clone();
+            if let Some(location) = assembly.and_then(|assembly| {
+                measure_set
+                    .measures
+                    .iter()
+                    .flat_map(|m| m.sequence_locations.iter())
+                    .rfind(|l| l.assembly == assembly)
+            }) {
+                record.chromosome = Some(ChromosomeStyle::Ucsc.name(&location.chr));
+                record.position = location.position_vcf.map(|p| p as usize);
+                record.reference = location.reference_allele_vcf.clone();
+                record.alternate = location.alternate_allele_vcf.clone();
+            }
+            for measure in &measure_set.measures {
+                for symbol in measure
+                    .measure_relationships
+                    .iter()
+                    .flat_map(|r| r.symbols.iter())
+                    .filter(|s| s.element_value.value_type == "Preferred")
+                {
+                    push_unique(&mut record.genes, &symbol.element_value.value);
+                }
+                for attribute in measure
+                    .attribute_sets
+                    .iter()
+                    .map(|a| &a.attribute)
+                    .filter(|a| a.attribute_type.starts_with("HGVS"))
+                {
+                    if let Some(hgvs) = attribute.value.as_deref().filter(|v| !v.is_empty()) {
+                        push_unique(&mut record.hgvs, hgvs);
+                    }
+                }
+            }
+        }
+        if let Some(trait_set) = &rca.trait_set {
+            for name in trait_set
+                .traits
+                .iter()
+                .flat_map(|t| t.names.iter())
+                .filter(|n| n.element_value.value_type == "Preferred")
+            {
+                push_unique(&mut record.conditions, &name.element_value.value);
+            }
+        }
+        record.submissions = set
+            .clinvar_assertions
+            .iter()
+            .map(|a| a.clinvar_accession.acc.clone())
+            .collect();
+        record
+    }
+
+    /// Multi-valued fields, with their serialized names.
+    /// Flat serializers (CSV) get them pipe-joined, the others should get them as real lists.
+    pub fn multi_valued_fields(&self) -> [(&'static str, &[String]); 4] {
+        [
+            ("CONDITIONS", &self.conditions),
+            ("GENES", &self.genes),
+            ("HGVS", &self.hgvs),
+            ("SUBMISSIONS", &self.submissions),
+        ]
+    }
+
+    /// Serializes this record as a JSON object, with multi-valued fields as arrays.
+    /// Unlike in CSV, every field is there, in order, `null` when it has no value.
+    pub fn to_json(&self) -> Result<JsonObject, ClinvarXMLTabError> {
+        let mut object = match serde_json::to_value(self)? {
+            serde_json::Value::Object(object) => object,
+            _ => serde_json::Map::new(),
+        };
+        for (key, values) in self.multi_valued_fields() {
+            object.insert(key.to_string(), values.into());
+        }
+        Ok(JSON_KEYS
+            .iter()
+            .map(|key| {
+                let value = object.remove(*key).unwrap_or(serde_json::Value::Null);
+                (key.to_string(), value)
+            })
+            .collect())
+    }
+
+    /// ClinVarSet ID
+    pub fn clinvar_id(&self) -> Option<usize> {
+        self.clinvar_id
+    }
+
+    /// Record status of the ClinVarSet (`current`, `replaced`, ...)
+    pub fn status(&self) -> Option<&str> {
+        self.status.as_deref()
+    }
+
+    /// Accession replaced by this record, if any
+    pub fn replaces(&self) -> Option<&str> {
+        self.replaces.as_deref()
+    }
+
+    /// RCV accession of the reference assertion
+    pub fn rcv(&self) -> Option<&str> {
+        self.rcv.as_deref()
+    }
+
+    /// VCV accession of the measure set
+    pub fn vcv(&self) -> Option<&str> {
+        self.vcv.as_deref()
+    }
+
+    /// Star rating of the review status, see [`review_status_stars`]
+    pub fn stars(&self) -> Option<u8> {
+        self.review_status.as_deref().map(review_status_stars)
+    }
+
+    /// Germline classification, lowercased with underscores (e.g. `likely_pathogenic`)
+    pub fn clnsig(&self) -> Option<&str> {
+        self.clnsig.as_deref()
+    }
+
+    /// Review status of the germline classification (e.g. `criteria provided, multiple submitters, no conflicts`)
+    pub fn review_status(&self) -> Option<&str> {
+        self.review_status.as_deref()
+    }
+
+    /// Title of the ClinVarSet
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn date_last_updated(&self) -> Option<&str> {
+        self.date_last_updated.as_deref()
+    }
+
+    /// Chromosome name, UCSC style (`chr1`, ..., `chrM`)
+    pub fn chromosome(&self) -> Option<&str> {
+        self.chromosome.as_deref()
+    }
+
+    /// 1-based VCF position
+    pub fn position(&self) -> Option<usize> {
+        self.position
+    }
+
+    /// VCF reference allele
+    pub fn reference(&self) -> Option<&str> {
+        self.reference.as_deref()
+    }
+
+    /// VCF alternate allele
+    pub fn alternate(&self) -> Option<&str> {
+        self.alternate.as_deref()
+    }
+
+    /// Preferred names of the conditions
+    pub fn conditions(&self) -> &[String] {
+        &self.conditions
+    }
+
+    /// Preferred gene symbols
+    pub fn genes(&self) -> &[String] {
+        &self.genes
+    }
+
+    /// HGVS expressions of the variant (coding, genomic and protein)
+    pub fn hgvs(&self) -> &[String] {
+        &self.hgvs
+    }
+
+    /// SCV accessions of the submissions
+    pub fn submissions(&self) -> &[String] {
+        &self.submissions
+    }
 }
 
 /// Just a convenient function that can be used to deserialize a vec of strings into any serializer that doesn't support nested serialization
-#[allow(dead_code)]
-fn vec_as_string_pipe<S>(v: &Vec<String>, serializer: S) -> Result<S::Ok, S::Error>
+fn vec_as_string_pipe<S>(v: &[String], serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
@@ -41,68 +353,36 @@ where
 pub struct CSVRecordHandler<W: std::io::Write> {
     record: ClinVarRecord,
     serializer: csv::Writer<W>,
+    assembly: Option<&'static str>,
+    headers: bool,
+    fields: Option<FieldSpec>,
+    /// Values of the selected fields, for the current record
+    values: Vec<Vec<String>>,
 }
 
 impl<W: std::io::Write> EventHandler for CSVRecordHandler<W> {
-    fn handle(
-        &mut self,
-        node: &roxmltree::Node,
-        current_path: &Vec<String>,
-        _attributes: &std::collections::HashMap<String, String>,
-        depth: u32,
-    ) -> Result<(), ClinvarXMLTabError> {
-        if depth == 2 {
-            if node.has_tag_name("RecordStatus") {
-                self.record.status = Some(node.text().unwrap_or("").to_string());
-            }
-            if node.has_tag_name("Replaces") {
-                self.record.replaces = Some(node.text().unwrap_or("").to_string());
-            }
-            if node.has_tag_name("Title") {
-                self.record.description = Some(node.text().unwrap_or("").to_string());
-            }
-            if node.has_tag_name("ReferenceClinVarAssertion") {
-                self.record.date_last_updated =
-                    Some(node.attribute("DateLastUpdated").unwrap_or("").to_string());
-            }
+    fn handle_clinvar_set(&mut self, event: &ClinVarSetEvent) -> Result<(), ClinvarXMLTabError> {
+        self.record = ClinVarRecord::from_clinvar_set(event.set()?, self.assembly);
+        if let Some(fields) = &self.fields {
+            self.values = fields.values(event.node(), &self.record);
         }
-        if depth == 3 {
-            if node.has_tag_name("ClinVarAccession") {
-                if let Some(acc_type) = node.attribute("Type") {
-                    if acc_type == "RCV" {
-                        self.record.rcv = Some(node.attribute("Acc").unwrap_or("").to_string());
-                    }
-                }
-            }
-            if current_path == &["ClinVarSet", "ReferenceClinVarAssertion", "MeasureSet"] {
-                self.record.vcv = Some(node.attribute("Acc").unwrap_or("").to_string());
-            }
-        }
-        if depth == 5 {
-            if current_path
-                == &[
-                    "ClinVarSet",
-                    "ReferenceClinVarAssertion",
-                    "Classifications",
-                    "GermlineClassification",
-                    "Description",
-                ]
-            {
-                self.record.clnsig = Some(
-                    node.text()
-                        .iter()
-                        .map(|s| s.replace(" ", "_").to_lowercase())
-                        .collect(),
-                );
-            }
-        }
-
         Ok(())
     }
 
     fn end_record(&mut self) -> Result<(), ClinvarXMLTabError> {
         let record = take(&mut self.record);
-        self.serializer.serialize(record)?;
+        if self.fields.is_some() {
+            let values = take(&mut self.values);
+            self.serializer
+                .write_record(values.iter().map(|v| v.join("|")))?;
+        } else {
+            self.serializer.serialize(record)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), ClinvarXMLTabError> {
+        self.serializer.flush()?;
         Ok(())
     }
 }
@@ -112,176 +392,413 @@ impl<W: std::io::Write> CSVRecordHandler<W> {
         Self {
             serializer: csv::Writer::from_writer(writer),
             record: ClinVarRecord::default(),
+            assembly: None,
+            headers: true,
+            fields: None,
+            values: Vec::new(),
+        }
+    }
+
+    /// Tab separated values, with VCF coordinates from `assembly`.
+    /// The header line is only written if `headers` is set (it shouldn't be when appending to an existing file).
+    pub fn new_tsv_from_writer(writer: W, assembly: &'static str, headers: bool) -> Self {
+        Self {
+            serializer: csv::WriterBuilder::new()
+                .delimiter(b'\t')
+                .has_headers(headers)
+                .from_writer(writer),
+            record: ClinVarRecord::default(),
+            assembly: Some(assembly),
+            headers,
+            fields: None,
+            values: Vec::new(),
+        }
+    }
+
+    /// Writes the given fields instead of the whole record
+    pub fn with_fields(mut self, fields: FieldSpec) -> Result<Self, ClinvarXMLTabError> {
+        if self.headers {
+            self.serializer.write_record(fields.names())?;
+        }
+        self.fields = Some(fields);
+        Ok(self)
+    }
+}
+
+/// Writes one JSON object per ClinVarSet, one per line.
+/// Unlike in CSV, multi-valued fields are written as arrays.
+pub struct JsonLinesRecordHandler<W: std::io::Write> {
+    record: ClinVarRecord,
+    writer: W,
+    assembly: &'static str,
+    fields: Option<FieldSpec>,
+    values: Vec<Vec<String>>,
+}
+
+impl<W: std::io::Write> EventHandler for JsonLinesRecordHandler<W> {
+    fn handle_clinvar_set(&mut self, event: &ClinVarSetEvent) -> Result<(), ClinvarXMLTabError> {
+        self.record = ClinVarRecord::from_clinvar_set(event.set()?, Some(self.assembly));
+        if let Some(fields) = &self.fields {
+            self.values = fields.values(event.node(), &self.record);
         }
+        Ok(())
+    }
+
+    fn end_record(&mut self) -> Result<(), ClinvarXMLTabError> {
+        let record = take(&mut self.record);
+        let value = match &self.fields {
+            Some(fields) => fields.json(&take(&mut self.values)),
+            None => record.to_json()?,
+        };
+        serde_json::to_writer(&mut self.writer, &value)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), ClinvarXMLTabError> {
+        self.writer.flush()?;
+        Ok(())
     }
 }
 
+impl<W: std::io::Write> JsonLinesRecordHandler<W> {
+    pub fn new_from_writer(writer: W, assembly: &'static str) -> Self {
+        Self {
+            record: ClinVarRecord::default(),
+            writer,
+            assembly,
+            fields: None,
+            values: Vec::new(),
+        }
+    }
+
+    /// Writes the given fields instead of the whole record
+    pub fn with_fields(mut self, fields: FieldSpec) -> Self {
+        self.fields = Some(fields);
+        self
+    }
+}
+
+/// One INFO field of a record: its key, its values and whether it is an integer
+type InfoValues = (String, Vec<String>, bool);
+
+/// ClinVarSets of the same variant (MeasureSet accession), to be written as one VCF line
+struct VcvGroup {
+    chromosome: String,
+    position: usize,
+    reference: String,
+    alternate: String,
+    /// INFO fields of every ClinVarSet in the group
+    records: Vec<Vec<InfoValues>>,
+    ref_mismatch: bool,
+}
+
+/// Natural order of chromosomes (1 to 22, X, Y, M), for those missing from the VCF header
+fn chromosome_rank(chromosome: &str) -> (usize, String) {
+    let name = chromosome.strip_prefix("chr").unwrap_or(chromosome);
+    let rank = match name {
+        "X" => 23,
+        "Y" => 24,
+        "M" | "MT" => 25,
+        name => name.parse().unwrap_or(26),
+    };
+    (rank, name.to_string())
+}
+
 pub struct VCFRecordHandler<W: std::io::Write> {
     record: ClinVarRecord,
+    written: u64,
     vcf_writer: vcf::io::Writer<W>,
     vcf_header: vcf::Header,
     assembly: &'static str,
+    fields: Option<FieldSpec>,
+    values: Vec<Vec<String>>,
+    /// Variants kept until the end, when aggregating by VCV (in the order they were first seen)
+    groups: Option<Vec<VcvGroup>>,
+    group_index: HashMap<String, usize>,
+    reference_genome: Option<(Arc<Mutex<ReferenceGenome>>, RefMismatch)>,
+    reference_counts: ReferenceCounts,
+}
+
+/// What happened to the variants checked against the reference genome
+#[derive(Debug, Default, Clone, Copy)]
+struct ReferenceCounts {
+    checked: u64,
+    mismatches: u64,
+    normalized: u64,
+    unknown_contig: u64,
 }
 
 impl<W: std::io::Write> EventHandler for VCFRecordHandler<W> {
-    fn handle(
-        &mut self,
-        node: &roxmltree::Node,
-        current_path: &Vec<String>,
-        _attributes: &std::collections::HashMap<String, String>,
-        depth: u32,
-    ) -> Result<(), ClinvarXMLTabError> {
-        if depth == 1 {
-            if node.has_tag_name("ClinVarSet") {
-                self.record.clinvar_id = node
-                    .attribute("ID")
-                    .map(|s| s.parse::<usize>().ok())
-                    .flatten();
-            }
-        }
-        if depth == 2 {
-            if node.has_tag_name("RecordStatus") {
-                self.record.status = Some(node.text().unwrap_or("").to_string());
-            }
-            if node.has_tag_name("Replaces") {
-                self.record.replaces = Some(node.text().unwrap_or("").to_string());
-            }
-            if node.has_tag_name("Title") {
-                self.record.description = Some(node.text().unwrap_or("").to_string());
-            }
-            if node.has_tag_name("ReferenceClinVarAssertion") {
-                self.record.date_last_updated =
-                    Some(node.attribute("DateLastUpdated").unwrap_or("").to_string());
-            }
+    fn handle_clinvar_set(&mut self, event: &ClinVarSetEvent) -> Result<(), ClinvarXMLTabError> {
+        self.record = ClinVarRecord::from_clinvar_set(event.set()?, Some(self.assembly));
+        if let Some(fields) = &self.fields {
+            self.values = fields.values(event.node(), &self.record);
         }
-        if depth == 3 {
-            if node.has_tag_name("ClinVarAccession") {
-                if let Some(acc_type) = node.attribute("Type") {
-                    if acc_type == "RCV" {
-                        self.record.rcv = Some(node.attribute("Acc").unwrap_or("").to_string());
+        Ok(())
+    }
+
+    fn end_record(&mut self) -> Result<(), ClinvarXMLTabError> {
+        let record = take(&mut self.record);
+        let info = self.info_values(&record);
+        let (Some(chrom), Some(pos), Some(reference), Some(alternate)) = (
+            record.chromosome,
+            record.position,
+            record.reference,
+            record.alternate,
+        ) else {
+            return Ok(());
+        };
+        let Some((pos, reference, alternate, ref_mismatch)) =
+            self.check_reference(&chrom, pos, reference, alternate)?
+        else {
+            return Ok(());
+        };
+        match &mut self.groups {
+            Some(groups) => {
+                let group = VcvGroup {
+                    chromosome: chrom,
+                    position: pos,
+                    reference,
+                    alternate,
+                    records: Vec::new(),
+                    ref_mismatch,
+                };
+                // A ClinVarSet without VCV is a variant of its own, still written in order
+                let index = match record.vcv {
+                    Some(vcv) => *self.group_index.entry(vcv).or_insert_with(|| {
+                        groups.push(group);
+                        groups.len() - 1
+                    }),
+                    None => {
+                        groups.push(group);
+                        groups.len() - 1
                     }
-                }
+                };
+                groups[index].records.push(info);
             }
-            if current_path == &["ClinVarSet", "ReferenceClinVarAssertion", "MeasureSet"] {
-                self.record.vcv = Some(node.attribute("Acc").unwrap_or("").to_string());
+            None => {
+                use vcf::variant::record_buf::info::field::Value;
+                let info = info
+                    .into_iter()
+                    .map(|(key, values, integer)| {
+                        let value = match values[0].parse() {
+                            Ok(integer_value) if integer => Value::Integer(integer_value),
+                            _ => Value::String(values.join("|")),
+                        };
+                        (key, Some(value))
+                    })
+                    .collect();
+                self.write(&chrom, pos, &reference, &alternate, info, ref_mismatch)?;
             }
         }
-        if depth == 5 {
-            if current_path
-                == &[
-                    "ClinVarSet",
-                    "ReferenceClinVarAssertion",
-                    "Classifications",
-                    "GermlineClassification",
-                    "Description",
-                ]
-            {
-                self.record.clnsig = Some(
-                    node.text()
-                        .iter()
-                        .map(|s| s.replace(" ", "_").to_lowercase())
-                        .collect(),
-                );
-            }
-            if current_path
-                == &[
-                    "ClinVarSet",
-                    "ReferenceClinVarAssertion",
-                    "MeasureSet",
-                    "Measure",
-                    "SequenceLocation",
-                ]
-            {
-                if let Some(genome_assembly) = node.attribute("Assembly") {
-                    if genome_assembly == self.assembly {
-                        self.record.chromosome = node.attribute("Chr").map(|s| match s {
-                            "MT" => "chrM".to_string(),
-                            _ => format!("chr{s}"),
-                        });
-                        self.record.position = node
-                            .attribute("positionVCF")
-                            .map(|s| s.parse().ok())
-                            .flatten();
-                        self.record.reference =
-                            node.attribute("referenceAlleleVCF").map(|s| s.to_string());
-                        self.record.alternate =
-                            node.attribute("alternateAlleleVCF").map(|s| s.to_string());
-                    }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), ClinvarXMLTabError> {
+        std::io::Write::flush(self.vcf_writer.get_mut())?;
+        Ok(())
+    }
+
+    /// Writes the aggregated variants, sorted by position
+    fn finish(&mut self) -> Result<(), ClinvarXMLTabError> {
+        use vcf::variant::record_buf::info::field::Value;
+        let Some(mut groups) = self.groups.take() else {
+            return Ok(());
+        };
+        let contigs: HashMap<&str, usize> = self
+            .vcf_header
+            .contigs()
+            .keys()
+            .enumerate()
+            .map(|(i, name)| (name.as_str(), i))
+            .collect();
+        groups.sort_by_cached_key(|g| {
+            (
+                contigs
+                    .get(g.chromosome.as_str())
+                    .copied()
+                    .unwrap_or(usize::MAX),
+                chromosome_rank(&g.chromosome),
+                g.position,
+            )
+        });
+        for group in groups {
+            // Keys in the order they first appear, with the values of every record (`.` if missing).
+            // Several values of the same record are joined with `&`, records with `|`.
+            let mut keys: Vec<&str> = Vec::new();
+            for (key, _, _) in group.records.iter().flatten() {
+                if !keys.contains(&key.as_str()) {
+                    keys.push(key);
                 }
             }
+            let info = keys
+                .iter()
+                .map(|key| {
+                    let values: Vec<String> = group
+                        .records
+                        .iter()
+                        .map(|record| {
+                            record
+                                .iter()
+                                .find(|(k, _, _)| k == key)
+                                .map_or_else(|| ".".to_string(), |(_, values, _)| values.join("&"))
+                        })
+                        .collect();
+                    (key.to_string(), Some(Value::String(values.join("|"))))
+                })
+                .collect();
+            self.write(
+                &group.chromosome,
+                group.position,
+                &group.reference,
+                &group.alternate,
+                info,
+                group.ref_mismatch,
+            )?;
         }
-
         Ok(())
     }
 
-    fn end_record(&mut self) -> Result<(), ClinvarXMLTabError> {
-        use vcf::variant::record_buf::{info::field::Value, Info};
-        let info: Info = [
-            (
-                String::from("CLNACC"),
-                self.record
-                    .rcv
-                    .as_ref()
-                    .map(|s| Value::String(s.to_string())),
-            ),
-            (
-                String::from("CLNSIG"),
-                self.record
-                    .clnsig
-                    .as_ref()
-                    .map(|s| Value::String(s.to_string())),
-            ),
-            (
-                String::from("CLNID"),
-                self.record
-                    .clinvar_id
-                    .as_ref()
-                    .map(|v| Value::Integer(*v as i32)),
-            ),
+    fn records_written(&self) -> Option<u64> {
+        Some(self.written)
+    }
+
+    fn counters(&self) -> Vec<(&'static str, u64)> {
+        if self.reference_genome.is_none() {
+            return Vec::new();
+        }
+        let counts = self.reference_counts;
+        vec![
+            ("reference_checked", counts.checked),
+            ("reference_mismatches", counts.mismatches),
+            ("normalized", counts.normalized),
+            ("unknown_contig", counts.unknown_contig),
         ]
-        .into_iter()
-        .collect();
-        match (
-            &self.record.chromosome,
-            &self.record.position,
-            &self.record.reference,
-            &self.record.alternate,
-        ) {
-            (Some(chrom), Some(pos), Some(reference), Some(alternate)) => {
-                let vcf_record = vcf::variant::RecordBuf::builder()
-                    .set_reference_sequence_name(chrom)
-                    .set_variant_start(Position::new(*pos).expect("Invalid position"))
-                    .set_reference_bases(reference)
-                    .set_alternate_bases(vcf::variant::record_buf::AlternateBases::from(vec![
-                        alternate.to_string(),
-                    ]))
-                    .set_info(info)
-                    .build();
-                self.vcf_writer
-                    .write_variant_record(&self.vcf_header, &vcf_record)?;
+    }
+}
+
+impl<W: std::io::Write> VCFRecordHandler<W> {
+    /// Checks the alleles against the reference genome, if any, and normalizes them.
+    /// Returns `None` for variants to leave out, along with whether REF mismatched otherwise.
+    fn check_reference(
+        &mut self,
+        chrom: &str,
+        pos: usize,
+        reference: String,
+        alternate: String,
+    ) -> Result<Option<(usize, String, String, bool)>, ClinvarXMLTabError> {
+        let Some((genome, on_mismatch)) = &self.reference_genome else {
+            return Ok(Some((pos, reference, alternate, false)));
+        };
+        let mut genome = genome.lock().unwrap_or_else(PoisonError::into_inner);
+        let counts = &mut self.reference_counts;
+        counts.checked += 1;
+        match check_and_normalize(&mut genome, chrom, pos, &reference, &alternate)? {
+            Checked::Valid {
+                position,
+                reference: normalized_reference,
+                alternate: normalized_alternate,
+            } => {
+                if position != pos || !normalized_reference.eq_ignore_ascii_case(&reference) {
+                    counts.normalized += 1;
+                }
+                Ok(Some((
+                    position,
+                    normalized_reference,
+                    normalized_alternate,
+                    false,
+                )))
+            }
+            Checked::Mismatch => {
+                counts.mismatches += 1;
+                match on_mismatch {
+                    RefMismatch::Flag => Ok(Some((pos, reference, alternate, true))),
+                    RefMismatch::Drop => Ok(None),
+                }
+            }
+            // Can't tell, so it is kept as it is
+            Checked::UnknownContig => {
+                counts.unknown_contig += 1;
+                Ok(Some((pos, reference, alternate, false)))
             }
-            _ => {}
         }
+    }
 
-        // We've just wrote
-        self.record = ClinVarRecord::default();
+    /// INFO fields of `record`, without those that have no value
+    fn info_values(&mut self, record: &ClinVarRecord) -> Vec<InfoValues> {
+        let mut info: Vec<InfoValues> = if let Some(fields) = &self.fields {
+            fields
+                .fields()
+                .iter()
+                .zip(take(&mut self.values))
+                .map(|(field, values)| {
+                    let integer =
+                        matches!(field.kind(), FieldKind::Builtin(builtin) if builtin.is_integer());
+                    (field.info_key(), values, integer)
+                })
+                .collect()
+        } else {
+            let mut info = vec![
+                (
+                    "CLNACC".to_string(),
+                    record.rcv.iter().cloned().collect(),
+                    false,
+                ),
+                (
+                    "CLNSIG".to_string(),
+                    record.clnsig.iter().cloned().collect(),
+                    false,
+                ),
+                (
+                    "CLNID".to_string(),
+                    record.clinvar_id.iter().map(|v| v.to_string()).collect(),
+                    true,
+                ),
+            ];
+            if self.groups.is_some() {
+                info.push(("CLNDN".to_string(), record.conditions.clone(), false));
+            }
+            info
+        };
+        info.retain(|(_, values, _)| !values.is_empty());
+        info
+    }
 
+    fn write(
+        &mut self,
+        chrom: &str,
+        pos: usize,
+        reference: &str,
+        alternate: &str,
+        info: vcf::variant::record_buf::Info,
+        ref_mismatch: bool,
+    ) -> Result<(), ClinvarXMLTabError> {
+        let mut builder = vcf::variant::RecordBuf::builder();
+        if ref_mismatch {
+            builder = builder.set_filters([REF_MISMATCH_FILTER.to_string()].into_iter().collect());
+        }
+        let vcf_record = builder
+            .set_reference_sequence_name(chrom)
+            .set_variant_start(Position::new(pos).expect("Invalid position"))
+            .set_reference_bases(reference)
+            .set_alternate_bases(vcf::variant::record_buf::AlternateBases::from(vec![
+                alternate.to_string(),
+            ]))
+            .set_info(info)
+            .build();
+        self.vcf_writer
+            .write_variant_record(&self.vcf_header, &vcf_record)?;
+        self.written += 1;
         Ok(())
     }
-}
 
-impl<W: std::io::Write> VCFRecordHandler<W> {
     pub fn new_from_writer_unchecked(
         writer: W,
         vcf_header: vcf::Header,
         assembly: &'static str,
     ) -> Self {
-        let mut res = Self {
-            vcf_writer: vcf::io::Writer::new(writer),
-            record: ClinVarRecord::default(),
-            vcf_header,
-            assembly,
-        };
+        let mut res = Self::new_from_writer_without_header(writer, vcf_header, assembly);
         res.vcf_writer.write_header(&res.vcf_header).unwrap();
         res
     }
@@ -291,13 +808,192 @@ impl<W: std::io::Write> VCFRecordHandler<W> {
         vcf_header: vcf::Header,
         assembly: &'static str,
     ) -> Result<Self, ClinvarXMLTabError> {
-        let mut res = Self {
+        let mut res = Self::new_from_writer_without_header(writer, vcf_header, assembly);
+        res.vcf_writer.write_header(&res.vcf_header)?;
+        Ok(res)
+    }
+
+    /// Creates a handler that appends records to `writer`, assuming the header was already written (e.g. when resuming a conversion)
+    pub fn new_from_writer_without_header(
+        writer: W,
+        vcf_header: vcf::Header,
+        assembly: &'static str,
+    ) -> Self {
+        Self {
             vcf_writer: vcf::io::Writer::new(writer),
             record: ClinVarRecord::default(),
+            written: 0,
             vcf_header,
             assembly,
-        };
-        res.vcf_writer.write_header(&res.vcf_header)?;
-        Ok(res)
+            fields: None,
+            values: Vec::new(),
+            groups: None,
+            group_index: HashMap::new(),
+            reference_genome: None,
+            reference_counts: ReferenceCounts::default(),
+        }
+    }
+
+    /// Writes the given fields in INFO instead of CLNACC, CLNSIG and CLNID.
+    /// The header should declare them (see [`FieldSpec::add_info_headers`]).
+    pub fn with_fields(mut self, fields: FieldSpec) -> Self {
+        self.fields = Some(fields);
+        self
+    }
+
+    /// Writes one line per variant (VCV) instead of one per ClinVarSet, like NCBI's clinvar.vcf.
+    /// INFO fields hold the values of every ClinVarSet of the variant, pipe-separated (CLNDN, the conditions, is added).
+    /// Every variant of the input (along with the ClinVarSets without VCV) is kept in memory, so memory use grows
+    /// with the whole release, and written, sorted, once the input is exhausted.
+    pub fn with_vcv_aggregation(mut self) -> Self {
+        self.groups = Some(Vec::new());
+        self
+    }
+
+    /// Checks REF against `genome`, left-aligns and trims the alleles.
+    /// When flagging mismatches, the header should declare the filter (see [`crate::reference::add_ref_mismatch_filter`]).
+    /// `genome` can be shared by several handlers, e.g. those of the files written with `--split-by`.
+    pub fn with_reference(
+        mut self,
+        genome: Arc<Mutex<ReferenceGenome>>,
+        on_mismatch: RefMismatch,
+    ) -> Self {
+        self.reference_genome = Some((genome, on_mismatch));
+        self
+    }
+}
+
+/// Side table mapping replaced accessions to the RCV that replaces them, from the `Replaces` elements.
+/// Columns are REPLACED, REPLACED_BY, VCV and CLINVAR_ID.
+pub struct ReplacementsHandler<W: std::io::Write> {
+    writer: W,
+    rows: Vec<String>,
+}
+
+impl<W: std::io::Write> EventHandler for ReplacementsHandler<W> {
+    fn handle_clinvar_set(&mut self, event: &ClinVarSetEvent) -> Result<(), ClinvarXMLTabError> {
+        let node = event.node();
+        // Only a few ClinVarSets replace anything, so they are not worth deserializing
+        let replaced: Vec<&str> = node
+            .children()
+            .filter(|c| c.has_tag_name("Replaces"))
+            .filter_map(|c| c.text())
+            .map(str::trim)
+            .collect();
+        if replaced.is_empty() {
+            return Ok(());
+        }
+        let rca = node
+            .children()
+            .find(|c| c.has_tag_name("ReferenceClinVarAssertion"));
+        let rcv = rca
+            .and_then(|n| n.children().find(|c| c.has_tag_name("ClinVarAccession")))
+            .and_then(|n| n.attribute("Acc"))
+            .unwrap_or_default();
+        let vcv = rca
+            .and_then(|n| n.children().find(|c| c.has_tag_name("MeasureSet")))
+            .and_then(|n| n.attribute("Acc"))
+            .unwrap_or_default();
+        let id = node.attribute("ID").unwrap_or_default();
+        self.rows.extend(
+            replaced
+                .into_iter()
+                .map(|accession| format!("{accession}\t{rcv}\t{vcv}\t{id}\n")),
+        );
+        Ok(())
+    }
+
+    fn end_record(&mut self) -> Result<(), ClinvarXMLTabError> {
+        for row in self.rows.drain(..) {
+            self.writer.write_all(row.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), ClinvarXMLTabError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: std::io::Write> ReplacementsHandler<W> {
+    /// The header line is only written if `headers` is set (it shouldn't be when appending to an existing file)
+    pub fn new_from_writer(mut writer: W, headers: bool) -> Result<Self, ClinvarXMLTabError> {
+        if headers {
+            writer.write_all(b"REPLACED\tREPLACED_BY\tVCV\tCLINVAR_ID\n")?;
+        }
+        Ok(Self {
+            writer,
+            rows: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const XML: &str = include_str!("../clinvarset_new.xml");
+
+    #[test]
+    fn test_to_json() {
+        let set = ClinVarSet::from_xml(XML).unwrap();
+        let record = ClinVarRecord::from_clinvar_set(&set, Some("GRCh38"));
+        let object = record.to_json().unwrap();
+        assert!(object.keys().eq(JSON_KEYS));
+        assert_eq!(object.get("POSITION"), Some(&124408632.into()));
+        assert_eq!(object.get("GENES"), Some(&serde_json::json!(["OAT"])));
+        let json = serde_json::to_string(&object).unwrap();
+        assert!(json.starts_with(r#"{"CLINVAR_ID":92148146,"STATUS":"current","#));
+
+        // Every serialized field has its key, and it's there even without value
+        let empty = ClinVarRecord::default();
+        let serialized = serde_json::to_value(&record).unwrap();
+        let serialized = serialized.as_object().unwrap();
+        assert!(serialized
+            .keys()
+            .all(|key| JSON_KEYS.contains(&key.as_str())));
+        assert_eq!(
+            empty.to_json().unwrap().get("RCV"),
+            Some(&serde_json::Value::Null)
+        );
+    }
+
+    #[test]
+    fn test_vcv_aggregation() {
+        use vcf::header::record::value::{map::Contig, Map};
+
+        // The same variant twice, around a ClinVarSet without VCV further down the chromosome
+        let without_vcv = XML
+            .replace(r#" Acc="VCV000000165""#, "")
+            .replace(r#"positionVCF="124408632""#, r#"positionVCF="130000000""#);
+        let header = vcf::Header::builder()
+            .add_contig("chr10", Map::<Contig>::new())
+            .build();
+        let mut handler =
+            VCFRecordHandler::new_from_writer_without_header(Vec::new(), header, "GRCh38")
+                .with_vcv_aggregation();
+        for xml in [XML, &without_vcv, XML] {
+            let doc = roxmltree::Document::parse(xml).unwrap();
+            let event = ClinVarSetEvent::new(doc.root_element());
+            handler.handle_clinvar_set(&event).unwrap();
+            handler.end_record().unwrap();
+        }
+        assert_eq!(handler.records_written(), Some(0));
+        handler.finish().unwrap();
+        let vcf = String::from_utf8(handler.vcf_writer.get_ref().clone()).unwrap();
+        let lines: Vec<Vec<&str>> = vcf.lines().map(|l| l.split('\t').collect()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0][..2], ["chr10", "124408632"]);
+        assert!(lines[0][7].contains("CLNACC=RCV000000188|RCV000000188"));
+        assert_eq!(lines[1][..2], ["chr10", "130000000"]);
+    }
+
+    #[test]
+    fn test_csv_without_rcv() {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.serialize(ClinVarRecord::default()).unwrap();
+        let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert!(csv.starts_with("CLINVAR_ID,STATUS,REPLACES,VCV,"));
     }
 }
@@ -0,0 +1,440 @@
+//! Selection of ClinVarSets by position in the input, accession, filter, or random sampling (possibly stratified).
+//!
+//! Selected ClinVarSets are given as their raw XML, as found in the input, so they can be written as they are
+//! (e.g. to build test fixtures) or parsed again.
+use std::collections::HashMap;
+use std::io::BufRead;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::clinvar::filter::Filter;
+use crate::clinvar::get::Accession;
+use crate::clinvar::model::ClinVarSet;
+use crate::clinvar::record::ClinVarRecord;
+use crate::error::ClinvarXMLTabError;
+use crate::reader;
+
+/// What random samples are stratified by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stratum {
+    Clnsig,
+    /// Type of the first measure (`single nucleotide variant`, `Deletion`...)
+    VariantType,
+}
+
+impl Stratum {
+    fn value<'a>(&self, set: &'a ClinVarSet, record: &'a ClinVarRecord) -> &'a str {
+        match self {
+            Self::Clnsig => record.clnsig(),
+            Self::VariantType => set
+                .reference_clinvar_assertion
+                .measure_set
+                .as_ref()
+                .and_then(|m| m.measures.first())
+                .map(|m| m.measure_type.as_str()),
+        }
+        .unwrap_or_default()
+    }
+}
+
+/// Which ClinVarSets to select. By default, all of them.
+#[derive(Debug, Clone, Default)]
+pub struct Selection {
+    skip: u64,
+    limit: Option<u64>,
+    accessions: Vec<Accession>,
+    filter: Option<(Filter, &'static str)>,
+    /// Sample size and seed
+    random: Option<(usize, u64)>,
+    strata: Vec<Stratum>,
+}
+
+/// ClinVarSets sampled at random, along with their rank in the input
+#[derive(Debug, Default)]
+struct Reservoir {
+    candidates: usize,
+    sample: Vec<(u64, Vec<u8>)>,
+}
+
+impl Reservoir {
+    fn add(&mut self, size: usize, rng: &mut StdRng, rank: u64, elem_bytes: &[u8]) {
+        self.candidates += 1;
+        if self.sample.len() < size {
+            self.sample.push((rank, elem_bytes.to_vec()));
+        } else {
+            let i = rng.gen_range(0..self.candidates);
+            if i < size {
+                self.sample[i] = (rank, elem_bytes.to_vec());
+            }
+        }
+    }
+}
+
+impl Selection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Skips the first `skip` ClinVarSets of the input
+    pub fn with_skip(mut self, skip: u64) -> Self {
+        self.skip = skip;
+        self
+    }
+
+    /// Selects at most `limit` ClinVarSets
+    pub fn with_limit(mut self, limit: u64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Only selects the ClinVarSets with any of these accessions.
+    /// Without a limit, reading stops once each of them was found.
+    pub fn with_accessions(mut self, accessions: Vec<Accession>) -> Self {
+        self.accessions = accessions;
+        self
+    }
+
+    /// Only selects the ClinVarSets that pass `filter`, with coordinates on `assembly`
+    pub fn with_filter(mut self, filter: Filter, assembly: &'static str) -> Self {
+        self.filter = Some((filter, assembly));
+        self
+    }
+
+    /// Samples `size` ClinVarSets (see [`Selection::with_random_sample`]) for every combination of values
+    /// of `strata`, instead of `size` overall
+    pub fn with_strata(mut self, strata: Vec<Stratum>) -> Self {
+        self.strata = strata;
+        self
+    }
+
+    /// Selects `size` ClinVarSets at random among those that would be selected otherwise (reservoir sampling,
+    /// so the whole input is read). They are given in input order.
+    pub fn with_random_sample(mut self, size: usize, seed: u64) -> Self {
+        self.random = Some((size, seed));
+        self
+    }
+
+    /// Whether the ClinVarSet passes the filters, and if so, its stratum.
+    /// Marks the accessions it has as `found`.
+    fn matches(
+        &self,
+        elem_bytes: &[u8],
+        found: &mut [bool],
+    ) -> Result<Option<String>, ClinvarXMLTabError> {
+        if self.accessions.is_empty() && self.filter.is_none() && self.strata.is_empty() {
+            return Ok(Some(String::new()));
+        }
+        let xml = std::str::from_utf8(elem_bytes)?;
+        let doc = roxmltree::Document::parse(xml)?;
+        let node = doc.root_element();
+        if !self.accessions.is_empty() {
+            let mut matched = false;
+            for (accession, found) in self.accessions.iter().zip(found.iter_mut()) {
+                if accession.matches(&node) {
+                    *found = true;
+                    matched = true;
+                }
+            }
+            if !matched {
+                return Ok(None);
+            }
+        }
+        let needs_record = self
+            .filter
+            .as_ref()
+            .is_some_and(|(filter, _)| filter.needs_record());
+        if !needs_record && self.strata.is_empty() {
+            let passes = self
+                .filter
+                .as_ref()
+                .is_none_or(|(filter, _)| filter.matches_status(&node));
+            return Ok(passes.then(String::new));
+        }
+        let assembly = self
+            .filter
+            .as_ref()
+            .map_or("GRCh38", |(_, assembly)| assembly);
+        let set = ClinVarSet::from_node(&node)?;
+        let record = ClinVarRecord::from_clinvar_set(&set, Some(assembly));
+        if let Some((filter, _)) = &self.filter {
+            if !filter.matches(&node, &record) {
+                return Ok(None);
+            }
+        }
+        let stratum: Vec<&str> = self
+            .strata
+            .iter()
+            .map(|stratum| stratum.value(&set, &record))
+            .collect();
+        Ok(Some(stratum.join("\t")))
+    }
+
+    /// Calls `on_set` with the raw XML of every selected ClinVarSet, until it returns `false`.
+    /// Returns the number of ClinVarSets selected.
+    pub fn select(
+        &self,
+        input: impl BufRead,
+        mut on_set: impl FnMut(&[u8]) -> Result<bool, ClinvarXMLTabError>,
+    ) -> Result<u64, ClinvarXMLTabError> {
+        let mut seen = 0;
+        let mut selected = 0;
+        let mut found = vec![false; self.accessions.len()];
+        let mut reservoirs: HashMap<String, Reservoir> = HashMap::new();
+        let mut rng = self.random.map(|(_, seed)| StdRng::seed_from_u64(seed));
+        let under_limit = |selected: u64| self.limit.is_none_or(|limit| selected < limit);
+        reader::scan_clinvar_sets(input, |_, elem_bytes| {
+            seen += 1;
+            if seen <= self.skip {
+                return Ok(true);
+            }
+            let Some(stratum) = self.matches(elem_bytes, &mut found)? else {
+                return Ok(true);
+            };
+            match (self.random, rng.as_mut()) {
+                (Some((size, _)), Some(rng)) => {
+                    reservoirs
+                        .entry(stratum)
+                        .or_default()
+                        .add(size, rng, seen, elem_bytes);
+                    Ok(true)
+                }
+                _ => {
+                    selected += 1;
+                    if !on_set(elem_bytes)? {
+                        return Ok(false);
+                    }
+                    let all_found = !found.is_empty() && found.iter().all(|found| *found);
+                    Ok(under_limit(selected) && !(self.limit.is_none() && all_found))
+                }
+            }
+        })?;
+        // Sampled ClinVarSets are given in input order, whatever their stratum
+        let mut sample: Vec<(u64, Vec<u8>)> = reservoirs
+            .into_values()
+            .flat_map(|reservoir| reservoir.sample)
+            .collect();
+        sample.sort_unstable_by_key(|(rank, _)| *rank);
+        for (_, elem_bytes) in sample {
+            if !under_limit(selected) {
+                break;
+            }
+            selected += 1;
+            if !on_set(&elem_bytes)? {
+                break;
+            }
+        }
+        Ok(selected)
+    }
+}
+
+/// Writes the ClinVarSets selected from `input` as a well-formed XML document, with the same XML declaration and
+/// root element (and its attributes) as the input. Returns the number of ClinVarSets written.
+pub fn write_subset<W: std::io::Write>(
+    mut input: impl BufRead,
+    selection: &Selection,
+    mut writer: W,
+) -> Result<u64, ClinvarXMLTabError> {
+    let prolog = reader::read_prolog(&mut input)?;
+    writer.write_all(&prolog.xml)?;
+    writer.write_all(b"\n")?;
+    let selected = selection.select(&mut input, |elem_bytes| {
+        writer.write_all(elem_bytes)?;
+        writer.write_all(b"\n")?;
+        Ok(true)
+    })?;
+    writeln!(writer, "</{}>", prolog.root)?;
+    writer.flush()?;
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A release of `n` minimal ClinVarSets, with IDs 0 to n-1 and RCVs RCV000000000 to RCV00000000{n-1}
+    fn release(n: usize) -> String {
+        let sets: String = (0..n)
+            .map(|i| {
+                format!(
+                    r#"<ClinVarSet ID="{i}"><RecordStatus>current</RecordStatus><ReferenceClinVarAssertion><ClinVarAccession Acc="RCV{i:09}"/></ReferenceClinVarAssertion></ClinVarSet>
+"#
+                )
+            })
+            .collect();
+        format!("<ReleaseSet>\n{sets}</ReleaseSet>\n")
+    }
+
+    /// IDs of the ClinVarSets selected from `release`
+    fn selected_ids(selection: &Selection, release: &str) -> Vec<u64> {
+        let mut ids = Vec::new();
+        let selected = selection
+            .select(release.as_bytes(), |elem_bytes| {
+                let xml = std::str::from_utf8(elem_bytes)?;
+                let doc = roxmltree::Document::parse(xml)?;
+                ids.push(doc.root_element().attribute("ID").unwrap().parse().unwrap());
+                Ok(true)
+            })
+            .unwrap();
+        assert_eq!(selected as usize, ids.len());
+        ids
+    }
+
+    #[test]
+    fn test_skip_and_limit() {
+        let release = release(10);
+        assert_eq!(
+            selected_ids(&Selection::new(), &release),
+            (0..10).collect::<Vec<_>>()
+        );
+        let selection = Selection::new().with_skip(3).with_limit(4);
+        assert_eq!(selected_ids(&selection, &release), [3, 4, 5, 6]);
+        assert!(selected_ids(&Selection::new().with_skip(10), &release).is_empty());
+    }
+
+    #[test]
+    fn test_accessions() {
+        let release = release(10);
+        let accessions = ["RCV000000007", "2"]
+            .into_iter()
+            .map(|a| Accession::parse(a).unwrap())
+            .collect();
+        let selection = Selection::new().with_accessions(accessions);
+        assert_eq!(selected_ids(&selection, &release), [2, 7]);
+        // Reading stops as soon as both were found: the input can be cut short after them
+        let truncated = &release[..release.find(r#"<ClinVarSet ID="8""#).unwrap()];
+        assert_eq!(selected_ids(&selection, truncated), [2, 7]);
+    }
+
+    #[test]
+    fn test_random_sample() {
+        let input = release(50);
+        let sample = selected_ids(&Selection::new().with_random_sample(10, 42), &input);
+        assert_eq!(sample.len(), 10);
+        assert!(sample.windows(2).all(|w| w[0] < w[1]), "{sample:?}");
+        // Reproducible with the same seed
+        assert_eq!(
+            selected_ids(&Selection::new().with_random_sample(10, 42), &input),
+            sample
+        );
+        assert_eq!(
+            selected_ids(&Selection::new().with_random_sample(100, 1), &input).len(),
+            50
+        );
+        // Every ClinVarSet is about as likely to be picked, including the first ones
+        let mut picked = [0u32; 5];
+        let small = release(5);
+        for seed in 0..2000 {
+            for id in selected_ids(&Selection::new().with_random_sample(2, seed), &small) {
+                picked[id as usize] += 1;
+            }
+        }
+        assert!(
+            picked.iter().all(|&n| (700..900).contains(&n)),
+            "{picked:?}"
+        );
+    }
+
+    #[test]
+    fn test_write_subset() {
+        let sets = release(5);
+        let sets = sets
+            .trim_start_matches("<ReleaseSet>\n")
+            .trim_end_matches("</ReleaseSet>\n")
+            .replace(
+                "<RecordStatus>current</RecordStatus>",
+                "<RecordStatus>current</RecordStatus><Title>A &amp; B <![CDATA[<C>]]></Title>",
+            );
+        let declaration = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#;
+        let input = format!(
+            r#"{declaration}
+<!-- A comment -->
+<ReleaseSet Dated="2024-01-01" Type="full" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:noNamespaceSchemaLocation="ClinVar_Public.xsd">
+{sets}</ReleaseSet>
+"#
+        );
+        let mut output = Vec::new();
+        let selection = Selection::new().with_skip(1).with_limit(3);
+        let written = write_subset(input.as_bytes(), &selection, &mut output).unwrap();
+        assert_eq!(written, 3);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with(declaration), "{output}");
+        let doc = roxmltree::Document::parse(&output).unwrap();
+        let root = doc.root_element();
+        assert_eq!(root.tag_name().name(), "ReleaseSet");
+        assert_eq!(root.attribute("Dated"), Some("2024-01-01"));
+        assert_eq!(root.attribute("Type"), Some("full"));
+        assert_eq!(
+            root.attribute((
+                "http://www.w3.org/2001/XMLSchema-instance",
+                "noNamespaceSchemaLocation"
+            )),
+            Some("ClinVar_Public.xsd")
+        );
+        let sets: Vec<roxmltree::Node> = root.children().filter(|n| n.is_element()).collect();
+        let ids: Vec<&str> = sets.iter().filter_map(|set| set.attribute("ID")).collect();
+        assert_eq!(ids, ["1", "2", "3"]);
+        // ClinVarSets are written as they are, entities and CDATA included
+        let title = sets[0]
+            .descendants()
+            .find(|n| n.has_tag_name("Title"))
+            .unwrap();
+        let text: String = title.children().filter_map(|n| n.text()).collect();
+        assert_eq!(text, "A & B <C>");
+
+        // Still a document when nothing is selected
+        let mut output = Vec::new();
+        let selection = Selection::new().with_skip(5);
+        assert_eq!(
+            write_subset(input.as_bytes(), &selection, &mut output).unwrap(),
+            0
+        );
+        let output = String::from_utf8(output).unwrap();
+        let doc = roxmltree::Document::parse(&output).unwrap();
+        assert_eq!(doc.root_element().attribute("Type"), Some("full"));
+        assert!(!doc.root_element().children().any(|n| n.is_element()));
+    }
+
+    #[test]
+    fn test_strata() {
+        // IDs 0 to 3 are pathogenic deletions, 4 to 9 benign, of which 8 and 9 are SNVs
+        let template = include_str!("../clinvarset_new.xml");
+        let sets: String = (0..10)
+            .map(|i| {
+                let mut set = template.replacen(r#"ID="92148146""#, &format!(r#"ID="{i}""#), 1);
+                if i >= 4 {
+                    set = set.replacen(">Pathogenic/Likely pathogenic<", ">Benign<", 1);
+                }
+                if i >= 8 {
+                    set = set.replacen(
+                        r#"Measure Type="Deletion""#,
+                        r#"Measure Type="single nucleotide variant""#,
+                        1,
+                    );
+                }
+                set + "\n"
+            })
+            .collect();
+        let input = format!("<ReleaseSet>\n{sets}</ReleaseSet>\n");
+
+        for seed in 0..20 {
+            let selection = Selection::new()
+                .with_strata(vec![Stratum::Clnsig])
+                .with_random_sample(3, seed);
+            let ids = selected_ids(&selection, &input);
+            assert_eq!(ids.iter().filter(|&&id| id < 4).count(), 3, "{ids:?}");
+            assert_eq!(ids.iter().filter(|&&id| id >= 4).count(), 3, "{ids:?}");
+            assert!(ids.windows(2).all(|w| w[0] < w[1]), "{ids:?}");
+
+            let selection = Selection::new()
+                .with_strata(vec![Stratum::Clnsig, Stratum::VariantType])
+                .with_random_sample(1, seed);
+            let ids = selected_ids(&selection, &input);
+            assert_eq!(ids.len(), 3, "{ids:?}");
+            assert!(
+                ids[0] < 4 && (4..8).contains(&ids[1]) && ids[2] >= 8,
+                "{ids:?}"
+            );
+        }
+    }
+}
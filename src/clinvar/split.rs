@@ -0,0 +1,388 @@
+//! Output split in one file per chromosome, gene or clinical significance.
+//!
+//! Each file (shard) has its own handler, made by a factory given to [`SplitHandler`], so that it gets its own
+//! header. Shards are first written uncompressed, to spill files in a temporary directory of the output
+//! directory. At most a given number of them are kept open: the least recently used one is closed when another
+//! needs to be opened, and appended to (without a new header) if it's needed again. Once done, every spill file
+//! is moved to its shard's file, or compressed to it in a single stream.
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::clinvar::record::ClinVarRecord;
+use crate::error::ClinvarXMLTabError;
+use crate::handler::{ClinVarSetEvent, EventHandler};
+use crate::utils;
+
+/// Key of the records without a value
+const NONE: &str = "none";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitBy {
+    /// Chromosome in the selected assembly
+    Chromosome,
+    /// Every gene of the record: a record in several genes is written in each of their files
+    Gene,
+    Clnsig,
+}
+
+impl SplitBy {
+    fn keys(&self, record: &ClinVarRecord) -> Vec<String> {
+        let keys: Vec<&str> = match self {
+            Self::Chromosome => record.chromosome().into_iter().collect(),
+            Self::Gene => record.genes().iter().map(String::as_str).collect(),
+            Self::Clnsig => record.clnsig().into_iter().collect(),
+        };
+        if keys.is_empty() {
+            return vec![NONE.to_string()];
+        }
+        keys.into_iter().map(file_name_safe).collect()
+    }
+}
+
+/// `key`, with anything but letters, digits, `.`, `-` and `_` replaced by `_`
+fn file_name_safe(key: &str) -> String {
+    key.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Makes the handler of a shard, given the writer of its spill file and whether it is appended to
+pub type ShardFactory = Box<
+    dyn FnMut(Box<dyn Write + Send>, bool) -> Result<Box<dyn EventHandler>, ClinvarXMLTabError>,
+>;
+
+struct Shard {
+    handler: Box<dyn EventHandler>,
+    last_used: u64,
+}
+
+pub struct SplitHandler {
+    split_by: SplitBy,
+    assembly: &'static str,
+    output_dir: PathBuf,
+    template: String,
+    /// Where the shards are written until they are done
+    spill_dir: PathBuf,
+    new_shard: ShardFactory,
+    max_open: usize,
+    compression_level: Option<u32>,
+    open: HashMap<String, Shard>,
+    /// Shards whose file was created, open or not
+    created: HashSet<String>,
+    /// Shards of the current record
+    current: Vec<String>,
+    records: u64,
+    /// What the shards that were closed wrote
+    closed_written: Option<u64>,
+    closed_counters: Vec<(&'static str, u64)>,
+}
+
+impl SplitHandler {
+    /// Shards are written in `output_dir`, named after `template`, where `{key}` is replaced by the chromosome,
+    /// gene or CLNSIG of the shard, and compressed according to their extension
+    pub fn new(
+        split_by: SplitBy,
+        assembly: &'static str,
+        output_dir: &Path,
+        template: &str,
+        new_shard: ShardFactory,
+    ) -> Result<Self, ClinvarXMLTabError> {
+        if !template.contains("{key}") {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("the output template `{template}` has no {{key}}"),
+            )
+            .into());
+        }
+        let spill_dir = output_dir.join(format!(".split-{}", std::process::id()));
+        std::fs::create_dir_all(&spill_dir)?;
+        Ok(Self {
+            split_by,
+            assembly,
+            output_dir: output_dir.to_path_buf(),
+            template: template.to_string(),
+            spill_dir,
+            new_shard,
+            max_open: 64,
+            compression_level: None,
+            open: HashMap::new(),
+            created: HashSet::new(),
+            current: Vec::new(),
+            records: 0,
+            closed_written: None,
+            closed_counters: Vec::new(),
+        })
+    }
+
+    /// Keeps at most `max_open` shards open (at least one)
+    pub fn with_max_open(mut self, max_open: usize) -> Self {
+        self.max_open = max_open.max(1);
+        self
+    }
+
+    /// Compression level of the shards (ignored when they are not compressed)
+    pub fn with_compression_level(mut self, level: Option<u32>) -> Self {
+        self.compression_level = level;
+        self
+    }
+
+    /// Closes the least recently used shard that is not written to by the current record
+    fn close_one(&mut self) -> Result<(), ClinvarXMLTabError> {
+        let Some(key) = self
+            .open
+            .iter()
+            .filter(|(key, _)| !self.current.contains(key))
+            .min_by_key(|(_, shard)| shard.last_used)
+            .map(|(key, _)| key.clone())
+        else {
+            return Ok(());
+        };
+        if let Some(mut shard) = self.open.remove(&key) {
+            shard.handler.finish()?;
+            shard.handler.flush()?;
+            if let Some(written) = shard.handler.records_written() {
+                *self.closed_written.get_or_insert(0) += written;
+            }
+            self.closed_counters.extend(shard.handler.counters());
+        }
+        Ok(())
+    }
+
+    fn open_shard(&mut self, key: &str) -> Result<(), ClinvarXMLTabError> {
+        let tick = self.records;
+        if let Some(shard) = self.open.get_mut(key) {
+            shard.last_used = tick;
+            return Ok(());
+        }
+        if self.open.len() >= self.max_open {
+            self.close_one()?;
+        }
+        // Spill files are uncompressed, so that they can be appended to as a single stream
+        let append = !self.created.insert(key.to_string());
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.spill_dir.join(key))?;
+        let writer = Box::new(BufWriter::with_capacity(128 * 1024, file));
+        let handler = (self.new_shard)(writer, append)?;
+        self.open.insert(
+            key.to_string(),
+            Shard {
+                handler,
+                last_used: tick,
+            },
+        );
+        Ok(())
+    }
+}
+
+impl EventHandler for SplitHandler {
+    fn handle_clinvar_set(&mut self, event: &ClinVarSetEvent) -> Result<(), ClinvarXMLTabError> {
+        let record = ClinVarRecord::from_clinvar_set(event.set()?, Some(self.assembly));
+        self.current = self.split_by.keys(&record);
+        for key in self.current.clone() {
+            self.open_shard(&key)?;
+        }
+        for key in &self.current {
+            if let Some(shard) = self.open.get_mut(key) {
+                shard.handler.handle_clinvar_set(event)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn end_record(&mut self) -> Result<(), ClinvarXMLTabError> {
+        for key in &self.current {
+            if let Some(shard) = self.open.get_mut(key) {
+                shard.handler.end_record()?;
+            }
+        }
+        self.records += 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), ClinvarXMLTabError> {
+        for shard in self.open.values_mut() {
+            shard.handler.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Closes the shards, then moves (or compresses) their spill files to their own
+    fn finish(&mut self) -> Result<(), ClinvarXMLTabError> {
+        self.current.clear();
+        while !self.open.is_empty() {
+            self.close_one()?;
+        }
+        let mut keys: Vec<&String> = self.created.iter().collect();
+        keys.sort_unstable();
+        for key in keys {
+            let spill = self.spill_dir.join(key);
+            let path = self.output_dir.join(self.template.replace("{key}", key));
+            if utils::is_compressed_output(&path) {
+                let mut writer =
+                    utils::file_writer_with_level(Some(&path), self.compression_level)?;
+                std::io::copy(&mut BufReader::new(File::open(&spill)?), &mut writer)?;
+                writer.flush()?;
+                std::fs::remove_file(&spill)?;
+            } else {
+                std::fs::rename(&spill, &path)?;
+            }
+        }
+        std::fs::remove_dir(&self.spill_dir)?;
+        Ok(())
+    }
+
+    /// What the shards wrote, adding up records written in several of them
+    fn records_written(&self) -> Option<u64> {
+        self.open
+            .values()
+            .filter_map(|shard| shard.handler.records_written())
+            .chain(self.closed_written)
+            .reduce(|a, b| a + b)
+            .or(Some(self.records))
+    }
+
+    fn counters(&self) -> Vec<(&'static str, u64)> {
+        let mut counters: Vec<(&'static str, u64)> = Vec::new();
+        let all = self
+            .open
+            .values()
+            .flat_map(|shard| shard.handler.counters())
+            .chain(self.closed_counters.iter().copied());
+        for (name, count) in all {
+            match counters.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, total)) => *total += count,
+                None => counters.push((name, count)),
+            }
+        }
+        counters.push(("shards", self.created.len() as u64));
+        counters
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clinvar::model::ClinVarSet;
+    use crate::clinvar::record::CSVRecordHandler;
+    use std::io::Read;
+
+    const XML: &str = include_str!("../clinvarset_new.xml");
+
+    fn record(xml: &str) -> ClinVarRecord {
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        let set = ClinVarSet::from_node(&doc.root_element()).unwrap();
+        ClinVarRecord::from_clinvar_set(&set, Some("GRCh38"))
+    }
+
+    #[test]
+    fn test_keys() {
+        let record = record(XML);
+        assert_eq!(SplitBy::Chromosome.keys(&record), ["chr10"]);
+        assert_eq!(SplitBy::Gene.keys(&record), ["OAT"]);
+        assert_eq!(
+            SplitBy::Clnsig.keys(&record),
+            ["pathogenic_likely_pathogenic"]
+        );
+        let unplaced = self::record(&XML.replace(r#"Assembly="GRCh38""#, r#"Assembly="GRCh99""#));
+        assert_eq!(SplitBy::Chromosome.keys(&unplaced), [NONE]);
+        assert_eq!(
+            file_name_safe("Uncertain significance/../x"),
+            "Uncertain_significance_.._x"
+        );
+    }
+
+    /// ClinVarSets with IDs 0 to 9, on chromosomes 1, 2, 3, 1, 2, 3...
+    fn split(name: &str, template: &str, max_open: usize) -> (PathBuf, SplitHandler) {
+        let output_dir =
+            std::env::temp_dir().join(format!("clinvar_split_{name}_{}", std::process::id()));
+        let new_shard: ShardFactory = Box::new(|writer, append| {
+            Ok(Box::new(CSVRecordHandler::new_tsv_from_writer(
+                writer, "GRCh38", !append,
+            )))
+        });
+        let mut handler = SplitHandler::new(
+            SplitBy::Chromosome,
+            "GRCh38",
+            &output_dir,
+            template,
+            new_shard,
+        )
+        .unwrap()
+        .with_max_open(max_open);
+        for i in 0..10 {
+            let xml = XML
+                .replacen(r#"ID="92148146""#, &format!(r#"ID="{i}""#), 1)
+                .replace(r#"Chr="10""#, &format!(r#"Chr="{}""#, i % 3 + 1));
+            let doc = roxmltree::Document::parse(&xml).unwrap();
+            handler
+                .handle_clinvar_set(&ClinVarSetEvent::new(doc.root_element()))
+                .unwrap();
+            handler.end_record().unwrap();
+        }
+        handler.finish().unwrap();
+        (output_dir, handler)
+    }
+
+    /// Header and CLINVAR_ID column of a shard
+    fn read_shard(tsv: &str) -> (usize, Vec<String>) {
+        let mut lines = tsv.lines();
+        let header: Vec<&str> = lines.next().unwrap().split('\t').collect();
+        let column = header.iter().position(|c| *c == "CLINVAR_ID").unwrap();
+        let ids: Vec<String> = lines
+            .map(|line| line.split('\t').nth(column).unwrap().to_string())
+            .collect();
+        let headers = tsv.lines().filter(|l| l.contains("CLINVAR_ID")).count();
+        (headers, ids)
+    }
+
+    #[test]
+    fn test_split() {
+        // With a single file open at a time, every record closes a shard and reopens another
+        for max_open in [1, 64] {
+            let (output_dir, handler) =
+                split(&format!("max_open_{max_open}"), "{key}.tsv", max_open);
+            for (key, ids) in [
+                ("1", ["0", "3", "6", "9"].as_slice()),
+                ("2", &["1", "4", "7"]),
+            ] {
+                let tsv =
+                    std::fs::read_to_string(output_dir.join(format!("chr{key}.tsv"))).unwrap();
+                assert_eq!(
+                    read_shard(&tsv),
+                    (1, ids.iter().map(|s| s.to_string()).collect())
+                );
+            }
+            assert_eq!(handler.records_written(), Some(10));
+            assert!(handler.counters().contains(&("shards", 3)));
+            // Nothing is left but the shards
+            assert_eq!(std::fs::read_dir(&output_dir).unwrap().count(), 3);
+            std::fs::remove_dir_all(&output_dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_split_compressed() {
+        let (output_dir, _) = split("compressed", "{key}.tsv.gz", 1);
+        let gz = std::fs::read(output_dir.join("chr1.tsv.gz")).unwrap();
+        // A single gzip stream, with a single header
+        let mut decoder = flate2::read::GzDecoder::new(gz.as_slice());
+        let mut tsv = String::new();
+        decoder.read_to_string(&mut tsv).unwrap();
+        assert!(decoder.into_inner().is_empty());
+        assert_eq!(
+            read_shard(&tsv),
+            (1, vec!["0".into(), "3".into(), "6".into(), "9".into()])
+        );
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+}
@@ -0,0 +1,437 @@
+//! SQLite export, with one table per kind of entity instead of a single flat record.
+//!
+//! Variants are keyed by their VariationID (the `MeasureSet` ID), and everything else refers to them
+//! and/or to the RCV accession they were found in.
+use std::path::Path;
+
+use rusqlite::{params, Connection};
+
+use crate::clinvar::model::{ClinVarSet, MeasureSet};
+use crate::clinvar::record::ClinVarRecord;
+use crate::error::ClinvarXMLTabError;
+use crate::xml::handler::{ClinVarSetEvent, EventHandler};
+
+/// Number of ClinVarSets inserted per transaction
+const BATCH_SIZE: u64 = 10_000;
+
+const SCHEMA: &str = "
+CREATE TABLE variants (
+    variation_id INTEGER PRIMARY KEY,
+    vcv TEXT,
+    name TEXT,
+    variant_type TEXT,
+    rsid TEXT,
+    chrom TEXT,
+    pos INTEGER,
+    ref TEXT,
+    alt TEXT
+);
+CREATE TABLE rcv (
+    rcv TEXT PRIMARY KEY,
+    variation_id INTEGER REFERENCES variants(variation_id),
+    clinvar_set_id INTEGER,
+    record_status TEXT,
+    title TEXT,
+    clnsig TEXT,
+    review_status TEXT,
+    date_last_updated TEXT
+);
+CREATE TABLE submissions (
+    scv TEXT NOT NULL,
+    rcv TEXT NOT NULL REFERENCES rcv(rcv),
+    variation_id INTEGER REFERENCES variants(variation_id),
+    submitter TEXT,
+    clnsig TEXT,
+    review_status TEXT,
+    date_last_evaluated TEXT,
+    PRIMARY KEY (scv, rcv)
+);
+CREATE TABLE conditions (
+    rcv TEXT NOT NULL REFERENCES rcv(rcv),
+    variation_id INTEGER REFERENCES variants(variation_id),
+    name TEXT,
+    medgen TEXT,
+    UNIQUE (rcv, name)
+);
+CREATE TABLE genes (
+    variation_id INTEGER NOT NULL REFERENCES variants(variation_id),
+    symbol TEXT NOT NULL,
+    gene_id TEXT,
+    PRIMARY KEY (variation_id, symbol)
+);
+CREATE TABLE citations (
+    rcv TEXT NOT NULL REFERENCES rcv(rcv),
+    variation_id INTEGER REFERENCES variants(variation_id),
+    source TEXT NOT NULL,
+    citation_id TEXT NOT NULL,
+    UNIQUE (rcv, source, citation_id)
+);
+";
+
+/// Created once everything is loaded, which is much faster than maintaining them during the inserts
+const INDICES: &str = "
+CREATE INDEX variants_chrom_pos ON variants (chrom, pos);
+CREATE INDEX variants_vcv ON variants (vcv);
+CREATE INDEX rcv_variation_id ON rcv (variation_id);
+CREATE INDEX submissions_variation_id ON submissions (variation_id);
+CREATE INDEX conditions_variation_id ON conditions (variation_id);
+CREATE INDEX genes_symbol ON genes (symbol);
+CREATE INDEX citations_variation_id ON citations (variation_id);
+";
+
+pub struct SqliteHandler {
+    connection: Connection,
+    assembly: &'static str,
+    /// ClinVarSets inserted in the current transaction
+    pending: u64,
+}
+
+impl SqliteHandler {
+    /// Creates a new database at `path`. The tables are created from scratch, so an existing file is only
+    /// replaced with `overwrite`.
+    pub fn new<P: AsRef<Path>>(
+        path: P,
+        assembly: &'static str,
+        overwrite: bool,
+    ) -> Result<Self, ClinvarXMLTabError> {
+        let path = path.as_ref();
+        if path.exists() {
+            if !overwrite {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AlreadyExists,
+                    format!(
+                        "{} already exists, use --overwrite to replace it",
+                        path.display()
+                    ),
+                )
+                .into());
+            }
+            std::fs::remove_file(path)?;
+        }
+        Self::from_connection(Connection::open(path)?, assembly)
+    }
+
+    /// Creates the tables in an empty database
+    fn from_connection(
+        connection: Connection,
+        assembly: &'static str,
+    ) -> Result<Self, ClinvarXMLTabError> {
+        // We can always start the export over, so durability is not worth its cost
+        connection.execute_batch("PRAGMA journal_mode = OFF; PRAGMA synchronous = OFF;")?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self {
+            connection,
+            assembly,
+            pending: 0,
+        })
+    }
+
+    fn insert_variant(
+        &self,
+        variation_id: u64,
+        measure_set: &MeasureSet,
+        record: &ClinVarRecord,
+    ) -> Result<(), ClinvarXMLTabError> {
+        let name = measure_set
+            .names
+            .iter()
+            .find(|n| n.element_value.value_type == "Preferred")
+            .map(|n| n.element_value.value.as_str());
+        let variant_type = measure_set
+            .measures
+            .first()
+            .map(|m| m.measure_type.as_str());
+        let rsid = measure_set
+            .measures
+            .iter()
+            .flat_map(|m| m.xrefs.iter())
+            .find(|x| x.db == "dbSNP")
+            .map(|x| format!("rs{}", x.id));
+        self.connection
+            .prepare_cached("INSERT OR IGNORE INTO variants VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)")?
+            .execute(params![
+                variation_id,
+                measure_set.acc,
+                name,
+                variant_type,
+                rsid,
+                record.chromosome(),
+                record.position(),
+                record.reference(),
+                record.alternate(),
+            ])?;
+        for measure in &measure_set.measures {
+            for relationship in &measure.measure_relationships {
+                let gene_id = relationship
+                    .xrefs
+                    .iter()
+                    .find(|x| x.db == "Gene")
+                    .map(|x| x.id.as_str());
+                for symbol in relationship
+                    .symbols
+                    .iter()
+                    .filter(|s| s.element_value.value_type == "Preferred")
+                {
+                    self.connection
+                        .prepare_cached("INSERT OR IGNORE INTO genes VALUES (?, ?, ?)")?
+                        .execute(params![variation_id, symbol.element_value.value, gene_id])?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn insert_set(&self, set: &ClinVarSet) -> Result<(), ClinvarXMLTabError> {
+        let rca = &set.reference_clinvar_assertion;
+        let rcv = &rca.clinvar_accession.acc;
+        let record = ClinVarRecord::from_clinvar_set(set, Some(self.assembly));
+        let variation_id = rca.measure_set.as_ref().and_then(|m| m.id);
+
+        // Without a VariationID, there is nothing to key the variant on: the other tables just leave it NULL
+        if let (Some(measure_set), Some(variation_id)) = (&rca.measure_set, variation_id) {
+            self.insert_variant(variation_id, measure_set, &record)?;
+        }
+
+        self.connection
+            .prepare_cached("INSERT OR REPLACE INTO rcv VALUES (?, ?, ?, ?, ?, ?, ?, ?)")?
+            .execute(params![
+                rcv,
+                variation_id,
+                set.id,
+                set.record_status,
+                set.title,
+                record.clnsig(),
+                record.review_status(),
+                record.date_last_updated(),
+            ])?;
+
+        for assertion in &set.clinvar_assertions {
+            let classification = assertion.classification.as_ref();
+            self.connection
+                .prepare_cached("INSERT OR IGNORE INTO submissions VALUES (?, ?, ?, ?, ?, ?, ?)")?
+                .execute(params![
+                    assertion.clinvar_accession.acc,
+                    rcv,
+                    variation_id,
+                    assertion.submission_id.submitter,
+                    classification.and_then(|c| c.germline.as_deref()),
+                    classification.and_then(|c| c.review_status.as_deref()),
+                    classification.and_then(|c| c.date_last_evaluated.as_deref()),
+                ])?;
+        }
+
+        for condition in rca.trait_set.iter().flat_map(|t| t.traits.iter()) {
+            let name = condition
+                .names
+                .iter()
+                .find(|n| n.element_value.value_type == "Preferred")
+                .map(|n| n.element_value.value.as_str());
+            let medgen = condition
+                .xrefs
+                .iter()
+                .find(|x| x.db == "MedGen")
+                .map(|x| x.id.as_str());
+            self.connection
+                .prepare_cached("INSERT OR IGNORE INTO conditions VALUES (?, ?, ?, ?)")?
+                .execute(params![rcv, variation_id, name, medgen])?;
+        }
+
+        // Citations can be found at several levels of the reference assertion
+        let citations = rca
+            .citations
+            .iter()
+            .chain(
+                rca.measure_set
+                    .iter()
+                    .flat_map(|m| m.measures.iter())
+                    .flat_map(|m| m.citations.iter()),
+            )
+            .chain(
+                rca.observed_in
+                    .iter()
+                    .flat_map(|o| o.observed_data.iter())
+                    .flat_map(|d| d.citations.iter()),
+            );
+        for id in citations.flat_map(|c| c.ids.iter()) {
+            self.connection
+                .prepare_cached("INSERT OR IGNORE INTO citations VALUES (?, ?, ?, ?)")?
+                .execute(params![rcv, variation_id, id.source, id.value])?;
+        }
+        Ok(())
+    }
+}
+
+impl EventHandler for SqliteHandler {
+    fn handle_clinvar_set(&mut self, event: &ClinVarSetEvent) -> Result<(), ClinvarXMLTabError> {
+        if self.pending == 0 {
+            self.connection.execute_batch("BEGIN")?;
+        }
+        self.insert_set(event.set()?)?;
+        self.pending += 1;
+        Ok(())
+    }
+
+    fn end_record(&mut self) -> Result<(), ClinvarXMLTabError> {
+        if self.pending >= BATCH_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Commits the current transaction
+    fn flush(&mut self) -> Result<(), ClinvarXMLTabError> {
+        if self.pending > 0 {
+            self.connection.execute_batch("COMMIT")?;
+            self.pending = 0;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), ClinvarXMLTabError> {
+        self.flush()?;
+        self.connection.execute_batch(INDICES)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const XML: &str = include_str!("../clinvarset_new.xml");
+
+    fn load(handler: &mut SqliteHandler, xml: &str) {
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        handler
+            .handle_clinvar_set(&ClinVarSetEvent::new(doc.root_element()))
+            .unwrap();
+        handler.end_record().unwrap();
+    }
+
+    fn count(connection: &Connection, table: &str) -> u64 {
+        connection
+            .query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_sqlite() {
+        let mut handler =
+            SqliteHandler::from_connection(Connection::open_in_memory().unwrap(), "GRCh38")
+                .unwrap();
+        load(&mut handler, XML);
+        handler.finish().unwrap();
+        let connection = &handler.connection;
+
+        for (table, rows) in [
+            ("variants", 1),
+            ("rcv", 1),
+            ("submissions", 2),
+            ("conditions", 1),
+            ("genes", 1),
+            ("citations", 1),
+        ] {
+            assert_eq!(count(connection, table), rows, "{table}");
+        }
+        let variant: (u64, String, String, u64, String, String) = connection
+            .query_row(
+                "SELECT variation_id, vcv, chrom, pos, ref, alt FROM variants",
+                [],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                        row.get(5)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            variant,
+            (
+                165,
+                "VCV000000165".to_string(),
+                "chr10".to_string(),
+                124408632,
+                "CCACTCCTAT".to_string(),
+                "C".to_string()
+            )
+        );
+        let gene: String = connection
+            .query_row(
+                "SELECT symbol FROM genes WHERE variation_id = 165",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(gene, "OAT");
+
+        // Every row refers to the variant and RCV inserted
+        let violations: u64 = connection
+            .query_row("SELECT COUNT(*) FROM pragma_foreign_key_check", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(violations, 0);
+        for table in ["rcv", "submissions", "conditions", "citations"] {
+            let orphans: u64 = connection
+                .query_row(
+                    &format!(
+                        "SELECT COUNT(*) FROM {table} WHERE variation_id IS NULL \
+                         OR variation_id NOT IN (SELECT variation_id FROM variants)"
+                    ),
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(orphans, 0, "{table}");
+        }
+    }
+
+    #[test]
+    fn test_sqlite_without_variation_id() {
+        let mut handler =
+            SqliteHandler::from_connection(Connection::open_in_memory().unwrap(), "GRCh38")
+                .unwrap();
+        let xml = XML.replacen(
+            r#"<MeasureSet Type="Variant" ID="165""#,
+            r#"<MeasureSet Type="Variant""#,
+            1,
+        );
+        load(&mut handler, &xml);
+        handler.finish().unwrap();
+        let connection = &handler.connection;
+
+        assert_eq!(count(connection, "variants"), 0);
+        assert_eq!(count(connection, "genes"), 0);
+        assert_eq!(count(connection, "rcv"), 1);
+        assert_eq!(count(connection, "submissions"), 2);
+        let variation_id: Option<u64> = connection
+            .query_row("SELECT variation_id FROM rcv", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(variation_id, None);
+    }
+
+    #[test]
+    fn test_overwrite() {
+        let path = std::env::temp_dir().join(format!("sqlite-{}.db", std::process::id()));
+        std::fs::write(&path, "not a database").unwrap();
+
+        let error = SqliteHandler::new(&path, "GRCh38", false).err().unwrap();
+        assert!(error.to_string().contains("use --overwrite"), "{error}");
+        assert_eq!(std::fs::read(&path).unwrap(), b"not a database");
+
+        let mut handler = SqliteHandler::new(&path, "GRCh38", true).unwrap();
+        load(&mut handler, XML);
+        handler.finish().unwrap();
+        drop(handler);
+        let connection = Connection::open(&path).unwrap();
+        assert_eq!(count(&connection, "rcv"), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
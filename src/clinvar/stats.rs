@@ -0,0 +1,346 @@
+//! Release statistics: how many ClinVarSets there are by clinical significance, review status, variant type,
+//! chromosome, record status and submitter, which assemblies they have locations on, and how many lack VCF
+//! coordinates in each assembly.
+use std::collections::HashMap;
+
+use crate::clinvar::model::ClinVarSet;
+use crate::clinvar::record::ClinVarRecord;
+use crate::error::ClinvarXMLTabError;
+use crate::xml::handler::{ClinVarSetEvent, EventHandler};
+
+/// Assemblies always reported, even when no record has a location on them
+const ASSEMBLIES: [&str; 2] = ["GRCh37", "GRCh38"];
+
+/// Key of the records without a value
+const NONE: &str = "(none)";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsFormat {
+    /// Aligned columns, one section per category
+    Table,
+    /// A single JSON object
+    Json,
+}
+
+/// Number of records for each value
+#[derive(Debug, Default)]
+struct Tally(HashMap<String, u64>);
+
+impl Tally {
+    fn add(&mut self, value: Option<&str>) {
+        *self.0.entry(value.unwrap_or(NONE).to_string()).or_default() += 1;
+    }
+
+    /// Values by decreasing count (then by name), at most `top` of them
+    fn sorted(&self, top: Option<usize>) -> Vec<(&str, u64)> {
+        let mut counts: Vec<(&str, u64)> = self.0.iter().map(|(k, v)| (k.as_str(), *v)).collect();
+        counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        counts.truncate(top.unwrap_or(usize::MAX));
+        counts
+    }
+}
+
+pub struct StatsHandler<W: std::io::Write> {
+    writer: W,
+    format: StatsFormat,
+    assembly: &'static str,
+    top: Option<usize>,
+    records: u64,
+    clnsig: Tally,
+    review_status: Tally,
+    variant_type: Tally,
+    chromosome: Tally,
+    record_status: Tally,
+    /// Submissions (SCVs) of each submitter
+    submitter: Tally,
+    /// Records with at least one location on each assembly
+    assembly_coverage: Tally,
+    /// Records with VCF coordinates on each assembly
+    with_vcf_coordinates: Tally,
+}
+
+impl<W: std::io::Write> StatsHandler<W> {
+    /// Chromosomes are those of `assembly`. Only the `top` most frequent values of each category are written.
+    pub fn new_from_writer(
+        writer: W,
+        format: StatsFormat,
+        assembly: &'static str,
+        top: Option<usize>,
+    ) -> Self {
+        Self {
+            writer,
+            format,
+            assembly,
+            top,
+            records: 0,
+            clnsig: Tally::default(),
+            review_status: Tally::default(),
+            variant_type: Tally::default(),
+            chromosome: Tally::default(),
+            record_status: Tally::default(),
+            submitter: Tally::default(),
+            assembly_coverage: Tally::default(),
+            with_vcf_coordinates: Tally::default(),
+        }
+    }
+
+    fn add(&mut self, set: &ClinVarSet) {
+        let record = ClinVarRecord::from_clinvar_set(set, Some(self.assembly));
+        self.records += 1;
+        self.clnsig.add(record.clnsig());
+        self.review_status.add(record.review_status());
+        self.chromosome.add(record.chromosome());
+        self.record_status.add(record.status());
+        let measure_set = set.reference_clinvar_assertion.measure_set.as_ref();
+        self.variant_type.add(
+            measure_set
+                .and_then(|m| m.measures.first())
+                .map(|m| m.measure_type.as_str()),
+        );
+        for assertion in &set.clinvar_assertions {
+            self.submitter
+                .add(assertion.submission_id.submitter.as_deref());
+        }
+        let locations: Vec<_> = measure_set
+            .iter()
+            .flat_map(|m| m.measures.iter())
+            .flat_map(|m| m.sequence_locations.iter())
+            .collect();
+        let mut assemblies: Vec<&str> = locations.iter().map(|l| l.assembly.as_str()).collect();
+        assemblies.sort_unstable();
+        assemblies.dedup();
+        for assembly in assemblies {
+            self.assembly_coverage.add(Some(assembly));
+            if locations.iter().any(|l| {
+                l.assembly == assembly
+                    && l.position_vcf.is_some()
+                    && l.reference_allele_vcf.is_some()
+                    && l.alternate_allele_vcf.is_some()
+            }) {
+                self.with_vcf_coordinates.add(Some(assembly));
+            }
+        }
+    }
+
+    /// Records without VCF coordinates, for every assembly seen (and GRCh37 and GRCh38)
+    fn lacking_vcf_coordinates(&self) -> Tally {
+        let mut assemblies: Vec<&str> = self
+            .assembly_coverage
+            .0
+            .keys()
+            .map(String::as_str)
+            .collect();
+        assemblies.extend(ASSEMBLIES);
+        Tally(
+            assemblies
+                .into_iter()
+                .map(|assembly| {
+                    let with = self.with_vcf_coordinates.0.get(assembly).copied();
+                    (assembly.to_string(), self.records - with.unwrap_or(0))
+                })
+                .collect(),
+        )
+    }
+
+    fn categories<'a>(
+        &'a self,
+        lacking_vcf_coordinates: &'a Tally,
+    ) -> [(&'static str, &'a Tally); 8] {
+        [
+            ("clnsig", &self.clnsig),
+            ("review_status", &self.review_status),
+            ("variant_type", &self.variant_type),
+            ("chromosome", &self.chromosome),
+            ("record_status", &self.record_status),
+            ("assembly_coverage", &self.assembly_coverage),
+            ("lacking_vcf_coordinates", lacking_vcf_coordinates),
+            ("submitter", &self.submitter),
+        ]
+    }
+
+    fn json(&self) -> serde_json::Value {
+        let lacking_vcf_coordinates = self.lacking_vcf_coordinates();
+        let mut object = serde_json::Map::new();
+        object.insert("records".to_string(), self.records.into());
+        for (name, tally) in self.categories(&lacking_vcf_coordinates) {
+            let counts: serde_json::Map<String, serde_json::Value> = tally
+                .sorted(self.top)
+                .into_iter()
+                .map(|(value, count)| (value.to_string(), count.into()))
+                .collect();
+            object.insert(name.to_string(), counts.into());
+        }
+        object.into()
+    }
+
+    fn table(&self) -> String {
+        use std::fmt::Write;
+
+        let lacking_vcf_coordinates = self.lacking_vcf_coordinates();
+        let mut table = format!("records: {}\n", self.records);
+        // Submitters are counted by submission, not by record
+        let submissions: u64 = self.submitter.0.values().sum();
+        for (name, tally) in self.categories(&lacking_vcf_coordinates) {
+            let counts = tally.sorted(self.top);
+            let total = if name == "submitter" {
+                submissions
+            } else {
+                self.records
+            };
+            let width = counts
+                .iter()
+                .map(|(value, _)| value.chars().count())
+                .chain([name.len()])
+                .max()
+                .unwrap_or(0);
+            let _ = writeln!(table, "\n{:<width$}  {:>10}  {:>7}", name, "count", "%");
+            for (value, count) in counts {
+                let percent = if total == 0 {
+                    0.0
+                } else {
+                    100.0 * count as f64 / total as f64
+                };
+                let _ = writeln!(table, "{value:<width$}  {count:>10}  {percent:>6.2}%");
+            }
+        }
+        table
+    }
+}
+
+impl<W: std::io::Write> EventHandler for StatsHandler<W> {
+    fn handle_clinvar_set(&mut self, event: &ClinVarSetEvent) -> Result<(), ClinvarXMLTabError> {
+        self.add(event.set()?);
+        Ok(())
+    }
+
+    fn end_record(&mut self) -> Result<(), ClinvarXMLTabError> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), ClinvarXMLTabError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the statistics, once every record is counted
+    fn finish(&mut self) -> Result<(), ClinvarXMLTabError> {
+        match self.format {
+            StatsFormat::Table => {
+                let table = self.table();
+                self.writer.write_all(table.as_bytes())?;
+            }
+            StatsFormat::Json => {
+                let json = self.json();
+                serde_json::to_writer_pretty(&mut self.writer, &json)?;
+                self.writer.write_all(b"\n")?;
+            }
+        }
+        self.flush()
+    }
+
+    fn records_written(&self) -> Option<u64> {
+        Some(self.records)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const XML: &str = include_str!("../clinvarset_new.xml");
+
+    fn add(handler: &mut StatsHandler<Vec<u8>>, xml: &str) {
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        handler
+            .handle_clinvar_set(&ClinVarSetEvent::new(doc.root_element()))
+            .unwrap();
+        handler.end_record().unwrap();
+    }
+
+    #[test]
+    fn test_sorted() {
+        let mut tally = Tally::default();
+        for value in ["b", "c", "a", "c", "b", "d"] {
+            tally.add(Some(value));
+        }
+        tally.add(None);
+        // Ties are sorted by name
+        assert_eq!(
+            tally.sorted(None),
+            [("b", 2), ("c", 2), (NONE, 1), ("a", 1), ("d", 1)]
+        );
+        assert_eq!(tally.sorted(Some(3)), [("b", 2), ("c", 2), (NONE, 1)]);
+        assert!(tally.sorted(Some(0)).is_empty());
+        assert_eq!(tally.sorted(Some(10)).len(), 5);
+    }
+
+    #[test]
+    fn test_lacking_vcf_coordinates() {
+        let mut handler =
+            StatsHandler::new_from_writer(Vec::new(), StatsFormat::Json, "GRCh38", None);
+        let lacking = handler.lacking_vcf_coordinates();
+        assert_eq!(lacking.sorted(None), [("GRCh37", 0), ("GRCh38", 0)]);
+
+        add(&mut handler, XML);
+        // A location on GRCh37, but without VCF coordinates
+        add(
+            &mut handler,
+            &XML.replace(r#" positionVCF="126097201""#, ""),
+        );
+        // No location on GRCh38 at all
+        add(
+            &mut handler,
+            &XML.replace(r#"Assembly="GRCh38""#, r#"Assembly="NCBI36""#),
+        );
+        // Every record lacks them on an assembly it has no location on
+        let lacking = handler.lacking_vcf_coordinates();
+        assert_eq!(
+            lacking.sorted(None),
+            [("NCBI36", 2), ("GRCh37", 1), ("GRCh38", 1)]
+        );
+        assert_eq!(
+            handler.assembly_coverage.sorted(None),
+            [("GRCh37", 3), ("GRCh38", 2), ("NCBI36", 1)]
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let mut handler =
+            StatsHandler::new_from_writer(Vec::new(), StatsFormat::Json, "GRCh38", None);
+        add(&mut handler, XML);
+        add(&mut handler, XML);
+        handler.finish().unwrap();
+        assert_eq!(handler.records_written(), Some(2));
+        let json: serde_json::Value = serde_json::from_slice(&handler.writer).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "records": 2,
+                "clnsig": {"pathogenic/likely_pathogenic": 2},
+                "review_status": {"no assertion criteria provided": 2},
+                "variant_type": {"Deletion": 2},
+                "chromosome": {"chr10": 2},
+                "record_status": {"current": 2},
+                "assembly_coverage": {"GRCh37": 2, "GRCh38": 2},
+                "lacking_vcf_coordinates": {"GRCh37": 0, "GRCh38": 0},
+                "submitter": {
+                    "OMIM": 2,
+                    "Juha Muilu Group; Institute for Molecular Medicine Finland (FIMM)": 2
+                },
+            })
+        );
+
+        // Only the most frequent values of each category
+        let mut handler =
+            StatsHandler::new_from_writer(Vec::new(), StatsFormat::Json, "GRCh38", Some(1));
+        add(&mut handler, XML);
+        handler.finish().unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&handler.writer).unwrap();
+        assert_eq!(
+            json["submitter"],
+            serde_json::json!({"Juha Muilu Group; Institute for Molecular Medicine Finland (FIMM)": 1})
+        );
+        assert_eq!(json["assembly_coverage"], serde_json::json!({"GRCh37": 1}));
+    }
+}
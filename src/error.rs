@@ -19,9 +19,48 @@ pub enum ClinvarXMLTabError {
     #[error(transparent)]
     CSVError(#[from] csv::Error),
 
-    // Quickxml is not used for serde anymore. Roxml is much easier to work with
-    // #[error(transparent)]
-    // SerdeError(#[from] quick_xml::DeError),
+    // Roxml is used to walk the tree, and quick-xml's serde to deserialize the typed model
+    #[error(transparent)]
+    SerdeError(#[from] quick_xml::DeError),
+
+    #[error(transparent)]
+    SerializeError(#[from] quick_xml::SeError),
+
     #[error(transparent)]
     RoxmlError(#[from] roxmltree::Error),
+
+    #[error(transparent)]
+    ArrowError(#[from] arrow_schema::ArrowError),
+
+    #[error(transparent)]
+    ParquetError(#[from] parquet::errors::ParquetError),
+
+    #[error(transparent)]
+    SqliteError(#[from] rusqlite::Error),
+
+    #[error("Invalid field: {0}")]
+    FieldSpecError(String),
+
+    #[error("Invalid filter: {0}")]
+    FilterError(String),
+
+    #[error("Cannot resume: {0}")]
+    ResumeError(String),
+}
+
+impl ClinvarXMLTabError {
+    /// Whether this error comes from writing to a closed pipe
+    pub fn is_broken_pipe(&self) -> bool {
+        match self {
+            ClinvarXMLTabError::IOError(e) => e.kind() == io::ErrorKind::BrokenPipe,
+            ClinvarXMLTabError::CSVError(e) => match e.kind() {
+                csv::ErrorKind::Io(io_err) => io_err.kind() == io::ErrorKind::BrokenPipe,
+                _ => false,
+            },
+            ClinvarXMLTabError::SerdeJsonError(e) => {
+                e.io_error_kind() == Some(io::ErrorKind::BrokenPipe)
+            }
+            _ => false,
+        }
+    }
 }
@@ -1,7 +1,590 @@
+//! Generic conversion of XML elements to JSON, without loss.
+//!
+//! An element becomes an object with its namespace declarations (`@xmlns:p`) and attributes (`@Name` by
+//! default, prefixed names as `@p:Name`), then its child elements by name, in document order. Children whose
+//! name repeats become arrays. Text is kept as is, under `#text` by default, or as the element's value when it
+//! has neither attributes nor children (`null` if it has no text). Mixed content, text between child elements,
+//! is kept in order as a `#content` list of strings and one-key objects. Only whitespace between child
+//! elements (indentation), comments and processing instructions are left out. [`JsonRules::to_xml`] writes
+//! the element back.
+//!
+//! [`flatten`] then turns such a value into a single level object with dotted keys (`Measure.0.@Type`),
+//! e.g. to load it in a data frame.
+use serde::{Serialize, Serializer};
+
+use crate::error::ClinvarXMLTabError;
+use crate::handler::{ClinVarSetEvent, EventHandler};
+
+/// A JSON value whose objects keep their keys in document order (serde_json's `Map` sorts them)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum XmlJson {
+    #[default]
+    Null,
+    String(String),
+    Array(Vec<XmlJson>),
+    Object(Vec<(String, XmlJson)>),
+}
+
+impl XmlJson {
+    /// Value of `key`, if this is an object that has it
+    pub fn get(&self, key: &str) -> Option<&XmlJson> {
+        match self {
+            Self::Object(object) => object.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl Serialize for XmlJson {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::{SerializeMap, SerializeSeq};
+        match self {
+            Self::Null => serializer.serialize_unit(),
+            Self::String(value) => serializer.serialize_str(value),
+            Self::Array(values) => {
+                let mut seq = serializer.serialize_seq(Some(values.len()))?;
+                for value in values {
+                    seq.serialize_element(value)?;
+                }
+                seq.end()
+            }
+            Self::Object(object) => {
+                let mut map = serializer.serialize_map(Some(object.len()))?;
+                for (key, value) in object {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+/// When child elements are put in arrays
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Arrays {
+    /// Only when there are several with the same name
+    #[default]
+    Repeated,
+    /// Always, so that the shape of the JSON doesn't depend on the record
+    Always,
+}
+
+/// How elements are converted
+#[derive(Debug, Clone)]
+pub struct JsonRules {
+    attribute_prefix: String,
+    text_key: String,
+    content_key: String,
+    arrays: Arrays,
+}
+
+impl Default for JsonRules {
+    fn default() -> Self {
+        Self {
+            attribute_prefix: "@".to_string(),
+            text_key: "#text".to_string(),
+            content_key: "#content".to_string(),
+            arrays: Arrays::default(),
+        }
+    }
+}
+
+impl JsonRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Prefix of the attribute keys, `@` by default (it must not be empty)
+    pub fn with_attribute_prefix(mut self, prefix: &str) -> Self {
+        self.attribute_prefix = prefix.to_string();
+        self
+    }
+
+    /// Key of the text of elements that also have attributes, `#text` by default
+    pub fn with_text_key(mut self, key: &str) -> Self {
+        self.text_key = key.to_string();
+        self
+    }
+
+    /// Key of the mixed content of elements, `#content` by default
+    pub fn with_content_key(mut self, key: &str) -> Self {
+        self.content_key = key.to_string();
+        self
+    }
+
+    pub fn with_arrays(mut self, arrays: Arrays) -> Self {
+        self.arrays = arrays;
+        self
+    }
+
+    /// JSON value of the element `node`
+    pub fn to_json(&self, node: &roxmltree::Node) -> XmlJson {
+        let mut object = Vec::new();
+        for (prefix, uri) in declared_namespaces(node) {
+            let name = match prefix {
+                Some(prefix) => format!("xmlns:{prefix}"),
+                None => "xmlns".to_string(),
+            };
+            object.push((
+                format!("{}{name}", self.attribute_prefix),
+                XmlJson::String(uri.to_string()),
+            ));
+        }
+        for attribute in node.attributes() {
+            object.push((
+                format!(
+                    "{}{}",
+                    self.attribute_prefix,
+                    attribute_name(node, &attribute)
+                ),
+                XmlJson::String(attribute.value().to_string()),
+            ));
+        }
+        let elements: Vec<roxmltree::Node> =
+            node.children().filter(|child| child.is_element()).collect();
+        let texts: Vec<&str> = node
+            .children()
+            .filter(|child| child.is_text())
+            .filter_map(|child| child.text())
+            .collect();
+
+        if elements.is_empty() {
+            let text = (!texts.is_empty()).then(|| XmlJson::String(texts.concat()));
+            if object.is_empty() {
+                return text.unwrap_or_default();
+            }
+            if let Some(text) = text {
+                object.push((self.text_key.clone(), text));
+            }
+            return XmlJson::Object(object);
+        }
+
+        if texts.iter().any(|text| !text.trim().is_empty()) {
+            // Mixed content, kept in order with its whitespace
+            let content = node
+                .children()
+                .filter_map(|child| {
+                    if child.is_element() {
+                        Some(XmlJson::Object(vec![(
+                            element_name(&child),
+                            self.to_json(&child),
+                        )]))
+                    } else {
+                        child
+                            .text()
+                            .filter(|_| child.is_text())
+                            .map(|text| XmlJson::String(text.to_string()))
+                    }
+                })
+                .collect();
+            object.push((self.content_key.clone(), XmlJson::Array(content)));
+            return XmlJson::Object(object);
+        }
+
+        // Children are grouped by name, in the order of their first occurrence
+        let mut children: Vec<(String, Vec<XmlJson>)> = Vec::new();
+        for element in elements {
+            let name = element_name(&element);
+            let value = self.to_json(&element);
+            match children.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, values)) => values.push(value),
+                None => children.push((name, vec![value])),
+            }
+        }
+        for (name, mut values) in children {
+            let value = if values.len() == 1 && self.arrays == Arrays::Repeated {
+                values.remove(0)
+            } else {
+                XmlJson::Array(values)
+            };
+            object.push((name, value));
+        }
+        XmlJson::Object(object)
+    }
+
+    /// XML of the element `name` whose JSON value is `value`, the inverse of [`Self::to_json`]
+    pub fn to_xml(&self, name: &str, value: &XmlJson) -> String {
+        let mut xml = String::new();
+        self.write_xml(name, value, &mut xml);
+        xml
+    }
+
+    fn write_xml(&self, name: &str, value: &XmlJson, xml: &mut String) {
+        match value {
+            XmlJson::Null => xml.push_str(&format!("<{name}/>")),
+            XmlJson::String(text) => {
+                xml.push_str(&format!("<{name}>{}</{name}>", escape(text, false)))
+            }
+            XmlJson::Array(values) => {
+                for value in values {
+                    self.write_xml(name, value, xml);
+                }
+            }
+            XmlJson::Object(object) => {
+                xml.push('<');
+                xml.push_str(name);
+                for (key, value) in object {
+                    if let (Some(attribute), Some(value)) =
+                        (key.strip_prefix(&self.attribute_prefix), value.as_str())
+                    {
+                        xml.push_str(&format!(" {attribute}=\"{}\"", escape(value, true)));
+                    }
+                }
+                xml.push('>');
+                for (key, value) in object {
+                    if key.starts_with(&self.attribute_prefix) {
+                        continue;
+                    }
+                    if *key == self.text_key {
+                        xml.push_str(&escape(value.as_str().unwrap_or_default(), false));
+                    } else if *key == self.content_key {
+                        let XmlJson::Array(items) = value else {
+                            continue;
+                        };
+                        for item in items {
+                            match item {
+                                XmlJson::String(text) => xml.push_str(&escape(text, false)),
+                                XmlJson::Object(child) => {
+                                    for (name, value) in child {
+                                        self.write_xml(name, value, xml);
+                                    }
+                                }
+                                _ => {}
+                            }
+                        }
+                    } else {
+                        self.write_xml(key, value, xml);
+                    }
+                }
+                xml.push_str(&format!("</{name}>"));
+            }
+        }
+    }
+}
+
+/// Name of `element`, with the prefix of its namespace
+fn element_name(element: &roxmltree::Node) -> String {
+    let name = element.tag_name();
+    match name.namespace().and_then(|uri| element.lookup_prefix(uri)) {
+        Some(prefix) => format!("{prefix}:{}", name.name()),
+        None => name.name().to_string(),
+    }
+}
+
+/// Name of `attribute` of `element`, with the prefix of its namespace (never the default one)
+fn attribute_name(element: &roxmltree::Node, attribute: &roxmltree::Attribute) -> String {
+    let prefix = attribute.namespace().and_then(|uri| {
+        if uri == roxmltree::NS_XML_URI {
+            return Some("xml");
+        }
+        element
+            .namespaces()
+            .find(|ns| ns.uri() == uri && ns.name().is_some())
+            .and_then(|ns| ns.name())
+    });
+    match prefix {
+        Some(prefix) => format!("{prefix}:{}", attribute.name()),
+        None => attribute.name().to_string(),
+    }
+}
+
+/// Namespaces declared by `element` itself (prefix and URI), rather than inherited from its parent
+fn declared_namespaces<'a>(element: &roxmltree::Node<'a, '_>) -> Vec<(Option<&'a str>, &'a str)> {
+    let inherited: Vec<(Option<&str>, &str)> = element
+        .parent_element()
+        .map(|parent| {
+            parent
+                .namespaces()
+                .map(|ns| (ns.name(), ns.uri()))
+                .collect()
+        })
+        .unwrap_or_default();
+    element
+        .namespaces()
+        .map(|ns| (ns.name(), ns.uri()))
+        .filter(|ns| ns.1 != roxmltree::NS_XML_URI && !inherited.contains(ns))
+        .collect()
+}
+
+/// Escapes `text` for XML; in attribute values, also the whitespace the parser would otherwise normalize
+fn escape(text: &str, attribute: bool) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' if attribute => escaped.push_str("&quot;"),
+            '\n' if attribute => escaped.push_str("&#10;"),
+            '\t' if attribute => escaped.push_str("&#9;"),
+            '\r' => escaped.push_str("&#13;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Flattens nested objects and arrays into a single level object, whose keys are the paths to the values,
+/// joined with `separator` (array items by their index)
+pub fn flatten(value: &XmlJson, separator: &str) -> XmlJson {
+    let mut flat = Vec::new();
+    flatten_into(value, String::new(), separator, &mut flat);
+    XmlJson::Object(flat)
+}
+
+fn flatten_into(value: &XmlJson, key: String, separator: &str, flat: &mut Vec<(String, XmlJson)>) {
+    let child_key = |name: &str| {
+        if key.is_empty() {
+            name.to_string()
+        } else {
+            format!("{key}{separator}{name}")
+        }
+    };
+    match value {
+        XmlJson::Object(object) if !object.is_empty() => {
+            for (name, value) in object {
+                flatten_into(value, child_key(name), separator, flat);
+            }
+        }
+        XmlJson::Array(array) if !array.is_empty() => {
+            for (i, value) in array.iter().enumerate() {
+                flatten_into(value, child_key(&i.to_string()), separator, flat);
+            }
+        }
+        value => flat.push((key, value.clone())),
+    }
+}
+/// Writes every ClinVarSet as a JSON object, one per line (see [`JsonRules`])
+pub struct JsonFullHandler<W: std::io::Write> {
+    writer: W,
+    rules: JsonRules,
+    /// Separator of the keys, if the objects are flattened
+    flatten: Option<String>,
+    value: XmlJson,
+}
+
+impl<W: std::io::Write> JsonFullHandler<W> {
+    pub fn new_from_writer(writer: W) -> Self {
+        Self {
+            writer,
+            rules: JsonRules::default(),
+            flatten: None,
+            value: XmlJson::Null,
+        }
+    }
+
+    pub fn with_rules(mut self, rules: JsonRules) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Writes flattened objects, with keys joined with `separator` (see [`flatten`])
+    pub fn with_flattening(mut self, separator: &str) -> Self {
+        self.flatten = Some(separator.to_string());
+        self
+    }
+}
+
+impl<W: std::io::Write> EventHandler for JsonFullHandler<W> {
+    fn handle_clinvar_set(&mut self, event: &ClinVarSetEvent) -> Result<(), ClinvarXMLTabError> {
+        self.value = self.rules.to_json(event.node());
+        Ok(())
+    }
+
+    fn end_record(&mut self) -> Result<(), ClinvarXMLTabError> {
+        let value = std::mem::take(&mut self.value);
+        match &self.flatten {
+            Some(separator) => {
+                serde_json::to_writer(&mut self.writer, &flatten(&value, separator))?
+            }
+            None => serde_json::to_writer(&mut self.writer, &value)?,
+        }
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), ClinvarXMLTabError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    const XML: &str = r#"<Measure Type="single nucleotide variant">
+        <Name>c.1311G&gt;C</Name>
+        <XRef DB="dbSNP" ID="1800456"/>
+        <XRef DB="OMIM" ID="613349.0004"/>
+        <Comment Type="public">Some <b>bold</b> text </Comment>
+        <Empty/>
+        <Citation> PMID 1 </Citation>
+    </Measure>"#;
+
+    /// Child elements and text nodes of `node`, but for whitespace between elements
+    fn significant_children<'a, 'input>(
+        node: roxmltree::Node<'a, 'input>,
+    ) -> Vec<roxmltree::Node<'a, 'input>> {
+        let blank = |child: &roxmltree::Node| child.text().unwrap_or_default().trim().is_empty();
+        let mixed = node
+            .children()
+            .any(|child| child.is_text() && !blank(&child));
+        let has_elements = node.children().any(|child| child.is_element());
+        node.children()
+            .filter(|child| {
+                child.is_element() || (child.is_text() && (mixed || !has_elements || !blank(child)))
+            })
+            .collect()
+    }
+
+    /// Elements, attributes and text nodes of `a` and `b` are the same, but for whitespace between elements
+    fn assert_same_tree(a: roxmltree::Node, b: roxmltree::Node) {
+        assert_eq!(a.tag_name(), b.tag_name());
+        let attributes = |node: roxmltree::Node| -> Vec<(Option<String>, String, String)> {
+            node.attributes()
+                .map(|a| {
+                    let namespace = a.namespace().map(str::to_string);
+                    (namespace, a.name().to_string(), a.value().to_string())
+                })
+                .collect()
+        };
+        assert_eq!(attributes(a), attributes(b));
+        let (a_children, b_children) = (significant_children(a), significant_children(b));
+        assert_eq!(a_children.len(), b_children.len());
+        for (a, b) in a_children.into_iter().zip(b_children) {
+            if a.is_text() {
+                assert_eq!(a.text(), b.text());
+            } else {
+                assert_same_tree(a, b);
+            }
+        }
+    }
+
+    fn assert_round_trip(xml: &str) {
+        let rules = JsonRules::new();
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        let root = doc.root_element();
+        let value = rules.to_json(&root);
+        let written = rules.to_xml(&element_name(&root), &value);
+        let doc_again = roxmltree::Document::parse(&written).unwrap();
+        assert_same_tree(root, doc_again.root_element());
+        assert_eq!(rules.to_json(&doc_again.root_element()), value);
+    }
+
+    #[test]
+    fn test_to_json() {
+        let doc = roxmltree::Document::parse(XML).unwrap();
+        let value = JsonRules::new().to_json(&doc.root_element());
+        assert_eq!(
+            serde_json::to_value(&value).unwrap(),
+            serde_json::json!({
+                "@Type": "single nucleotide variant",
+                "Name": "c.1311G>C",
+                "XRef": [
+                    {"@DB": "dbSNP", "@ID": "1800456"},
+                    {"@DB": "OMIM", "@ID": "613349.0004"}
+                ],
+                "Comment": {
+                    "@Type": "public",
+                    "#content": ["Some ", {"b": "bold"}, " text "]
+                },
+                "Empty": null,
+                "Citation": " PMID 1 "
+            })
+        );
+        // Keys are in document order
+        let json = serde_json::to_string(&value).unwrap();
+        assert!(
+            json.starts_with(r#"{"@Type":"single nucleotide variant","Name":"c.1311G>C","XRef":"#)
+        );
+        assert!(json.ends_with(r#""Empty":null,"Citation":" PMID 1 "}"#));
+    }
+
+    #[test]
+    fn test_namespaces() {
+        let xml = r#"<ClinVarSet xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:type="T" ID="1">
+            <n:Note xmlns:n="urn:note" n:lang="en" xml:space="preserve">a&#10;b</n:Note>
+            <Plain Value="x&#10;y&#9;z"/>
+        </ClinVarSet>"#;
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        let value = JsonRules::new().to_json(&doc.root_element());
+        assert_eq!(
+            serde_json::to_value(&value).unwrap(),
+            serde_json::json!({
+                "@xmlns:xsi": "http://www.w3.org/2001/XMLSchema-instance",
+                "@xsi:type": "T",
+                "@ID": "1",
+                "n:Note": {
+                    "@xmlns:n": "urn:note",
+                    "@n:lang": "en",
+                    "@xml:space": "preserve",
+                    "#text": "a\nb"
+                },
+                "Plain": {"@Value": "x\ny\tz"}
+            })
+        );
+        assert_round_trip(xml);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        assert_round_trip(XML);
+        assert_round_trip(include_str!("clinvarset_new.xml"));
+        assert_round_trip("<a>x<b/>&lt;y&amp;<c>1</c><c>2</c>\r\n</a>");
+    }
+
+    #[test]
+    fn test_rules() {
+        let doc = roxmltree::Document::parse(XML).unwrap();
+        let rules = JsonRules::new()
+            .with_attribute_prefix("_")
+            .with_text_key("value")
+            .with_content_key("content")
+            .with_arrays(Arrays::Always);
+        let value = rules.to_json(&doc.root_element());
+        let value = serde_json::to_value(&value).unwrap();
+        assert_eq!(value["_Type"], "single nucleotide variant");
+        assert_eq!(value["Name"], serde_json::json!(["c.1311G>C"]));
+        // Mixed content is never grouped in arrays
+        assert_eq!(value["Comment"][0]["content"][1]["b"], "bold");
+        let written = rules.to_xml("Measure", &rules.to_json(&doc.root_element()));
+        let doc_again = roxmltree::Document::parse(&written).unwrap();
+        assert_same_tree(doc.root_element(), doc_again.root_element());
+    }
+
     #[test]
-    fn test_flatten(v: JsonValue) {}
+    fn test_flatten() {
+        let doc = roxmltree::Document::parse(XML).unwrap();
+        let flat = flatten(&JsonRules::new().to_json(&doc.root_element()), ".");
+        assert_eq!(
+            flat.get("@Type").unwrap().as_str(),
+            Some("single nucleotide variant")
+        );
+        assert_eq!(
+            flat.get("XRef.1.@ID").unwrap().as_str(),
+            Some("613349.0004")
+        );
+        assert_eq!(
+            flat.get("Comment.#content.0").unwrap().as_str(),
+            Some("Some ")
+        );
+        assert_eq!(
+            flat.get("Comment.#content.1.b").unwrap().as_str(),
+            Some("bold")
+        );
+        assert_eq!(flat.get("Empty"), Some(&XmlJson::Null));
+        let XmlJson::Object(flat) = flat else {
+            panic!("not an object");
+        };
+        assert_eq!(flat.len(), 12);
+        assert_eq!(flat[0].0, "@Type");
+    }
 }
@@ -1,8 +1,13 @@
 //! # XML Clinvar to TSV
 //! This is a conversion utility to...
+pub mod checkpoint;
 pub mod error;
+pub mod json_flatten;
+pub mod progress;
+pub mod reference;
 pub mod utils;
 mod xml;
+pub use xml::census;
 pub use xml::handler;
 pub use xml::reader;
 
@@ -11,16 +11,82 @@ use clap_complete::aot::generate;
 use clap_complete::Shell;
 
 // Use this crate's lib
-use clinvar_xml_tab;
+use clinvar_xml_tab::census;
+use clinvar_xml_tab::checkpoint::Checkpoint;
+use clinvar_xml_tab::clinvar::fields::FieldSpec;
+use clinvar_xml_tab::clinvar::filter::{Filter, FilteredHandler};
+use clinvar_xml_tab::clinvar::{
+    annotate, bed, columnar, diff, get, index, record, select, split, sqlite, stats,
+};
+use clinvar_xml_tab::json_flatten;
+use clinvar_xml_tab::progress::{Progress, ProgressMode};
 use clinvar_xml_tab::reader;
+use clinvar_xml_tab::reference::{add_ref_mismatch_filter, RefMismatch, ReferenceGenome};
 use clinvar_xml_tab::utils;
 
 fn convert(
     params: &cli::Cli,
     subparams: &cli::Convert,
 ) -> Result<(), clinvar_xml_tab::error::ClinvarXMLTabError> {
-    let in_stream = utils::file_reader(params.input())?;
-    let out_stream = utils::file_writer(params.output())?;
+    use clinvar_xml_tab::error::ClinvarXMLTabError;
+    use clinvar_xml_tab::handler::{EventHandler, TeeHandler};
+    use std::sync::{Arc, Mutex};
+
+    let assembly = match params.genome() {
+        cli::Genome::Hg19 => "GRCh37",
+        cli::Genome::Hg38 => "GRCh38",
+    };
+    let options = checkpoint_options(params, subparams, assembly);
+
+    // Checkpoints only make sense when the output files can be truncated back to a known length
+    let checkpoint_path = params
+        .output()
+        .filter(|output| !utils::is_compressed_output(output))
+        .filter(|_| {
+            subparams
+                .replaced_table()
+                .is_none_or(|table| !utils::is_compressed_output(table))
+        })
+        .filter(|_| subparams.format().is_line_based())
+        // Aggregated variants are only written at the very end
+        .filter(|_| !subparams.aggregate_vcv())
+        .map(Checkpoint::sidecar_path);
+
+    let start = if subparams.resume() {
+        let Some(checkpoint_path) = &checkpoint_path else {
+            return Err(ClinvarXMLTabError::ResumeError(
+                "output (and --replaced-table) must be uncompressed files, in a line-based format"
+                    .to_string(),
+            ));
+        };
+        let Some(checkpoint) = Checkpoint::load(checkpoint_path)? else {
+            return Err(ClinvarXMLTabError::ResumeError(format!(
+                "no checkpoint found at {}",
+                checkpoint_path.display()
+            )));
+        };
+        checkpoint.check_options(&options)?;
+        eprintln!(
+            "Resuming after {} records (input offset {})",
+            checkpoint.records, checkpoint.input_offset
+        );
+        Some(checkpoint)
+    } else {
+        None
+    };
+
+    let input_counter = utils::ByteCounter::default();
+    let in_stream = utils::file_reader_at(
+        params.input(),
+        start.as_ref().map_or(0, |c| c.input_offset),
+        &input_counter,
+    )?;
+
+    // Opened lazily, as not every format writes to a stream
+    let out_stream = || match (&start, params.output()) {
+        (Some(checkpoint), Some(output)) => utils::file_appender(output, checkpoint.output_length),
+        _ => utils::file_writer_with_level(params.output(), params.compression_level()),
+    };
 
     // let mut handler =
     //     clinvar_xml_tab::clinvar::record::CSVRecordHandler::new_from_writer(out_stream);
@@ -29,43 +95,718 @@ fn convert(
 
     let contig = Map::<Contig>::new();
 
-    let hdr = if let Some(existing_header) = subparams.existing_vcf_header() {
+    let mut hdr = if let Some(existing_header) = subparams.existing_vcf_header() {
         let mut vcf_reader = vcf::io::Reader::new(std::io::BufReader::new(std::fs::File::open(
             existing_header,
         )?));
-        let header = vcf_reader.read_header()?;
-        header
+        vcf_reader.read_header()?
     } else {
         noodles_vcf::Header::builder()
             .add_contig("chr1", contig)
             .build()
     };
 
-    let mut handler = clinvar_xml_tab::clinvar::record::VCFRecordHandler::new_from_writer_unchecked(
-        out_stream,
-        hdr,
-        match params.genome() {
-            cli::Genome::Hg19 => "GRCh37",
-            cli::Genome::Hg38 => "GRCh38",
-        },
-    );
+    let resuming = start.is_some();
+    let fields = match (subparams.fields(), subparams.fields_file()) {
+        (Some(fields), _) => Some(FieldSpec::parse(fields)?),
+        (None, Some(fields_file)) => Some(FieldSpec::from_file(fields_file)?),
+        (None, None) => None,
+    };
+    if fields.is_some() && !subparams.format().supports_fields() {
+        return Err(ClinvarXMLTabError::FieldSpecError(
+            "fields can only be selected for the vcf, tsv and jsonl formats".to_string(),
+        ));
+    }
+    let mut filter = Filter::default();
+    if let Some(statuses) = subparams.include_status() {
+        filter = filter.with_statuses(statuses.to_vec());
+    }
+    if let Some(expression) = subparams.filter() {
+        filter = filter.with_expression(expression)?;
+    }
+    if let Some(genes_file) = subparams.genes_file() {
+        filter = filter.with_genes_file(genes_file)?;
+    }
+    if let Some(regions_bed) = subparams.regions_bed() {
+        filter = filter.with_regions_bed(regions_bed)?;
+    }
+    if let Some(min_stars) = subparams.min_stars() {
+        filter = filter.with_min_stars(min_stars);
+    }
+    for (used, option) in [
+        (subparams.aggregate_vcv(), "--aggregate-vcv"),
+        (subparams.reference().is_some(), "--reference"),
+    ] {
+        if used && subparams.format() != cli::OutputFormat::Vcf {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{option} only applies to the vcf format"),
+            )
+            .into());
+        }
+    }
+    // Shared by the handlers of every file written with --split-by
+    let reference_genome = subparams
+        .reference()
+        .map(ReferenceGenome::open)
+        .transpose()?
+        .map(|genome| Arc::new(Mutex::new(genome)));
+    let on_ref_mismatch = match subparams.on_ref_mismatch() {
+        cli::OnRefMismatch::Flag => RefMismatch::Flag,
+        cli::OnRefMismatch::Drop => RefMismatch::Drop,
+    };
+    if reference_genome.is_some() && on_ref_mismatch == RefMismatch::Flag {
+        add_ref_mismatch_filter(&mut hdr);
+    }
+    if let Some(fields) = &fields {
+        fields.add_info_headers(&mut hdr);
+    }
+    let format = subparams.format();
+    let aggregate_vcv = subparams.aggregate_vcv();
+    let chromosome_style = match subparams.chrom_style() {
+        cli::ChromStyle::Ucsc => record::ChromosomeStyle::Ucsc,
+        cli::ChromStyle::Ensembl => record::ChromosomeStyle::Ensembl,
+    };
+    // Handler of the line-based formats, without a header when appending to an existing output
+    let line_handler = move |writer: Box<dyn std::io::Write + Send>,
+                             append: bool|
+          -> Result<Box<dyn EventHandler>, ClinvarXMLTabError> {
+        let fields = fields.clone();
+        Ok(match format {
+            cli::OutputFormat::Vcf => {
+                let handler = if append {
+                    record::VCFRecordHandler::new_from_writer_without_header(
+                        writer,
+                        hdr.clone(),
+                        assembly,
+                    )
+                } else {
+                    record::VCFRecordHandler::new_from_writer_unchecked(
+                        writer,
+                        hdr.clone(),
+                        assembly,
+                    )
+                };
+                let handler = match fields {
+                    Some(fields) => handler.with_fields(fields),
+                    None => handler,
+                };
+                let handler = match &reference_genome {
+                    Some(genome) => handler.with_reference(Arc::clone(genome), on_ref_mismatch),
+                    None => handler,
+                };
+                if aggregate_vcv {
+                    Box::new(handler.with_vcv_aggregation())
+                } else {
+                    Box::new(handler)
+                }
+            }
+            cli::OutputFormat::Tsv => {
+                let handler =
+                    record::CSVRecordHandler::new_tsv_from_writer(writer, assembly, !append);
+                match fields {
+                    Some(fields) => Box::new(handler.with_fields(fields)?),
+                    None => Box::new(handler),
+                }
+            }
+            cli::OutputFormat::Jsonl => {
+                let handler = record::JsonLinesRecordHandler::new_from_writer(writer, assembly);
+                match fields {
+                    Some(fields) => Box::new(handler.with_fields(fields)),
+                    None => Box::new(handler),
+                }
+            }
+            cli::OutputFormat::JsonFull => {
+                Box::new(json_flatten::JsonFullHandler::new_from_writer(writer))
+            }
+            cli::OutputFormat::JsonFlat => Box::new(
+                json_flatten::JsonFullHandler::new_from_writer(writer).with_flattening("."),
+            ),
+            cli::OutputFormat::Bed => Box::new(bed::BedRecordHandler::new_from_writer(
+                writer,
+                assembly,
+                chromosome_style,
+                !append,
+            )?),
+            _ => unreachable!("{format:?} is not line-based"),
+        })
+    };
+    let mut handler: Box<dyn EventHandler> = match format {
+        _ if subparams.split_by().is_some() => {
+            let (Some(split_by), Some(output_dir)) = (subparams.split_by(), subparams.output_dir())
+            else {
+                unreachable!("--split-by requires --output-dir");
+            };
+            if !format.is_line_based() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("--split-by doesn't apply to the {format:?} format"),
+                )
+                .into());
+            }
+            if params.output().is_some() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "--split-by writes to --output-dir, not --output",
+                )
+                .into());
+            }
+            let split_by = match split_by {
+                cli::SplitByOption::Chrom => split::SplitBy::Chromosome,
+                cli::SplitByOption::Gene => split::SplitBy::Gene,
+                cli::SplitByOption::Clnsig => split::SplitBy::Clnsig,
+            };
+            let level = params.compression_level();
+            let template = subparams
+                .output_template()
+                .replace("{ext}", format.extension());
+            let new_shard: split::ShardFactory = Box::new(line_handler);
+            Box::new(
+                split::SplitHandler::new(split_by, assembly, output_dir, &template, new_shard)?
+                    .with_max_open(subparams.max_open_files())
+                    .with_compression_level(level),
+            )
+        }
+        cli::OutputFormat::Vcf
+        | cli::OutputFormat::Tsv
+        | cli::OutputFormat::Jsonl
+        | cli::OutputFormat::JsonFull
+        | cli::OutputFormat::JsonFlat
+        | cli::OutputFormat::Bed => line_handler(out_stream()?, resuming)?,
+        cli::OutputFormat::Parquet => {
+            Box::new(columnar::ColumnarRecordHandler::new_parquet_from_writer(
+                out_stream()?,
+                assembly,
+                params.compression_level(),
+            )?)
+        }
+        cli::OutputFormat::Arrow => Box::new(
+            columnar::ColumnarRecordHandler::new_arrow_ipc_from_writer(out_stream()?, assembly)?,
+        ),
+        cli::OutputFormat::Sqlite => {
+            let Some(output) = params.output() else {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "the sqlite format needs an --output file",
+                ))?;
+            };
+            Box::new(sqlite::SqliteHandler::new(
+                output,
+                assembly,
+                subparams.overwrite(),
+            )?)
+        }
+    };
+
+    if !filter.is_empty() {
+        handler = Box::new(FilteredHandler::new(handler, filter, assembly));
+    }
+    // The side table is fed every record, whatever the filters
+    if let Some(replaced_table) = subparams.replaced_table() {
+        let writer = match start.as_ref().map(|c| c.replaced_table_length) {
+            Some(Some(length)) => utils::file_appender(replaced_table, length)?,
+            Some(None) => {
+                return Err(ClinvarXMLTabError::ResumeError(
+                    "the checkpoint has no length for the replaced table".to_string(),
+                ))
+            }
+            None => utils::file_writer(Some(replaced_table))?,
+        };
+        handler = Box::new(TeeHandler::new(
+            handler,
+            Box::new(record::ReplacementsHandler::new_from_writer(
+                writer, !resuming,
+            )?),
+        ));
+    }
+
+    let start = start.map(|c| c.stats()).unwrap_or_default();
+    let progress_mode = if subparams.progress_json() {
+        Some(ProgressMode::Json)
+    } else if atty::is(atty::Stream::Stderr) {
+        Some(ProgressMode::Tty)
+    } else {
+        None
+    };
+    let mut progress = progress_mode.map(|mode| {
+        let input_size = params
+            .input()
+            .and_then(|input| std::fs::metadata(input).ok())
+            .map(|m| m.len());
+        Progress::new(mode, input_counter.clone(), input_size, &start)
+    });
+
+    let checkpoint_every = subparams.checkpoint_every();
+    let stats = reader::read_xml_with(in_stream, &mut *handler, None, start, |stats, handler| {
+        if let Some(progress) = &mut progress {
+            progress.update(stats, handler.records_written())?;
+        }
+        if let (Some(checkpoint_path), Some(output)) = (&checkpoint_path, params.output()) {
+            if checkpoint_every > 0 && stats.records % checkpoint_every == 0 {
+                handler.flush()?;
+                let output_length = std::fs::metadata(output)?.len();
+                let replaced_table_length = match subparams.replaced_table() {
+                    Some(table) => Some(std::fs::metadata(table)?.len()),
+                    None => None,
+                };
+                Checkpoint::new(stats, output_length)
+                    .with_replaced_table_length(replaced_table_length)
+                    .with_options(options.clone())
+                    .save(checkpoint_path)?;
+            }
+        }
+        Ok(())
+    })?;
 
-    reader::read_xml(in_stream, &mut handler, None)?;
+    // The conversion went through: there is nothing left to resume
+    match handler.flush() {
+        Err(e) if e.is_broken_pipe() => {}
+        result => result?,
+    }
+    if let Some(progress) = &mut progress {
+        progress.finish(&stats, handler.records_written())?;
+    }
+    let counters = handler.counters();
+    if !counters.is_empty() {
+        if subparams.progress_json() {
+            let counters: serde_json::Map<String, serde_json::Value> = counters
+                .into_iter()
+                .map(|(name, count)| (name.to_string(), count.into()))
+                .collect();
+            eprintln!("{}", serde_json::json!({ "counters": counters }));
+        } else {
+            for (name, count) in counters {
+                eprintln!("{name}: {count}");
+            }
+        }
+    }
+    if let Some(checkpoint_path) = &checkpoint_path {
+        if checkpoint_path.exists() {
+            std::fs::remove_file(checkpoint_path)?;
+        }
+    }
 
     Ok(())
 }
 
+/// Options the output of `convert` depends on, saved in checkpoints so that a conversion is only resumed with
+/// the same ones
+fn checkpoint_options(
+    params: &cli::Cli,
+    subparams: &cli::Convert,
+    assembly: &str,
+) -> serde_json::Map<String, serde_json::Value> {
+    use serde_json::json;
+    let path = |path: Option<&std::path::PathBuf>| path.map(|p| p.display().to_string());
+    [
+        ("input", json!(path(params.input()))),
+        ("assembly", json!(assembly)),
+        ("format", json!(format!("{:?}", subparams.format()))),
+        (
+            "existing_vcf_header",
+            json!(path(subparams.existing_vcf_header())),
+        ),
+        ("fields", json!(subparams.fields())),
+        ("fields_file", json!(path(subparams.fields_file()))),
+        ("include_status", json!(subparams.include_status())),
+        ("filter", json!(subparams.filter())),
+        ("genes_file", json!(path(subparams.genes_file()))),
+        ("regions_bed", json!(path(subparams.regions_bed()))),
+        ("min_stars", json!(subparams.min_stars())),
+        ("reference", json!(path(subparams.reference()))),
+        (
+            "on_ref_mismatch",
+            json!(format!("{:?}", subparams.on_ref_mismatch())),
+        ),
+        (
+            "chrom_style",
+            json!(format!("{:?}", subparams.chrom_style())),
+        ),
+        ("replaced_table", json!(path(subparams.replaced_table()))),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value))
+    .collect()
+}
+
 fn debug(
     params: &cli::Cli,
-    _subparams: &cli::Debug,
+    subparams: &cli::Debug,
+) -> Result<(), clinvar_xml_tab::error::ClinvarXMLTabError> {
+    use clinvar_xml_tab::clinvar::model::ClinVarSet;
+    use clinvar_xml_tab::handler::EventHandler;
+    use std::io::Write;
+
+    let in_stream = utils::file_reader(params.input())?;
+    let out_stream = utils::file_writer_with_level(params.output(), params.compression_level())?;
+
+    if subparams.census() {
+        let mut handler = census::CensusHandler::new_from_writer(out_stream);
+        if let Some(previous) = subparams.against() {
+            let previous = census::Census::from_reader(utils::file_reader(Some(previous))?)?;
+            handler = handler.with_previous(previous);
+        }
+        match reader::read_xml(in_stream, &mut handler, None) {
+            Err(e) if e.is_broken_pipe() => return Ok(()),
+            result => result?,
+        }
+        eprintln!("records: {}", handler.records_written().unwrap_or(0));
+        for (name, count) in handler.counters() {
+            eprintln!("{name}: {count}");
+        }
+        return Ok(());
+    }
+
+    let assembly = match params.genome() {
+        cli::Genome::Hg19 => "GRCh37",
+        cli::Genome::Hg38 => "GRCh38",
+    };
+    let accessions = subparams
+        .ids()
+        .iter()
+        .map(|a| get::Accession::parse(a))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut selection = select::Selection::new().with_skip(subparams.skip());
+    match subparams.limit() {
+        Some(limit) => selection = selection.with_limit(limit),
+        None if accessions.is_empty() && subparams.random().is_none() => {
+            selection = selection.with_limit(1)
+        }
+        None => {}
+    }
+    if !accessions.is_empty() {
+        selection = selection.with_accessions(accessions);
+    }
+    if let Some(size) = subparams.random() {
+        selection = selection.with_random_sample(size, random_seed(subparams.seed()));
+    }
+
+    let mut out_stream = out_stream;
+    let result = selection.select(in_stream, |elem_bytes| {
+        match subparams.format() {
+            cli::DebugFormatOption::Xml => {
+                out_stream.write_all(elem_bytes)?;
+                out_stream.write_all(b"\n")?;
+            }
+            cli::DebugFormatOption::Tree => {
+                let mut writer = clinvar_xml_tab::handler::BasicNodeWriter::new(&mut out_stream);
+                reader::read_xml(elem_bytes, &mut writer, None)?;
+            }
+            cli::DebugFormatOption::Json => {
+                let set = ClinVarSet::from_xml(std::str::from_utf8(elem_bytes)?)?;
+                let record = record::ClinVarRecord::from_clinvar_set(&set, Some(assembly));
+                serde_json::to_writer(&mut out_stream, &record.to_json()?)?;
+                out_stream.write_all(b"\n")?;
+            }
+        }
+        Ok(true)
+    });
+    match result {
+        Err(e) if e.is_broken_pipe() => return Ok(()),
+        result => result?,
+    };
+    out_stream.flush()?;
+    Ok(())
+}
+
+/// The seed given, or else a random one, printed so that the same sample can be drawn again
+fn random_seed(seed: Option<u64>) -> u64 {
+    seed.unwrap_or_else(|| {
+        let seed = rand::random();
+        eprintln!("seed: {seed}");
+        seed
+    })
+}
+
+fn subset(
+    params: &cli::Cli,
+    subparams: &cli::Subset,
+) -> Result<(), clinvar_xml_tab::error::ClinvarXMLTabError> {
+    use std::io::BufRead;
+
+    let assembly = match params.genome() {
+        cli::Genome::Hg19 => "GRCh37",
+        cli::Genome::Hg38 => "GRCh38",
+    };
+    let mut ids = subparams.ids().to_vec();
+    if let Some(ids_file) = subparams.ids_file() {
+        for line in utils::file_reader(Some(ids_file))?.lines() {
+            let line = line?;
+            let id = line.trim();
+            if !id.is_empty() && !id.starts_with('#') {
+                ids.push(id.to_string());
+            }
+        }
+    }
+    let accessions = ids
+        .iter()
+        .map(|a| get::Accession::parse(a))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut filter = Filter::default();
+    if let Some(statuses) = subparams.include_status() {
+        filter = filter.with_statuses(statuses.to_vec());
+    }
+    if let Some(expression) = subparams.filter() {
+        filter = filter.with_expression(expression)?;
+    }
+    if let Some(genes_file) = subparams.genes_file() {
+        filter = filter.with_genes_file(genes_file)?;
+    }
+    if !subparams.genes().is_empty() {
+        filter = filter.with_genes(subparams.genes().to_vec());
+    }
+    if let Some(regions_bed) = subparams.regions_bed() {
+        filter = filter.with_regions_bed(regions_bed)?;
+    }
+    if !subparams.regions().is_empty() {
+        filter = filter.with_regions(subparams.regions())?;
+    }
+
+    let mut selection = select::Selection::new();
+    if let Some(limit) = subparams.limit() {
+        selection = selection.with_limit(limit);
+    }
+    if !accessions.is_empty() {
+        selection = selection.with_accessions(accessions);
+    }
+    if !filter.is_empty() {
+        selection = selection.with_filter(filter, assembly);
+    }
+    if let Some(size) = subparams.random() {
+        selection = selection.with_random_sample(size, random_seed(subparams.seed()));
+        let strata = subparams
+            .stratify()
+            .iter()
+            .map(|stratum| match stratum {
+                cli::StratumOption::Clnsig => select::Stratum::Clnsig,
+                cli::StratumOption::VariantType => select::Stratum::VariantType,
+            })
+            .collect();
+        selection = selection.with_strata(strata);
+    }
+
+    let in_stream = utils::file_reader(params.input())?;
+    let out_stream = utils::file_writer_with_level(params.output(), params.compression_level())?;
+    let written = match select::write_subset(in_stream, &selection, out_stream) {
+        Err(e) if e.is_broken_pipe() => return Ok(()),
+        result => result?,
+    };
+    eprintln!("{written} ClinVarSets written");
+    Ok(())
+}
+
+fn annotate(
+    params: &cli::Cli,
+    subparams: &cli::Annotate,
+) -> Result<(), clinvar_xml_tab::error::ClinvarXMLTabError> {
+    use std::io::BufRead;
+
+    let assembly = match params.genome() {
+        cli::Genome::Hg19 => "GRCh37",
+        cli::Genome::Hg38 => "GRCh38",
+    };
+    let reference_genome = subparams
+        .reference()
+        .map(ReferenceGenome::open)
+        .transpose()?;
+    let mut clinvar = utils::file_reader(Some(subparams.clinvar()))?;
+    // The XML release starts with its declaration (or root element), a VCF with `##fileformat`
+    let is_vcf = clinvar.fill_buf()?.starts_with(b"#");
+    let mut index = if is_vcf {
+        let keys = subparams.fields().map(|fields| {
+            fields
+                .split(',')
+                .map(|k| k.trim().to_string())
+                .collect::<Vec<_>>()
+        });
+        annotate::ClinVarIndex::from_vcf(clinvar, keys.as_deref(), reference_genome)?
+    } else {
+        let fields = FieldSpec::parse(subparams.fields().unwrap_or(annotate::DEFAULT_FIELDS))?;
+        let mut filter = Filter::default();
+        if let Some(statuses) = subparams.include_status() {
+            filter = filter.with_statuses(statuses.to_vec());
+        }
+        let mut builder =
+            annotate::ClinVarIndexBuilder::new(fields, filter, assembly, reference_genome);
+        reader::read_xml(clinvar, &mut builder, None)?;
+        builder.into_index()
+    };
+    eprintln!("clinvar_variants: {}", index.len());
+
+    let mode = match subparams.match_mode() {
+        cli::MatchOption::Exact => annotate::MatchMode::Exact,
+        cli::MatchOption::Position => annotate::MatchMode::Position,
+    };
+    let in_stream = utils::file_reader(params.input())?;
+    let out_stream = utils::file_writer_with_level(params.output(), params.compression_level())?;
+    let counts =
+        match annotate::annotate(&mut index, mode, subparams.prefix(), in_stream, out_stream) {
+            Err(e) if e.is_broken_pipe() => return Ok(()),
+            result => result?,
+        };
+    eprintln!("records: {}", counts.records);
+    eprintln!("annotated: {}", counts.annotated);
+    Ok(())
+}
+
+fn diff(
+    params: &cli::Cli,
+    subparams: &cli::Diff,
+) -> Result<(), clinvar_xml_tab::error::ClinvarXMLTabError> {
+    use clinvar_xml_tab::handler::EventHandler;
+
+    let assembly = match params.genome() {
+        cli::Genome::Hg19 => "GRCh37",
+        cli::Genome::Hg38 => "GRCh38",
+    };
+    let mut filter = Filter::default();
+    if let Some(statuses) = subparams.include_status() {
+        filter = filter.with_statuses(statuses.to_vec());
+    }
+    let format = match subparams.format() {
+        cli::DiffFormatOption::Tsv => diff::DiffFormat::Tsv,
+        cli::DiffFormatOption::Jsonl => diff::DiffFormat::Jsonl,
+    };
+
+    let mut old_release = diff::OldReleaseLoader::new(filter, assembly)?;
+    reader::read_xml(
+        utils::file_reader(Some(subparams.old_release()))?,
+        &mut old_release,
+        None,
+    )?;
+    eprintln!(
+        "old_records: {}",
+        old_release.records_written().unwrap_or(0)
+    );
+
+    let out_stream = utils::file_writer_with_level(params.output(), params.compression_level())?;
+    let mut handler = diff::DiffHandler::new(old_release, out_stream, format)?;
+    match reader::read_xml(
+        utils::file_reader(Some(subparams.new_release()))?,
+        &mut handler,
+        None,
+    ) {
+        Err(e) if e.is_broken_pipe() => return Ok(()),
+        result => result?,
+    }
+    for (name, count) in handler.counters() {
+        eprintln!("{name}: {count}");
+    }
+    Ok(())
+}
+
+fn stats(
+    params: &cli::Cli,
+    subparams: &cli::Stats,
 ) -> Result<(), clinvar_xml_tab::error::ClinvarXMLTabError> {
+    let assembly = match params.genome() {
+        cli::Genome::Hg19 => "GRCh37",
+        cli::Genome::Hg38 => "GRCh38",
+    };
+    let format = match subparams.format() {
+        cli::StatsFormatOption::Table => stats::StatsFormat::Table,
+        cli::StatsFormatOption::Json => stats::StatsFormat::Json,
+    };
     let in_stream = utils::file_reader(params.input())?;
-    let out_stream = utils::file_writer(params.output())?;
+    let out_stream = utils::file_writer_with_level(params.output(), params.compression_level())?;
+    let mut handler =
+        stats::StatsHandler::new_from_writer(out_stream, format, assembly, subparams.top());
+    match reader::read_xml(in_stream, &mut handler, None) {
+        Err(e) if e.is_broken_pipe() => Ok(()),
+        result => result,
+    }
+}
+
+fn get(
+    params: &cli::Cli,
+    subparams: &cli::Get,
+) -> Result<(), clinvar_xml_tab::error::ClinvarXMLTabError> {
+    let assembly = match params.genome() {
+        cli::Genome::Hg19 => "GRCh37",
+        cli::Genome::Hg38 => "GRCh38",
+    };
+    let accessions = subparams
+        .accessions()
+        .iter()
+        .map(|a| get::Accession::parse(a))
+        .collect::<Result<Vec<_>, _>>()?;
+    let format = match subparams.format() {
+        cli::GetFormatOption::Xml => get::GetFormat::Xml,
+        cli::GetFormatOption::Tree => get::GetFormat::Tree,
+        cli::GetFormatOption::Record => get::GetFormat::Record,
+    };
+    let out_stream = utils::file_writer_with_level(params.output(), params.compression_level())?;
+    let mut handler =
+        get::GetHandler::new_from_writer(out_stream, format, assembly, accessions, subparams.all());
+    let result = match subparams.index() {
+        Some(index_path) => get_indexed(params, index_path, &mut handler),
+        None => reader::read_xml(utils::file_reader(params.input())?, &mut handler, None),
+    };
+    match result {
+        Err(e) if e.is_broken_pipe() => return Ok(()),
+        result => result?,
+    }
+    for accession in handler.not_found() {
+        eprintln!("not found: {accession}");
+    }
+    Ok(())
+}
+
+/// Input file of the subcommands that need to seek into it (not stdin)
+fn input_file(params: &cli::Cli) -> Result<&std::path::PathBuf, std::io::Error> {
+    params.input().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "an input file is needed (--input), stdin can't be seeked into",
+        )
+    })
+}
 
-    let mut handler = clinvar_xml_tab::handler::BasicNodeWriter::new(out_stream);
+/// Reads the ClinVarSets of the index entries with any of the accessions looked for
+fn get_indexed<W: std::io::Write>(
+    params: &cli::Cli,
+    index_path: &std::path::Path,
+    handler: &mut get::GetHandler<W>,
+) -> Result<(), clinvar_xml_tab::error::ClinvarXMLTabError> {
+    use clinvar_xml_tab::handler::EventHandler;
 
-    reader::read_xml(in_stream, &mut handler, Some(1))?;
+    let input_path = input_file(params)?;
+    let index = index::IndexReader::new(utils::file_reader(Some(index_path))?)?;
+    index.check_input(input_path)?;
+    let mut input = std::fs::File::open(input_path)?;
+    let offsets = index.offsets();
+    let accessions = handler.accessions().to_vec();
+    for entry in index {
+        let entry = entry?;
+        if !accessions.iter().any(|accession| entry.has(accession)) {
+            continue;
+        }
+        let elem_bytes = offsets.read_clinvar_set(&mut input, &entry)?;
+        reader::read_xml(elem_bytes.as_slice(), handler, None)?;
+        if handler.done() {
+            break;
+        }
+    }
+    Ok(())
+}
 
+fn index(
+    params: &cli::Cli,
+    _subparams: &cli::Index,
+) -> Result<(), clinvar_xml_tab::error::ClinvarXMLTabError> {
+    let input = input_file(params)?;
+    let blocks = index::BgzfBlocks::for_input(input)?;
+    let stamp = index::InputStamp::of(input)?;
+    let output = params.output().cloned().unwrap_or_else(|| {
+        let mut output = input.clone().into_os_string();
+        output.push(".idx");
+        output.into()
+    });
+    let in_stream = utils::file_reader(Some(input))?;
+    let out_stream = utils::file_writer_with_level(Some(&output), params.compression_level())?;
+    let indexed = index::write_index(in_stream, blocks.as_ref(), stamp, out_stream)?;
+    eprintln!("{indexed} ClinVarSets indexed in {}", output.display());
     Ok(())
 }
 
@@ -79,9 +820,7 @@ fn auto_complete(
 
     let output_dir = match gen {
         Shell::Zsh => {
-            if let Some(d) =
-                home::home_dir().and_then(|d| Some(d.join(".oh-my-zsh").join("completions")))
-            {
+            if let Some(d) = home::home_dir().map(|d| d.join(".oh-my-zsh").join("completions")) {
                 std::fs::DirBuilder::new().recursive(true).create(&d)?;
                 d
             } else {
@@ -109,6 +848,12 @@ fn main() -> Result<(), clinvar_xml_tab::error::ClinvarXMLTabError> {
     match args.command() {
         cli::Command::Convert(subparams) => convert(&args, subparams)?,
         cli::Command::Debug(subparams) => debug(&args, subparams)?,
+        cli::Command::Annotate(subparams) => annotate(&args, subparams)?,
+        cli::Command::Diff(subparams) => diff(&args, subparams)?,
+        cli::Command::Stats(subparams) => stats(&args, subparams)?,
+        cli::Command::Get(subparams) => get(&args, subparams)?,
+        cli::Command::Subset(subparams) => subset(&args, subparams)?,
+        cli::Command::Index(subparams) => index(&args, subparams)?,
         cli::Command::AutoComplete(subparams) => auto_complete(&args, subparams)?,
     }
 
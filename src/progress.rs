@@ -0,0 +1,205 @@
+//! Progress reporting on stderr, either as a single refreshed line (for humans), or as JSON lines (for workflow managers).
+use serde::Serialize;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use crate::reader::ReadStats;
+use crate::utils::ByteCounter;
+
+const TTY_INTERVAL: Duration = Duration::from_millis(500);
+const JSON_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// A single line on stderr, refreshed in place
+    Tty,
+    /// One JSON object per line on stderr
+    Json,
+}
+
+/// A snapshot of the conversion progress, as emitted in JSON mode
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct ProgressReport {
+    pub elapsed_secs: f64,
+    /// Bytes consumed from the input file (compressed, if it is)
+    pub input_bytes: u64,
+    /// Size of the input file, if known (not known for stdin)
+    pub input_size: Option<u64>,
+    pub records: u64,
+    pub records_per_sec: f64,
+    /// Records written by the handler during this run, if it keeps count
+    pub written: Option<u64>,
+    /// Records handled but not written during this run
+    pub skipped: Option<u64>,
+    pub eta_secs: Option<f64>,
+    pub done: bool,
+}
+
+pub struct Progress {
+    mode: ProgressMode,
+    counter: ByteCounter,
+    input_size: Option<u64>,
+    start: Instant,
+    last_report: Instant,
+    /// Records that were already handled before this run (when resuming)
+    initial_records: u64,
+    /// Input bytes that were already consumed when this run started
+    initial_bytes: u64,
+}
+
+impl Progress {
+    /// Must be created once the input is positioned where this run starts (see [`crate::utils::file_reader_at`]):
+    /// the bytes `counter` already counted, skipped to resume, are not part of this run's throughput and ETA.
+    pub fn new(
+        mode: ProgressMode,
+        counter: ByteCounter,
+        input_size: Option<u64>,
+        start: &ReadStats,
+    ) -> Self {
+        let now = Instant::now();
+        Self {
+            mode,
+            initial_bytes: counter.get(),
+            counter,
+            input_size,
+            start: now,
+            last_report: now,
+            initial_records: start.records,
+        }
+    }
+
+    /// Reports progress, unless the previous report was too recent.
+    /// `written` is the number of records the handler actually wrote, if it keeps count.
+    pub fn update(&mut self, stats: &ReadStats, written: Option<u64>) -> std::io::Result<()> {
+        let interval = match self.mode {
+            ProgressMode::Tty => TTY_INTERVAL,
+            ProgressMode::Json => JSON_INTERVAL,
+        };
+        if self.last_report.elapsed() < interval {
+            return Ok(());
+        }
+        self.last_report = Instant::now();
+        self.report(stats, written, false)
+    }
+
+    /// Reports the final state of the conversion
+    pub fn finish(&mut self, stats: &ReadStats, written: Option<u64>) -> std::io::Result<()> {
+        self.report(stats, written, true)?;
+        if self.mode == ProgressMode::Tty {
+            eprintln!();
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self, stats: &ReadStats, written: Option<u64>, done: bool) -> ProgressReport {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let input_bytes = self.counter.get();
+        let records_this_run = stats.records - self.initial_records;
+        let bytes_this_run = input_bytes.saturating_sub(self.initial_bytes);
+        let eta_secs = match self.input_size {
+            Some(size) if bytes_this_run > 0 && !done => {
+                Some(elapsed * size.saturating_sub(input_bytes) as f64 / bytes_this_run as f64)
+            }
+            _ => None,
+        };
+        ProgressReport {
+            elapsed_secs: elapsed,
+            input_bytes,
+            input_size: self.input_size,
+            records: stats.records,
+            records_per_sec: if elapsed > 0.0 {
+                records_this_run as f64 / elapsed
+            } else {
+                0.0
+            },
+            written,
+            skipped: written.map(|w| records_this_run.saturating_sub(w)),
+            eta_secs,
+            done,
+        }
+    }
+
+    fn report(&self, stats: &ReadStats, written: Option<u64>, done: bool) -> std::io::Result<()> {
+        let report = self.snapshot(stats, written, done);
+        let mut stderr = std::io::stderr().lock();
+        match self.mode {
+            ProgressMode::Json => {
+                serde_json::to_writer(&mut stderr, &report)?;
+                writeln!(stderr)?;
+            }
+            ProgressMode::Tty => {
+                let input = match report.input_size {
+                    Some(size) if size > 0 => format!(
+                        "{} / {} ({:.1}%)",
+                        human_bytes(report.input_bytes),
+                        human_bytes(size),
+                        100.0 * report.input_bytes as f64 / size as f64
+                    ),
+                    _ => human_bytes(report.input_bytes),
+                };
+                let output = match (report.written, report.skipped) {
+                    (Some(written), Some(skipped)) => {
+                        format!(" | {written} written, {skipped} skipped")
+                    }
+                    _ => String::new(),
+                };
+                let eta = report
+                    .eta_secs
+                    .map(|eta| format!(" | ETA {}", human_duration(eta)))
+                    .unwrap_or_default();
+                // Clear the end of the line, in case the previous report was longer
+                write!(
+                    stderr,
+                    "\r{input} | {} records ({:.0}/s){output}{eta}\x1b[K",
+                    report.records, report.records_per_sec
+                )?;
+            }
+        }
+        stderr.flush()
+    }
+}
+
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+fn human_duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    format!("{}:{:02}:{:02}", secs / 3600, (secs / 60) % 60, secs % 60)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resumed_eta() {
+        let counter = ByteCounter::default();
+        // Skipped to resume from a checkpoint
+        counter.add(600);
+        let start = ReadStats {
+            records: 60,
+            input_offset: 600,
+        };
+        let progress = Progress::new(ProgressMode::Json, counter.clone(), Some(1000), &start);
+        counter.add(200);
+        let stats = ReadStats {
+            records: 80,
+            input_offset: 800,
+        };
+        let report = progress.snapshot(&stats, Some(15), false);
+        assert_eq!(report.input_bytes, 800);
+        assert_eq!(report.skipped, Some(5));
+        // As many bytes are left as were read during this run
+        let eta = report.eta_secs.unwrap();
+        assert!((eta - report.elapsed_secs).abs() < 1e-3 * report.elapsed_secs.max(1.0));
+        assert!(progress.snapshot(&stats, None, true).eta_secs.is_none());
+    }
+}
@@ -0,0 +1,377 @@
+//! Random access to a reference genome FASTA, indexed with `samtools faidx` (`.fai`),
+//! and optionally compressed with `bgzip` (which then also needs its `.gzi` index).
+//!
+//! Used to check VCF alleles against the reference and to normalize them (left-align and trim).
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::error::ClinvarXMLTabError;
+
+/// Bases read at once around a requested position, as ClinVarSets tend to come gene by gene
+const WINDOW_SIZE: u64 = 1 << 16;
+
+trait ReadSeek: Read + Seek + Send {}
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+/// A line of the `.fai` index
+#[derive(Debug, Clone, Copy)]
+struct FaiRecord {
+    length: u64,
+    offset: u64,
+    line_bases: u64,
+    line_width: u64,
+}
+
+/// Sequence read around the last requested position
+struct Window {
+    name: String,
+    /// 0-based position of the first base
+    start: u64,
+    bases: Vec<u8>,
+}
+
+pub struct ReferenceGenome {
+    reader: Box<dyn ReadSeek>,
+    index: HashMap<String, FaiRecord>,
+    window: Option<Window>,
+}
+
+/// Opening errors don't say which file was missing
+fn with_path(path: &Path) -> impl Fn(std::io::Error) -> std::io::Error + '_ {
+    move |e| std::io::Error::new(e.kind(), format!("{}: {e}", path.display()))
+}
+
+fn with_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+impl ReferenceGenome {
+    /// Opens `path`, with its index at `<path>.fai` (and `<path>.gzi` if it is bgzipped)
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ClinvarXMLTabError> {
+        let path = path.as_ref();
+        let fai_path = with_extension(path, ".fai");
+        let invalid = |line: &str| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid line in {}: {line}", fai_path.display()),
+            )
+        };
+        let mut index = HashMap::new();
+        for line in BufReader::new(File::open(&fai_path).map_err(with_path(&fai_path))?).lines() {
+            let line = line?;
+            let columns: Vec<&str> = line.split('\t').collect();
+            let [name, length, offset, line_bases, line_width, ..] = columns[..] else {
+                return Err(invalid(&line).into());
+            };
+            let parse = |v: &str| v.parse::<u64>().map_err(|_| invalid(&line));
+            index.insert(
+                name.to_string(),
+                FaiRecord {
+                    length: parse(length)?,
+                    offset: parse(offset)?,
+                    line_bases: parse(line_bases)?,
+                    line_width: parse(line_width)?,
+                },
+            );
+        }
+
+        let mut file = File::open(path).map_err(with_path(path))?;
+        let mut magic = [0u8; 2];
+        let compressed = file.read(&mut magic)? == 2 && magic == [0x1f, 0x8b];
+        file.rewind()?;
+        let reader: Box<dyn ReadSeek> = if compressed {
+            let gzi_path = with_extension(path, ".gzi");
+            let gzi = noodles_bgzf::gzi::read(&gzi_path).map_err(with_path(&gzi_path))?;
+            Box::new(noodles_bgzf::IndexedReader::new(file, gzi))
+        } else {
+            Box::new(BufReader::new(file))
+        };
+        Ok(Self {
+            reader,
+            index,
+            window: None,
+        })
+    }
+
+    /// Name of `chromosome` in this reference: `chr1` and `1`, `chrM` and `MT` are considered the same
+    fn resolve(&self, chromosome: &str) -> Option<&str> {
+        let bare = chromosome.strip_prefix("chr").unwrap_or(chromosome);
+        let candidates: &[&str] = match bare {
+            "M" | "MT" => &["chrM", "MT", "chrMT", "M"],
+            _ => &[chromosome, bare],
+        };
+        candidates
+            .iter()
+            .map(|c| c.to_string())
+            .chain(std::iter::once(format!("chr{bare}")))
+            .find_map(|c| self.index.get_key_value(&c).map(|(k, _)| k.as_str()))
+    }
+
+    /// Reads `length` bases from the 0-based `start` of the sequence `name`
+    fn read(&mut self, name: &str, start: u64, length: u64) -> Result<Vec<u8>, ClinvarXMLTabError> {
+        let record = self.index[name];
+        let end = (start + length).min(record.length);
+        if start >= end {
+            return Ok(Vec::new());
+        }
+        let offset = |position: u64| {
+            record.offset
+                + (position / record.line_bases) * record.line_width
+                + position % record.line_bases
+        };
+        // A single read, line breaks included: seeking is costly in bgzipped files
+        let from = offset(start);
+        let mut raw = vec![0; (offset(end - 1) + 1 - from) as usize];
+        self.reader.seek(SeekFrom::Start(from))?;
+        self.reader.read_exact(&mut raw)?;
+        raw.retain(|b| !b.is_ascii_whitespace());
+        raw.make_ascii_uppercase();
+        Ok(raw)
+    }
+
+    /// Bases from the 1-based `position`, `length` of them (fewer at the end of the sequence).
+    /// `None` if the chromosome is not in the reference.
+    pub fn fetch(
+        &mut self,
+        chromosome: &str,
+        position: u64,
+        length: u64,
+    ) -> Result<Option<Vec<u8>>, ClinvarXMLTabError> {
+        let Some(name) = self.resolve(chromosome).map(str::to_string) else {
+            return Ok(None);
+        };
+        let start = position.saturating_sub(1);
+        let cached = self.window.as_ref().is_some_and(|w| {
+            w.name == name && w.start <= start && start + length <= w.start + w.bases.len() as u64
+        });
+        if !cached {
+            let window_start = start.saturating_sub(WINDOW_SIZE / 2);
+            let bases = self.read(
+                &name,
+                window_start,
+                start - window_start + length.max(WINDOW_SIZE / 2),
+            )?;
+            self.window = Some(Window {
+                name,
+                start: window_start,
+                bases,
+            });
+        }
+        let window = self.window.as_ref().expect("window was just filled");
+        let from = ((start - window.start) as usize).min(window.bases.len());
+        let to = (from + length as usize).min(window.bases.len());
+        Ok(Some(window.bases[from..to].to_vec()))
+    }
+}
+
+/// What to do with variants whose REF doesn't match the reference
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefMismatch {
+    /// Write them anyway, with the `REF_MISMATCH` filter
+    Flag,
+    /// Leave them out
+    Drop,
+}
+
+/// FILTER set on flagged variants
+pub const REF_MISMATCH_FILTER: &str = "REF_MISMATCH";
+
+/// Declares [`REF_MISMATCH_FILTER`] in `header`
+pub fn add_ref_mismatch_filter(header: &mut noodles_vcf::Header) {
+    use noodles_vcf::header::record::value::{map::Filter, Map};
+    header.filters_mut().insert(
+        REF_MISMATCH_FILTER.to_string(),
+        Map::<Filter>::new("REF does not match the reference genome"),
+    );
+}
+
+/// Outcome of checking a variant against the reference
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Checked {
+    /// REF matches the reference; the variant is left-aligned and trimmed
+    Valid {
+        position: usize,
+        reference: String,
+        alternate: String,
+    },
+    /// REF doesn't match the reference
+    Mismatch,
+    /// The chromosome is not in the reference
+    UnknownContig,
+}
+
+/// Checks REF against `genome`, then left-aligns and trims the alleles (as described by Tan et al., 2015)
+pub fn check_and_normalize(
+    genome: &mut ReferenceGenome,
+    chromosome: &str,
+    position: usize,
+    reference: &str,
+    alternate: &str,
+) -> Result<Checked, ClinvarXMLTabError> {
+    let Some(bases) = genome.fetch(chromosome, position as u64, reference.len() as u64)? else {
+        return Ok(Checked::UnknownContig);
+    };
+    if !bases.eq_ignore_ascii_case(reference.as_bytes()) {
+        return Ok(Checked::Mismatch);
+    }
+    let mut position = position;
+    let mut reference = reference.to_ascii_uppercase().into_bytes();
+    let mut alternate = alternate.to_ascii_uppercase().into_bytes();
+    // Symbolic or missing alleles can't be normalized, and there is nothing to align when they are the same
+    if !alternate.iter().all(|b| b"ACGTN".contains(b)) || reference == alternate {
+        return Ok(Checked::Valid {
+            position,
+            reference: String::from_utf8_lossy(&reference).into_owned(),
+            alternate: String::from_utf8_lossy(&alternate).into_owned(),
+        });
+    }
+    loop {
+        if !reference.is_empty() && !alternate.is_empty() && reference.last() == alternate.last() {
+            reference.pop();
+            alternate.pop();
+        } else if (reference.is_empty() || alternate.is_empty()) && position > 1 {
+            position -= 1;
+            let Some(base) = genome.fetch(chromosome, position as u64, 1)? else {
+                return Ok(Checked::UnknownContig);
+            };
+            reference.splice(0..0, base.iter().copied());
+            alternate.splice(0..0, base.iter().copied());
+        } else {
+            break;
+        }
+    }
+    while reference.len() > 1 && alternate.len() > 1 && reference[0] == alternate[0] {
+        reference.remove(0);
+        alternate.remove(0);
+        position += 1;
+    }
+    Ok(Checked::Valid {
+        position,
+        reference: String::from_utf8_lossy(&reference).into_owned(),
+        alternate: String::from_utf8_lossy(&alternate).into_owned(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    /// chr1 is wrapped at 10 bases per line, MT is soft-masked
+    const FASTA: &str = ">chr1\nGGGCACACAC\nTTTAAACCCG\nGGTTTAAA\n>MT\nacgtnacgt\n";
+    const FAI: &str = "chr1\t28\t6\t10\t11\nMT\t9\t41\t10\t11\n";
+
+    /// Writes the FASTA and its index to the temporary directory, bgzipped (a block per line) if `compressed`
+    fn write_reference(name: &str, compressed: bool) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{name}-{}.fa", std::process::id()));
+        if compressed {
+            let mut writer = noodles_bgzf::Writer::new(File::create(&path).unwrap());
+            let mut gzi = Vec::new();
+            let mut uncompressed = 0;
+            for line in FASTA.split_inclusive('\n') {
+                writer.write_all(line.as_bytes()).unwrap();
+                writer.flush().unwrap();
+                uncompressed += line.len() as u64;
+                gzi.push((writer.position(), uncompressed));
+            }
+            writer.finish().unwrap();
+            // The last entry would be the end of the file
+            gzi.pop();
+            let mut index = (gzi.len() as u64).to_le_bytes().to_vec();
+            for (compressed, uncompressed) in gzi {
+                index.extend(compressed.to_le_bytes());
+                index.extend(uncompressed.to_le_bytes());
+            }
+            std::fs::write(with_extension(&path, ".gzi"), index).unwrap();
+        } else {
+            std::fs::write(&path, FASTA).unwrap();
+        }
+        std::fs::write(with_extension(&path, ".fai"), FAI).unwrap();
+        path
+    }
+
+    fn remove_reference(path: &Path) {
+        for extension in ["", ".fai", ".gzi"] {
+            let _ = std::fs::remove_file(with_extension(path, extension));
+        }
+    }
+
+    fn check_fetch(genome: &mut ReferenceGenome) {
+        let mut fetch = |chromosome: &str, position: u64, length: u64| {
+            genome
+                .fetch(chromosome, position, length)
+                .unwrap()
+                .map(|bases| String::from_utf8(bases).unwrap())
+        };
+        assert_eq!(fetch("chr1", 1, 4).as_deref(), Some("GGGC"));
+        // Across line breaks
+        assert_eq!(fetch("chr1", 9, 4).as_deref(), Some("ACTT"));
+        assert_eq!(fetch("chr1", 8, 14).as_deref(), Some("CACTTTAAACCCGG"));
+        // Truncated at the end of the sequence
+        assert_eq!(fetch("chr1", 27, 5).as_deref(), Some("AA"));
+        assert_eq!(fetch("chr1", 40, 1).as_deref(), Some(""));
+        // Either naming, uppercased
+        assert_eq!(fetch("1", 4, 3).as_deref(), Some("CAC"));
+        assert_eq!(fetch("chrM", 1, 4).as_deref(), Some("ACGT"));
+        assert_eq!(fetch("MT", 5, 5).as_deref(), Some("NACGT"));
+        assert_eq!(fetch("chr2", 1, 1), None);
+    }
+
+    #[test]
+    fn test_fetch() {
+        let path = write_reference("reference", false);
+        let mut genome = ReferenceGenome::open(&path).unwrap();
+        check_fetch(&mut genome);
+        remove_reference(&path);
+    }
+
+    #[test]
+    fn test_fetch_bgzf() {
+        let path = write_reference("reference-bgzf", true);
+        let mut genome = ReferenceGenome::open(&path).unwrap();
+        check_fetch(&mut genome);
+        remove_reference(&path);
+
+        // The .gzi is needed
+        let path = write_reference("reference-bgzf-no-gzi", true);
+        std::fs::remove_file(with_extension(&path, ".gzi")).unwrap();
+        let error = ReferenceGenome::open(&path).err().unwrap();
+        assert!(error.to_string().contains(".gzi"), "{error}");
+        remove_reference(&path);
+    }
+
+    #[test]
+    fn test_check_and_normalize() {
+        let path = write_reference("normalize", false);
+        let mut genome = ReferenceGenome::open(&path).unwrap();
+        let mut check = |chromosome: &str, position: usize, reference: &str, alternate: &str| {
+            check_and_normalize(&mut genome, chromosome, position, reference, alternate).unwrap()
+        };
+        let valid = |position: usize, reference: &str, alternate: &str| Checked::Valid {
+            position,
+            reference: reference.to_string(),
+            alternate: alternate.to_string(),
+        };
+
+        // A deletion of AC at the end of CACACAC is moved to the start of the repeat
+        assert_eq!(check("chr1", 8, "CAC", "C"), valid(3, "GCA", "G"));
+        // And so is the insertion of another AC
+        assert_eq!(check("chr1", 10, "C", "CAC"), valid(3, "G", "GCA"));
+        // Shared bases are trimmed on both sides
+        assert_eq!(check("chr1", 11, "TTTA", "TGTA"), valid(12, "T", "G"));
+        assert_eq!(check("1", 11, "TTTAA", "TCA"), valid(12, "TTA", "C"));
+        // Already normalized, in lowercase
+        assert_eq!(check("chr1", 4, "c", "t"), valid(4, "C", "T"));
+        // Symbolic alleles are only checked
+        assert_eq!(check("chr1", 4, "CACA", "<DEL>"), valid(4, "CACA", "<DEL>"));
+
+        assert_eq!(check("chr1", 1, "AAA", "A"), Checked::Mismatch);
+        assert_eq!(check("chr1", 27, "AAT", "A"), Checked::Mismatch);
+        assert_eq!(check("chr2", 1, "A", "G"), Checked::UnknownContig);
+        remove_reference(&path);
+    }
+}
@@ -1,80 +1,226 @@
 use flate2::{write, Compression};
+use noodles_bgzf::writer::CompressionLevel;
 use std::ffi::OsStr;
 use std::fs::File;
 use std::io::{self, BufRead, BufReader};
-use std::io::{BufWriter, Read, Write};
+use std::io::{BufWriter, Read, Seek, Write};
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
 const MAGIC_MAX_LEN: usize = 6;
 const GZ_MAGIC: [u8; 3] = [0x1f, 0x8b, 0x08];
 const BZ_MAGIC: [u8; 3] = [0x42, 0x5a, 0x68];
 const XZ_MAGIC: [u8; 6] = [0xfd, 0x37, 0x7a, 0x58, 0x5A, 0x00];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
 const BUFF_SIZE: usize = 512 * 1024;
 
-fn magic_num<P: AsRef<Path> + Copy>(file_name: P) -> Result<[u8; MAGIC_MAX_LEN], std::io::Error> {
-    let mut buffer: [u8; MAGIC_MAX_LEN] = [0; MAGIC_MAX_LEN];
-    let mut fp = File::open(file_name)?;
-    let _ = fp.read(&mut buffer)?;
-    Ok(buffer)
-}
-
-fn is_gzipped<P: AsRef<Path> + Copy>(file_name: P) -> Result<bool, std::io::Error> {
-    let buffer = magic_num(file_name)?;
-    let gz_or_not =
-        buffer[0] == GZ_MAGIC[0] && buffer[1] == GZ_MAGIC[1] && buffer[2] == GZ_MAGIC[2];
-    Ok(gz_or_not
-        || file_name
-            .as_ref()
-            .extension()
-            .is_some_and(|ext| ext == "gz"))
-}
-
-fn is_bzipped<P: AsRef<Path> + Copy>(file_name: P) -> Result<bool, std::io::Error> {
-    let buffer = magic_num(file_name)?;
-    let bz_or_not =
-        buffer[0] == BZ_MAGIC[0] && buffer[1] == BZ_MAGIC[1] && buffer[2] == BZ_MAGIC[2];
-    Ok(bz_or_not
-        || file_name
-            .as_ref()
-            .extension()
-            .is_some_and(|ext| ext == "bz2"))
-}
-
-fn is_xz<P: AsRef<Path> + Copy>(file_name: P) -> Result<bool, std::io::Error> {
-    let buffer = magic_num(file_name)?;
-    let xz_or_not = buffer[0] == XZ_MAGIC[0]
-        && buffer[1] == XZ_MAGIC[1]
-        && buffer[2] == XZ_MAGIC[2]
-        && buffer[3] == XZ_MAGIC[3]
-        && buffer[4] == XZ_MAGIC[4]
-        && buffer[5] == XZ_MAGIC[5];
-    Ok(xz_or_not
-        || file_name
-            .as_ref()
-            .extension()
-            .is_some_and(|ext| ext == "xz"))
-}
-
-// Creates a handy writer to output to either a file or stdout (and automatically compresses if the file extension is .gz)
+/// Compression formats `file_reader` can decode, detected from the first bytes of the input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputCompression {
+    None,
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+impl InputCompression {
+    /// Detects the compression from the magic bytes at the very start of a stream
+    pub fn detect(magic: &[u8]) -> Self {
+        if magic.starts_with(&GZ_MAGIC) {
+            Self::Gzip
+        } else if magic.starts_with(&BZ_MAGIC) {
+            Self::Bzip2
+        } else if magic.starts_with(&XZ_MAGIC) {
+            Self::Xz
+        } else if magic.starts_with(&ZSTD_MAGIC) {
+            Self::Zstd
+        } else {
+            Self::None
+        }
+    }
+}
+
+/// Peeks at the first bytes of `input` and wraps it in the right decoder.
+/// Works on any stream (files, stdin, pipes, FIFOs...), since nothing needs to be reopened or seeked.
+fn decompressed<R: Read + Send + 'static>(
+    mut input: R,
+) -> Result<Box<dyn BufRead + Send>, std::io::Error> {
+    // A single read on a pipe may return fewer bytes than asked for
+    let mut magic = Vec::with_capacity(MAGIC_MAX_LEN);
+    (&mut input)
+        .take(MAGIC_MAX_LEN as u64)
+        .read_to_end(&mut magic)?;
+    let compression = InputCompression::detect(&magic);
+    // Put the peeked bytes back in front of the stream
+    let input = io::Cursor::new(magic).chain(input);
+
+    Ok(match compression {
+        InputCompression::Gzip => Box::new(BufReader::with_capacity(
+            BUFF_SIZE,
+            flate2::read::MultiGzDecoder::new(input),
+        )),
+        InputCompression::Bzip2 => Box::new(BufReader::with_capacity(
+            BUFF_SIZE,
+            bzip2::read::MultiBzDecoder::new(input),
+        )),
+        InputCompression::Xz => Box::new(BufReader::with_capacity(
+            BUFF_SIZE,
+            xz2::read::XzDecoder::new_multi_decoder(input),
+        )),
+        InputCompression::Zstd => Box::new(BufReader::with_capacity(
+            BUFF_SIZE,
+            zstd::stream::read::Decoder::new(input)?,
+        )),
+        InputCompression::None => Box::new(BufReader::with_capacity(BUFF_SIZE, input)),
+    })
+}
+
+/// Unlike the other encoders, bzip2's does not finish its stream when dropped
+struct FinishingBzEncoder<W: Write>(bzip2::write::BzEncoder<W>);
+
+impl<W: Write> Write for FinishingBzEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<W: Write> Drop for FinishingBzEncoder<W> {
+    fn drop(&mut self) {
+        let _ = self.0.try_finish();
+    }
+}
+
+/// Compression formats `file_writer` can produce
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputCompression {
+    None,
+    Gzip,
+    /// Blocked gzip, as expected by tabix (still readable by any gzip decoder)
+    Bgzf,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+impl OutputCompression {
+    /// Picks the compression from the file name extension.
+    /// `.bgz` and `.vcf.gz` get BGZF, so that the output can be indexed with tabix.
+    pub fn from_path<P: AsRef<Path>>(file_name: P) -> Self {
+        let file_name = file_name.as_ref();
+        match file_name.extension().and_then(OsStr::to_str) {
+            Some("bgz") => Self::Bgzf,
+            Some("gz") => {
+                if file_name
+                    .file_stem()
+                    .is_some_and(|stem| Path::new(stem).extension() == Some(OsStr::new("vcf")))
+                {
+                    Self::Bgzf
+                } else {
+                    Self::Gzip
+                }
+            }
+            Some("bz2") => Self::Bzip2,
+            Some("xz") => Self::Xz,
+            Some("zst") => Self::Zstd,
+            _ => Self::None,
+        }
+    }
+
+    /// Range of valid compression levels
+    fn levels(&self) -> std::ops::RangeInclusive<u32> {
+        match self {
+            Self::None => 0..=0,
+            Self::Gzip | Self::Bgzf | Self::Xz => 0..=9,
+            Self::Bzip2 => 1..=9,
+            Self::Zstd => 1..=22,
+        }
+    }
+
+    /// Wraps `file` in the right encoder, using `level` or the format's default level
+    fn encoder(
+        &self,
+        file: File,
+        level: Option<u32>,
+    ) -> Result<Box<dyn Write + Send>, std::io::Error> {
+        if let Some(level) = level {
+            if !self.levels().contains(&level) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "invalid compression level {level} for {self:?} (expected {:?})",
+                        self.levels()
+                    ),
+                ));
+            }
+        }
+        Ok(match self {
+            Self::None => Box::new(file),
+            Self::Gzip => Box::new(write::GzEncoder::new(
+                file,
+                level.map(Compression::new).unwrap_or_default(),
+            )),
+            Self::Bgzf => {
+                let mut builder = noodles_bgzf::writer::Builder::default();
+                if let Some(level) = level.and_then(|l| CompressionLevel::new(l as u8)) {
+                    builder = builder.set_compression_level(level);
+                }
+                Box::new(builder.build_from_writer(file))
+            }
+            Self::Bzip2 => Box::new(FinishingBzEncoder(bzip2::write::BzEncoder::new(
+                file,
+                level.map(bzip2::Compression::new).unwrap_or_default(),
+            ))),
+            Self::Xz => Box::new(xz2::write::XzEncoder::new(file, level.unwrap_or(6))),
+            Self::Zstd => Box::new(
+                zstd::stream::write::Encoder::new(
+                    file,
+                    level
+                        .map(|l| l as i32)
+                        .unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL),
+                )?
+                .auto_finish(),
+            ),
+        })
+    }
+}
+
+// Creates a handy writer to output to either a file or stdout (and automatically compresses according to the file extension, see `OutputCompression`)
 pub fn file_writer<P>(file_out: Option<P>) -> Result<Box<dyn Write + Send>, std::io::Error>
+where
+    P: AsRef<Path> + Copy,
+{
+    file_writer_with_level(file_out, None)
+}
+
+/// Same as `file_writer`, with an explicit compression level (ignored when not compressing)
+pub fn file_writer_with_level<P>(
+    file_out: Option<P>,
+    level: Option<u32>,
+) -> Result<Box<dyn Write + Send>, std::io::Error>
 where
     P: AsRef<Path> + Copy,
 {
     if let Some(file_name) = file_out {
         let file_name = file_name.as_ref();
-        let file = match File::create(&file_name) {
-            Err(why) => panic!("couldn't open {}: {}", file_name.display(), why.to_string()),
-            Ok(file) => file,
-        };
-
-        if file_name.extension() == Some(OsStr::new("gz")) {
-            Ok(Box::new(BufWriter::with_capacity(
-                128 * 1024,
-                write::GzEncoder::new(file, Compression::default()),
-            )))
-        } else {
-            Ok(Box::new(BufWriter::with_capacity(128 * 1024, file)))
-        }
+        let file = File::create(file_name).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("couldn't create {}: {e}", file_name.display()),
+            )
+        })?;
+
+        let compression = OutputCompression::from_path(file_name);
+        let level = level.filter(|_| compression != OutputCompression::None);
+        Ok(Box::new(BufWriter::with_capacity(
+            128 * 1024,
+            compression.encoder(file, level)?,
+        )))
     } else {
         if atty::is(atty::Stream::Stdout) {
             eprintln!("Warning: no redirection detected, not writing anywhere");
@@ -86,41 +232,334 @@ where
     }
 }
 
+/// Appends to the file `file_name` (created if needed), compressed according to its extension like `file_writer`.
+/// Compressed output is appended as a new stream, which `file_reader` reads along with the previous ones.
+pub fn file_writer_appending<P: AsRef<Path>>(
+    file_name: P,
+    level: Option<u32>,
+) -> Result<Box<dyn Write + Send>, std::io::Error> {
+    let file_name = file_name.as_ref();
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file_name)?;
+    let compression = OutputCompression::from_path(file_name);
+    let level = level.filter(|_| compression != OutputCompression::None);
+    Ok(Box::new(BufWriter::with_capacity(
+        128 * 1024,
+        compression.encoder(file, level)?,
+    )))
+}
+
+/// Whether `file_writer` would compress its output, given the output file name
+pub fn is_compressed_output<P: AsRef<Path>>(file_name: P) -> bool {
+    OutputCompression::from_path(file_name) != OutputCompression::None
+}
+
+/// Opens an existing, uncompressed output file for appending, after truncating it to `length` bytes
+/// (it's an error if it is shorter than that)
+pub fn file_appender<P: AsRef<Path>>(
+    file_name: P,
+    length: u64,
+) -> Result<Box<dyn Write + Send>, std::io::Error> {
+    let file_name = file_name.as_ref();
+    let mut file = std::fs::OpenOptions::new().write(true).open(file_name)?;
+    if file.metadata()?.len() < length {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("{} is shorter than {length} bytes", file_name.display()),
+        ));
+    }
+    file.set_len(length)?;
+    file.seek(io::SeekFrom::End(0))?;
+    Ok(Box::new(BufWriter::with_capacity(128 * 1024, file)))
+}
+
+/// Shared count of the bytes read from an input, before any decompression
+#[derive(Clone, Default, Debug)]
+pub struct ByteCounter(Arc<AtomicU64>);
+
+impl ByteCounter {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn add(&self, bytes: u64) {
+        self.0.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+/// Counts the bytes going through the inner reader
+struct CountingReader<R: Read> {
+    inner: R,
+    counter: ByteCounter,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.counter.add(n as u64);
+        Ok(n)
+    }
+}
+
 pub fn file_reader<P>(file_in: Option<P>) -> Result<Box<dyn BufRead + Send>, std::io::Error>
 where
     P: AsRef<Path> + Copy,
 {
-    if let Some(file_name) = file_in {
-        let gz_flag = is_gzipped(file_name)?;
-        let bz_flag = is_bzipped(file_name)?;
-        let zx_flag = is_xz(file_name)?;
-
-        let fp = File::open(file_name)?;
+    file_reader_counted(file_in, &ByteCounter::default())
+}
 
-        if gz_flag {
-            Ok(Box::new(BufReader::with_capacity(
-                BUFF_SIZE,
-                flate2::read::MultiGzDecoder::new(fp),
-            )))
-        } else if bz_flag {
-            Ok(Box::new(BufReader::with_capacity(
-                BUFF_SIZE,
-                bzip2::read::MultiBzDecoder::new(fp),
-            )))
-        } else if zx_flag {
-            Ok(Box::new(BufReader::with_capacity(
-                BUFF_SIZE,
-                xz2::read::XzDecoder::new_multi_decoder(fp),
-            )))
-        } else {
-            Ok(Box::new(BufReader::with_capacity(BUFF_SIZE, fp)))
-        }
+/// Same as `file_reader`, but keeps `counter` updated with the number of bytes read from the input (compressed, if it is)
+pub fn file_reader_counted<P>(
+    file_in: Option<P>,
+    counter: &ByteCounter,
+) -> Result<Box<dyn BufRead + Send>, std::io::Error>
+where
+    P: AsRef<Path> + Copy,
+{
+    if let Some(file_name) = file_in {
+        decompressed(CountingReader {
+            inner: File::open(file_name)?,
+            counter: counter.clone(),
+        })
     } else {
         if atty::is(atty::Stream::Stdin) {
             eprintln!("Error: stdin not detected");
             std::process::exit(1);
         }
-        let fp = BufReader::new(io::stdin());
-        Ok(Box::new(fp))
+        decompressed(CountingReader {
+            inner: io::stdin(),
+            counter: counter.clone(),
+        })
+    }
+}
+
+/// Same as `file_reader_counted`, but positioned at `offset` in the uncompressed input (e.g. to resume a conversion).
+/// Uncompressed files are seeked into, and `counter` starts at `offset`; anything else is read up to `offset`.
+pub fn file_reader_at<P>(
+    file_in: Option<P>,
+    offset: u64,
+    counter: &ByteCounter,
+) -> Result<Box<dyn BufRead + Send>, std::io::Error>
+where
+    P: AsRef<Path> + Copy,
+{
+    if let Some(file_name) = file_in {
+        let mut file = File::open(file_name)?;
+        let mut magic = Vec::with_capacity(MAGIC_MAX_LEN);
+        (&mut file)
+            .take(MAGIC_MAX_LEN as u64)
+            .read_to_end(&mut magic)?;
+        if InputCompression::detect(&magic) == InputCompression::None {
+            if offset > file.metadata()?.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "input is shorter than the offset to start from",
+                ));
+            }
+            file.seek(io::SeekFrom::Start(offset))?;
+            counter.0.store(offset, Ordering::Relaxed);
+            return Ok(Box::new(BufReader::with_capacity(
+                BUFF_SIZE,
+                CountingReader {
+                    inner: file,
+                    counter: counter.clone(),
+                },
+            )));
+        }
+    }
+    let mut reader = file_reader_counted(file_in, counter)?;
+    let skipped = io::copy(&mut (&mut reader).take(offset), &mut io::sink())?;
+    if skipped < offset {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "input is shorter than the offset to start from",
+        ));
+    }
+    Ok(reader)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CONTENT: &[u8] = b"<ReleaseSet><ClinVarSet ID=\"1\"/><ClinVarSet ID=\"2\"/></ReleaseSet>";
+
+    fn read_at(file_name: &Path, offset: u64) -> (String, u64) {
+        let counter = ByteCounter::default();
+        let mut content = String::new();
+        file_reader_at(Some(file_name), offset, &counter)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        (content, counter.get())
+    }
+
+    #[test]
+    fn test_file_reader_at() {
+        let dir = std::env::temp_dir();
+        let plain = dir.join(format!("reader-at-{}.xml", std::process::id()));
+        let gzipped = dir.join(format!("reader-at-{}.xml.gz", std::process::id()));
+        std::fs::write(&plain, CONTENT).unwrap();
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(CONTENT).unwrap();
+        std::fs::write(&gzipped, encoder.finish().unwrap()).unwrap();
+
+        // Seeked into: the counter is the position in the file
+        let (content, read) = read_at(&plain, 12);
+        assert_eq!(content.as_bytes(), &CONTENT[12..]);
+        assert_eq!(read, CONTENT.len() as u64);
+        let (content, _) = read_at(&gzipped, 12);
+        assert_eq!(content.as_bytes(), &CONTENT[12..]);
+
+        let too_far = CONTENT.len() as u64 + 1;
+        for file_name in [&plain, &gzipped] {
+            let error = file_reader_at(Some(file_name), too_far, &ByteCounter::default());
+            assert!(error.is_err());
+        }
+        std::fs::remove_file(plain).unwrap();
+        std::fs::remove_file(gzipped).unwrap();
+    }
+
+    #[test]
+    fn test_file_appender() {
+        let path = std::env::temp_dir().join(format!("appender-{}.tsv", std::process::id()));
+        std::fs::write(&path, "a\nb\nhalf a li").unwrap();
+        let mut writer = file_appender(&path, 4).unwrap();
+        writer.write_all(b"c\n").unwrap();
+        drop(writer);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a\nb\nc\n");
+        assert!(file_appender(&path, 100).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(file_appender(&path, 0).is_err());
+    }
+
+    #[test]
+    fn test_output_compression() {
+        for (file_name, compression) in [
+            ("clinvar.vcf.gz", OutputCompression::Bgzf),
+            ("clinvar.tsv.bgz", OutputCompression::Bgzf),
+            ("clinvar.tsv.gz", OutputCompression::Gzip),
+            ("clinvar.gz", OutputCompression::Gzip),
+            ("clinvar.tsv.zst", OutputCompression::Zstd),
+            ("clinvar.tsv.bz2", OutputCompression::Bzip2),
+            ("clinvar.tsv.xz", OutputCompression::Xz),
+            ("clinvar.vcf", OutputCompression::None),
+            ("clinvar", OutputCompression::None),
+        ] {
+            assert_eq!(
+                OutputCompression::from_path(file_name),
+                compression,
+                "{file_name}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_writer_round_trip() {
+        let dir = std::env::temp_dir();
+        for extension in ["tsv", "tsv.gz", "vcf.gz", "tsv.bz2", "tsv.xz", "tsv.zst"] {
+            let path = dir.join(format!("round-trip-{}.{extension}", std::process::id()));
+            for level in [None, Some(1)] {
+                let mut writer = file_writer_with_level(Some(&path), level).unwrap();
+                // Several writes, the encoders being finished when dropped
+                for _ in 0..1000 {
+                    writer.write_all(CONTENT).unwrap();
+                }
+                drop(writer);
+                let mut content = Vec::new();
+                file_reader(Some(&path))
+                    .unwrap()
+                    .read_to_end(&mut content)
+                    .unwrap();
+                assert_eq!(content, CONTENT.repeat(1000), "{extension} {level:?}");
+                assert_eq!(
+                    is_compressed_output(&path),
+                    std::fs::read(&path).unwrap() != content
+                );
+            }
+            // Out of range for every encoder, and ignored without one
+            let invalid_level = file_writer_with_level(Some(&path), Some(30));
+            assert_eq!(invalid_level.is_err(), is_compressed_output(&path));
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_writer_error() {
+        let path = std::env::temp_dir()
+            .join(format!("no-such-dir-{}", std::process::id()))
+            .join("out.tsv.gz");
+        let error = file_writer_with_level(Some(&path), None).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(error.to_string().contains("out.tsv.gz"));
+    }
+
+    /// A non-seekable stream, like a pipe, giving at most `chunk` bytes per read
+    struct Pipe {
+        data: Vec<u8>,
+        position: usize,
+        chunk: usize,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let end = (self.position + self.chunk.min(buf.len())).min(self.data.len());
+            let n = end - self.position;
+            buf[..n].copy_from_slice(&self.data[self.position..end]);
+            self.position = end;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_decompressed_stream() {
+        let content = CONTENT.repeat(100);
+        let mut gzip = write::GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(&content).unwrap();
+        let mut bzip2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        bzip2.write_all(&content).unwrap();
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(&content).unwrap();
+        let encoded = [
+            (InputCompression::Gzip, gzip.finish().unwrap()),
+            (InputCompression::Bzip2, bzip2.finish().unwrap()),
+            (InputCompression::Xz, xz.finish().unwrap()),
+            (
+                InputCompression::Zstd,
+                zstd::encode_all(content.as_slice(), 0).unwrap(),
+            ),
+            (InputCompression::None, content.clone()),
+        ];
+        for (compression, data) in encoded {
+            assert_eq!(InputCompression::detect(&data), compression);
+            for chunk in [1, 4, 1 << 16] {
+                // Read as it comes, in small pieces, and split in two streams
+                let (head, tail) = data.split_at(data.len() / 2);
+                let pipe = Pipe {
+                    data: head.to_vec(),
+                    position: 0,
+                    chunk,
+                }
+                .chain(io::Cursor::new(tail.to_vec()));
+                let mut read = Vec::new();
+                decompressed(pipe).unwrap().read_to_end(&mut read).unwrap();
+                assert_eq!(read, content, "{compression:?} by {chunk}");
+            }
+        }
+
+        // Shorter than the longest magic number, or empty
+        for short in [b"<a/>".as_slice(), b"\x1f", b""] {
+            assert_eq!(InputCompression::detect(short), InputCompression::None);
+            let pipe = Pipe {
+                data: short.to_vec(),
+                position: 0,
+                chunk: 1,
+            };
+            let mut read = Vec::new();
+            decompressed(pipe).unwrap().read_to_end(&mut read).unwrap();
+            assert_eq!(read, short);
+        }
     }
 }
@@ -0,0 +1,428 @@
+//! Census of the element paths found in the records: how often each occurs, the attributes it has, some of
+//! its text values and how many times it can occur within a single record.
+//!
+//! It also tells which elements and attributes the typed model ([`crate::clinvar::model`]) skips, so that what a
+//! new release adds beyond the model doesn't go unnoticed.
+//!
+//! A census is written as TSV, and can be read back to compare the schema of two releases.
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::error::ClinvarXMLTabError;
+use crate::handler::{walk_elements, ClinVarSetEvent, EventHandler};
+
+const HEADER: [&str; 8] = [
+    "PATH",
+    "COUNT",
+    "RECORDS",
+    "MAX_PER_RECORD",
+    "ATTRIBUTES",
+    "SAMPLES",
+    "MODELLED",
+    "UNMODELLED_ATTRIBUTES",
+];
+
+const DIFF_HEADER: [&str; 4] = ["CHANGE", "PATH", "OLD", "NEW"];
+
+/// Distinct text values kept per path
+const SAMPLES: usize = 3;
+
+/// Longer text values are truncated to this many characters
+const SAMPLE_LEN: usize = 80;
+
+const SAMPLE_SEPARATOR: &str = " | ";
+
+/// What was seen of an element path
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathCensus {
+    /// Number of elements
+    pub count: u64,
+    /// Number of records with at least one
+    pub records: u64,
+    /// Largest number of elements within a single record
+    pub max_per_record: u64,
+    pub attributes: BTreeSet<String>,
+    /// First distinct text values
+    pub samples: Vec<String>,
+    /// Whether the typed model skipped the element (in records it could deserialize)
+    pub unmodelled: bool,
+    /// Attributes the typed model skipped
+    pub unmodelled_attributes: BTreeSet<String>,
+}
+
+/// Census of every path, by path (dot-separated element names, from the record element)
+#[derive(Debug, Clone, Default)]
+pub struct Census(BTreeMap<String, PathCensus>);
+
+impl Census {
+    /// Reads a census written by [`Census::write`]
+    pub fn from_reader(reader: impl std::io::Read) -> Result<Self, ClinvarXMLTabError> {
+        let mut tsv = csv::ReaderBuilder::new()
+            .delimiter(b'\t')
+            .from_reader(reader);
+        let mut census = Self::default();
+        for record in tsv.records() {
+            let record = record?;
+            let field = |i: usize| record.get(i).unwrap_or_default();
+            let number = |i: usize| {
+                field(i).parse::<u64>().map_err(|_| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("invalid {} in census: {}", HEADER[i], field(i)),
+                    )
+                })
+            };
+            let path = PathCensus {
+                count: number(1)?,
+                records: number(2)?,
+                max_per_record: number(3)?,
+                attributes: field(4)
+                    .split(',')
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+                samples: field(5)
+                    .split(SAMPLE_SEPARATOR)
+                    .filter(|sample| !sample.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+                unmodelled: field(6) == "no",
+                unmodelled_attributes: field(7)
+                    .split(',')
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+            };
+            census.0.insert(field(0).to_string(), path);
+        }
+        Ok(census)
+    }
+
+    pub fn paths(&self) -> &BTreeMap<String, PathCensus> {
+        &self.0
+    }
+
+    pub fn write<W: std::io::Write>(&self, writer: W) -> Result<(), ClinvarXMLTabError> {
+        let mut tsv = csv::WriterBuilder::new()
+            .delimiter(b'\t')
+            .from_writer(writer);
+        tsv.write_record(HEADER)?;
+        for (path, census) in &self.0 {
+            tsv.write_record([
+                path.as_str(),
+                &census.count.to_string(),
+                &census.records.to_string(),
+                &census.max_per_record.to_string(),
+                &census
+                    .attributes
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(","),
+                &census.samples.join(SAMPLE_SEPARATOR),
+                if census.unmodelled { "no" } else { "yes" },
+                &census
+                    .unmodelled_attributes
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ])?;
+        }
+        tsv.flush()?;
+        Ok(())
+    }
+
+    /// Writes what changed since the `previous` census: paths and attributes that appeared (`new_path`,
+    /// `new_attribute`) or disappeared (`removed_path`, `removed_attribute`), and paths that became repeated
+    /// within a record or stopped being (`cardinality`). Returns the number of changes.
+    pub fn write_diff<W: std::io::Write>(
+        &self,
+        previous: &Census,
+        writer: W,
+    ) -> Result<u64, ClinvarXMLTabError> {
+        let mut tsv = csv::WriterBuilder::new()
+            .delimiter(b'\t')
+            .from_writer(writer);
+        tsv.write_record(DIFF_HEADER)?;
+        let mut changes = 0;
+        let paths: BTreeSet<&String> = self.0.keys().chain(previous.0.keys()).collect();
+        for path in paths {
+            let mut change = |change: &str, old: &str, new: &str| {
+                changes += 1;
+                tsv.write_record([change, path, old, new])
+            };
+            match (previous.0.get(path), self.0.get(path)) {
+                (None, Some(new)) => change("new_path", "", &new.count.to_string())?,
+                (Some(old), None) => change("removed_path", &old.count.to_string(), "")?,
+                (Some(old), Some(new)) => {
+                    for name in new.attributes.difference(&old.attributes) {
+                        change("new_attribute", "", name)?;
+                    }
+                    for name in old.attributes.difference(&new.attributes) {
+                        change("removed_attribute", name, "")?;
+                    }
+                    if (old.max_per_record > 1) != (new.max_per_record > 1) {
+                        change(
+                            "cardinality",
+                            &old.max_per_record.to_string(),
+                            &new.max_per_record.to_string(),
+                        )?;
+                    }
+                }
+                (None, None) => {}
+            }
+        }
+        tsv.flush()?;
+        Ok(changes)
+    }
+}
+
+/// Takes the census of every record, and writes it (or how it differs from a previous one) once they are all read
+pub struct CensusHandler<W: std::io::Write> {
+    writer: W,
+    census: Census,
+    previous: Option<Census>,
+    /// Elements of each path in the current record
+    in_record: HashMap<String, u64>,
+    records: u64,
+    changes: u64,
+    /// Records the typed model couldn't deserialize, so not checked against it
+    model_errors: u64,
+}
+
+impl<W: std::io::Write> CensusHandler<W> {
+    pub fn new_from_writer(writer: W) -> Self {
+        Self {
+            writer,
+            census: Census::default(),
+            previous: None,
+            in_record: HashMap::new(),
+            records: 0,
+            changes: 0,
+            model_errors: 0,
+        }
+    }
+
+    /// Writes the changes since `previous` instead of the census
+    pub fn with_previous(mut self, previous: Census) -> Self {
+        self.previous = Some(previous);
+        self
+    }
+}
+
+impl<W: std::io::Write> EventHandler for CensusHandler<W> {
+    fn handle_clinvar_set(&mut self, event: &ClinVarSetEvent) -> Result<(), ClinvarXMLTabError> {
+        walk_elements(event.node(), &mut Vec::new(), self, 1)?;
+        let unmodelled = match event.set() {
+            Ok(set) => set.unmodelled(event.node())?,
+            Err(_) => {
+                self.model_errors += 1;
+                return Ok(());
+            }
+        };
+        for path in unmodelled {
+            let (path, attribute) = match path.split_once('@') {
+                Some((path, attribute)) => (path, Some(attribute)),
+                None => (path.as_str(), None),
+            };
+            if let Some(census) = self.census.0.get_mut(path) {
+                match attribute {
+                    Some(attribute) => {
+                        census.unmodelled_attributes.insert(attribute.to_string());
+                    }
+                    None => census.unmodelled = true,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(
+        &mut self,
+        node: &roxmltree::Node,
+        current_path: &[String],
+        attributes: &HashMap<String, String>,
+        _depth: u32,
+    ) -> Result<(), ClinvarXMLTabError> {
+        let path = current_path.join(".");
+        let census = self.census.0.entry(path.clone()).or_default();
+        census.count += 1;
+        census.attributes.extend(attributes.keys().cloned());
+        if census.samples.len() < SAMPLES {
+            let text = node.text().unwrap_or_default().trim();
+            let sample = match text.char_indices().nth(SAMPLE_LEN) {
+                Some((end, _)) => format!("{}...", &text[..end]),
+                None => text.to_string(),
+            }
+            .replace(['\t', '\n', '\r'], " ");
+            if !sample.is_empty() && !census.samples.contains(&sample) {
+                census.samples.push(sample);
+            }
+        }
+        *self.in_record.entry(path).or_default() += 1;
+        Ok(())
+    }
+
+    fn end_record(&mut self) -> Result<(), ClinvarXMLTabError> {
+        for (path, count) in self.in_record.drain() {
+            if let Some(census) = self.census.0.get_mut(&path) {
+                census.records += 1;
+                census.max_per_record = census.max_per_record.max(count);
+            }
+        }
+        self.records += 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), ClinvarXMLTabError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), ClinvarXMLTabError> {
+        match &self.previous {
+            Some(previous) => {
+                self.changes = self.census.write_diff(previous, &mut self.writer)?;
+            }
+            None => self.census.write(&mut self.writer)?,
+        }
+        self.flush()
+    }
+
+    fn records_written(&self) -> Option<u64> {
+        Some(self.records)
+    }
+
+    fn counters(&self) -> Vec<(&'static str, u64)> {
+        let unmodelled = self
+            .census
+            .0
+            .values()
+            .filter(|census| census.unmodelled || !census.unmodelled_attributes.is_empty())
+            .count();
+        let mut counters = vec![
+            ("paths", self.census.0.len() as u64),
+            ("unmodelled_paths", unmodelled as u64),
+            ("model_errors", self.model_errors),
+        ];
+        if self.previous.is_some() {
+            counters.push(("changes", self.changes));
+        }
+        counters
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Census of the ClinVarSets in `records`
+    fn census_of(records: &str) -> Census {
+        let xml = format!("<ReleaseSet>{records}</ReleaseSet>");
+        let mut handler = CensusHandler::new_from_writer(Vec::new());
+        crate::reader::read_xml(xml.as_bytes(), &mut handler, None).unwrap();
+        handler.census
+    }
+
+    fn diff(old: &Census, new: &Census) -> Vec<String> {
+        let mut output = Vec::new();
+        let changes = new.write_diff(old, &mut output).unwrap();
+        let lines: Vec<String> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(str::to_string)
+            .collect();
+        assert_eq!(changes as usize, lines.len());
+        lines
+    }
+
+    #[test]
+    fn test_census() {
+        let census = census_of(
+            r#"<ClinVarSet ID="1"><Title>first</Title><Gene Symbol="OAT"/><Gene Symbol="X" ID="2"/></ClinVarSet>
+            <ClinVarSet ID="2"><Title>second</Title><Title>first</Title></ClinVarSet>"#,
+        );
+        let paths: Vec<&str> = census.paths().keys().map(String::as_str).collect();
+        assert_eq!(paths, ["ClinVarSet", "ClinVarSet.Gene", "ClinVarSet.Title"]);
+        let gene = &census.paths()["ClinVarSet.Gene"];
+        assert_eq!((gene.count, gene.records, gene.max_per_record), (2, 1, 2));
+        assert_eq!(gene.attributes, ["ID", "Symbol"].map(String::from).into());
+        let title = &census.paths()["ClinVarSet.Title"];
+        assert_eq!(
+            (title.count, title.records, title.max_per_record),
+            (3, 2, 2)
+        );
+        assert_eq!(title.samples, ["first", "second"]);
+
+        // Written, then read back
+        let mut output = Vec::new();
+        census.write(&mut output).unwrap();
+        let read = Census::from_reader(output.as_slice()).unwrap();
+        assert_eq!(read.paths(), census.paths());
+        assert!(diff(&census, &read).is_empty());
+    }
+
+    #[test]
+    fn test_unmodelled() {
+        let xml = include_str!("../clinvarset_new.xml").replacen(
+            "<RecordStatus>current</RecordStatus>",
+            "<RecordStatus>current</RecordStatus><NewElement/>",
+            1,
+        );
+        let mut handler = CensusHandler::new_from_writer(Vec::new());
+        crate::reader::read_xml(xml.as_bytes(), &mut handler, None).unwrap();
+        let census = &handler.census;
+        let unmodelled: Vec<&str> = census
+            .paths()
+            .iter()
+            .filter(|(_, census)| census.unmodelled)
+            .map(|(path, _)| path.as_str())
+            .collect();
+        assert_eq!(
+            unmodelled,
+            [
+                "ClinVarSet.ClinVarAssertion.ExternalID",
+                "ClinVarSet.NewElement"
+            ]
+        );
+        let accession = &census.paths()["ClinVarSet.ClinVarAssertion.ClinVarAccession"];
+        assert_eq!(
+            accession.unmodelled_attributes,
+            ["OrgType".to_string()].into()
+        );
+        assert!(handler.counters().contains(&("unmodelled_paths", 3)));
+        assert!(handler.counters().contains(&("model_errors", 0)));
+
+        let mut output = Vec::new();
+        census.write(&mut output).unwrap();
+        assert_eq!(
+            Census::from_reader(output.as_slice()).unwrap().paths(),
+            census.paths()
+        );
+
+        // Records the model can't deserialize are not checked
+        let census = census_of(r#"<ClinVarSet ID="1"><Title>a</Title></ClinVarSet>"#);
+        assert!(census.paths().values().all(|census| !census.unmodelled));
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = census_of(
+            r#"<ClinVarSet ID="1"><Title>a</Title><Gene Symbol="OAT" ID="1"/><Old/></ClinVarSet>"#,
+        );
+        let new = census_of(
+            r#"<ClinVarSet ID="1"><Title>a</Title><Title>b</Title><Gene Symbol="OAT" HGNC="1"/><New/></ClinVarSet>"#,
+        );
+        assert_eq!(
+            diff(&old, &new),
+            [
+                "new_attribute\tClinVarSet.Gene\t\tHGNC",
+                "removed_attribute\tClinVarSet.Gene\tID\t",
+                "new_path\tClinVarSet.New\t\t1",
+                "removed_path\tClinVarSet.Old\t1\t",
+                "cardinality\tClinVarSet.Title\t1\t2",
+            ]
+        );
+    }
+}
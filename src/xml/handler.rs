@@ -1,16 +1,155 @@
+use crate::clinvar::model::ClinVarSet;
 use crate::error::ClinvarXMLTabError;
+use std::cell::OnceCell;
 use std::collections::HashMap;
 
+/// A ClinVarSet read from the input: its element, and its typed model, deserialized the first time it is asked for
+/// and then shared by every handler
+pub struct ClinVarSetEvent<'a, 'input> {
+    node: roxmltree::Node<'a, 'input>,
+    set: OnceCell<ClinVarSet>,
+}
+
+impl<'a, 'input> ClinVarSetEvent<'a, 'input> {
+    /// `node` is the `<ClinVarSet>` element
+    pub fn new(node: roxmltree::Node<'a, 'input>) -> Self {
+        Self {
+            node,
+            set: OnceCell::new(),
+        }
+    }
+
+    pub fn node(&self) -> &roxmltree::Node<'a, 'input> {
+        &self.node
+    }
+
+    /// Typed model of the ClinVarSet, only deserialized once
+    pub fn set(&self) -> Result<&ClinVarSet, ClinvarXMLTabError> {
+        if let Some(set) = self.set.get() {
+            return Ok(set);
+        }
+        let set = ClinVarSet::from_node(&self.node)?;
+        Ok(self.set.get_or_init(|| set))
+    }
+}
+
+/// Gives `node` and every element below it to `handler`, with their path and depth
+pub(crate) fn walk_elements<H: EventHandler + ?Sized>(
+    node: &roxmltree::Node,
+    current_path: &mut Vec<String>,
+    handler: &mut H,
+    depth: u32,
+) -> Result<(), ClinvarXMLTabError> {
+    if !node.is_element() {
+        return Ok(());
+    }
+    current_path.push(node.tag_name().name().to_string());
+    let attributes: HashMap<String, String> = node
+        .attributes()
+        .map(|att| (att.name().to_string(), att.value().to_string()))
+        .collect();
+    handler.handle(node, current_path, &attributes, depth)?;
+    for child in node.children() {
+        walk_elements(&child, current_path, handler, depth + 1)?;
+    }
+    current_path.pop();
+    Ok(())
+}
+
 pub trait EventHandler {
+    /// Called with every element of a ClinVarSet (the ClinVarSet itself at depth 1), with its path from the
+    /// ClinVarSet, unless `handle_clinvar_set` is implemented
     fn handle(
         &mut self,
-        node: &roxmltree::Node,
-        current_path: &Vec<String>,
-        attributes: &HashMap<String, String>,
-        depth: u32,
-    ) -> Result<(), ClinvarXMLTabError>;
+        _node: &roxmltree::Node,
+        _current_path: &[String],
+        _attributes: &HashMap<String, String>,
+        _depth: u32,
+    ) -> Result<(), ClinvarXMLTabError> {
+        Ok(())
+    }
+
+    /// Called once per ClinVarSet. By default, gives each of its elements to `handle`: handlers that only need
+    /// the ClinVarSet (or its typed model) implement this instead, so that its elements are not walked.
+    fn handle_clinvar_set(&mut self, event: &ClinVarSetEvent) -> Result<(), ClinvarXMLTabError> {
+        walk_elements(event.node(), &mut Vec::new(), self, 1)
+    }
 
     fn end_record(&mut self) -> Result<(), ClinvarXMLTabError>;
+
+    /// Flushes everything written so far down to the underlying writer
+    fn flush(&mut self) -> Result<(), ClinvarXMLTabError> {
+        Ok(())
+    }
+
+    /// Called once after the last record, for handlers that need to write a footer or close their output
+    fn finish(&mut self) -> Result<(), ClinvarXMLTabError> {
+        Ok(())
+    }
+
+    /// Number of records actually written, for handlers that may skip some
+    fn records_written(&self) -> Option<u64> {
+        None
+    }
+
+    /// Named counters to report at the end of the run (e.g. REF mismatches), if any
+    fn counters(&self) -> Vec<(&'static str, u64)> {
+        Vec::new()
+    }
+
+    /// Whether the handler needs no more records, so that reading can stop before the end of the input
+    fn done(&self) -> bool {
+        false
+    }
+}
+
+/// Forwards everything to two handlers, e.g. to write a side table next to the main output.
+/// The records written are those of the `primary` handler.
+pub struct TeeHandler {
+    primary: Box<dyn EventHandler>,
+    secondary: Box<dyn EventHandler>,
+}
+
+impl TeeHandler {
+    pub fn new(primary: Box<dyn EventHandler>, secondary: Box<dyn EventHandler>) -> Self {
+        Self { primary, secondary }
+    }
+}
+
+impl EventHandler for TeeHandler {
+    fn handle_clinvar_set(&mut self, event: &ClinVarSetEvent) -> Result<(), ClinvarXMLTabError> {
+        self.primary.handle_clinvar_set(event)?;
+        self.secondary.handle_clinvar_set(event)
+    }
+
+    fn end_record(&mut self) -> Result<(), ClinvarXMLTabError> {
+        self.primary.end_record()?;
+        self.secondary.end_record()
+    }
+
+    fn flush(&mut self) -> Result<(), ClinvarXMLTabError> {
+        self.primary.flush()?;
+        self.secondary.flush()
+    }
+
+    fn finish(&mut self) -> Result<(), ClinvarXMLTabError> {
+        self.primary.finish()?;
+        self.secondary.finish()
+    }
+
+    fn records_written(&self) -> Option<u64> {
+        self.primary.records_written()
+    }
+
+    fn counters(&self) -> Vec<(&'static str, u64)> {
+        let mut counters = self.primary.counters();
+        counters.extend(self.secondary.counters());
+        counters
+    }
+
+    fn done(&self) -> bool {
+        self.primary.done() && self.secondary.done()
+    }
 }
 
 pub struct BasicNodeWriter<T: std::io::Write> {
@@ -29,17 +168,17 @@ impl<T: std::io::Write> EventHandler for BasicNodeWriter<T> {
     fn handle(
         &mut self,
         node: &roxmltree::Node,
-        current_path: &Vec<String>,
+        current_path: &[String],
         attributes: &HashMap<String, String>,
         depth: u32,
     ) -> Result<(), ClinvarXMLTabError> {
-        self.writer.write(
+        self.writer.write_all(
             format!(
                 "{}{} - {} - {}\n",
                 "\t".repeat((depth as usize).saturating_sub(1)),
                 current_path.join("."),
                 node.text().unwrap_or("No text").trim(),
-                if attributes.len() == 0 {
+                if attributes.is_empty() {
                     "No attributes".to_string()
                 } else {
                     attributes
@@ -56,4 +195,8 @@ impl<T: std::io::Write> EventHandler for BasicNodeWriter<T> {
     fn end_record(&mut self) -> Result<(), ClinvarXMLTabError> {
         Ok(())
     }
+    fn flush(&mut self) -> Result<(), ClinvarXMLTabError> {
+        self.writer.flush()?;
+        Ok(())
+    }
 }
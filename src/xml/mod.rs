@@ -1,2 +1,3 @@
+pub mod census;
 pub mod handler;
 pub mod reader;
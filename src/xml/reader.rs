@@ -1,10 +1,12 @@
 use quick_xml::events::{BytesStart, Event};
 use quick_xml::{Reader, Writer};
-use std::collections::HashMap;
 use std::io::BufRead;
 
+use crate::clinvar::model::ClinVarSet;
+use crate::clinvar::record::ClinVarRecord;
 use crate::error::ClinvarXMLTabError;
 use crate::handler;
+use crate::handler::ClinVarSetEvent;
 
 // Thanks https://capnfabs.net/posts/parsing-huge-xml-quickxml-rust-serde/
 fn read_to_end_into_buffer<R: BufRead>(
@@ -31,95 +33,285 @@ fn read_to_end_into_buffer<R: BufRead>(
                 depth -= 1;
             }
             Event::Eof => {
-                panic!("Truncated or invalid XML input file!")
+                // Truncated or invalid XML input file!
+                return Err(quick_xml::Error::IllFormed(
+                    quick_xml::errors::IllFormedError::MissingEndTag(
+                        String::from_utf8_lossy(tag_name.as_ref()).to_string(),
+                    ),
+                ));
             }
             _ => {}
         }
     }
 }
 
-fn note_flatten_treat(
-    node: &roxmltree::Node,
-    current_path: &mut Vec<String>,
-    handler: &mut impl handler::EventHandler,
-    depth: u32,
-) -> Result<(), ClinvarXMLTabError> {
-    if node.is_element() {
-        current_path.push(node.tag_name().name().to_string());
-        let attributes: HashMap<String, String> = node
-            .attributes()
-            .map(|att| (att.name().to_string(), att.value().to_string()))
-            .collect();
-        handler.handle(node, current_path, &attributes, depth)?;
-        for child in node.children() {
-            note_flatten_treat(&child, current_path, handler, depth + 1)?;
-        }
-        current_path.pop();
-    } else {
-        for child in node.children() {
-            note_flatten_treat(&child, current_path, handler, depth + 1)?;
+/// Reads events until the next `ClinVarSet` element, and returns its offset in the input and its raw bytes
+/// (or `None` at the end of input).
+fn next_clinvar_set<R: BufRead>(
+    reader: &mut Reader<R>,
+    buf: &mut Vec<u8>,
+    junk_buf: &mut Vec<u8>,
+) -> Result<Option<(u64, Vec<u8>)>, ClinvarXMLTabError> {
+    loop {
+        buf.clear();
+        let offset = reader.buffer_position();
+        match reader.read_event_into(buf)? {
+            Event::Eof => return Ok(None),
+            Event::Start(e) if e.name().as_ref() == b"ClinVarSet" => {
+                // load entire tag into buffer
+                let e = e.into_owned();
+                let elem_bytes = read_to_end_into_buffer(reader, &e, junk_buf)?;
+                return Ok(Some((offset, elem_bytes)));
+            }
+            // Other Events are not important for us
+            _ => (),
         }
     }
-    Ok(())
 }
 
-pub fn read_xml(
+/// Counters kept by `read_xml` while streaming the input.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReadStats {
+    /// Number of ClinVarSets handled so far
+    pub records: u64,
+    /// Offset in the uncompressed input, right after the last completed ClinVarSet
+    pub input_offset: u64,
+}
+
+pub fn read_xml<H: handler::EventHandler + ?Sized>(
     reader: impl std::io::BufRead,
-    handler: &mut impl handler::EventHandler,
+    handler: &mut H,
     limit: Option<u64>,
 ) -> Result<(), ClinvarXMLTabError> {
+    read_xml_with(reader, handler, limit, ReadStats::default(), |_, _| Ok(()))?;
+    Ok(())
+}
+
+/// Same as `read_xml`, but starts from `start` (as reported by a previous run), and calls `on_record` after each ClinVarSet.
+///
+/// `reader` must already be at `start.input_offset` in the (uncompressed) input (see [`crate::utils::file_reader_at`]).
+/// `limit` only counts the records handled during this call.
+/// Returns the counters as they were after the last handled ClinVarSet.
+pub fn read_xml_with<H: handler::EventHandler + ?Sized>(
+    reader: impl std::io::BufRead,
+    handler: &mut H,
+    limit: Option<u64>,
+    start: ReadStats,
+    mut on_record: impl FnMut(&ReadStats, &mut H) -> Result<(), ClinvarXMLTabError>,
+) -> Result<ReadStats, ClinvarXMLTabError> {
     let mut reader = Reader::from_reader(reader);
+    if start.input_offset > 0 {
+        // We start in the middle of the document, so the root end tag has no matching start tag
+        reader.config_mut().allow_unmatched_ends = true;
+    }
 
+    let mut stats = start;
     let mut count = 0;
 
     let mut buf = Vec::new();
     let mut junk_buf = Vec::new();
-    loop {
-        match reader.read_event_into(&mut buf) {
-            Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
-            Ok(Event::Eof) => {
+    while let Some((_, elem_bytes)) = next_clinvar_set(&mut reader, &mut buf, &mut junk_buf)? {
+        let xml = std::str::from_utf8(&elem_bytes)?;
+        let doc = roxmltree::Document::parse(xml)?;
+        handler.handle_clinvar_set(&ClinVarSetEvent::new(doc.root_element()))?;
+        match handler.end_record() {
+            Ok(()) => Ok(()),
+            // Output was closed (e.g. piped to `head`), no need to read any further
+            Err(e) if e.is_broken_pipe() => return Ok(stats),
+            Err(e) => Err(e),
+        }?;
+        stats.records += 1;
+        stats.input_offset = start.input_offset + reader.buffer_position();
+        on_record(&stats, handler)?;
+        count += 1;
+        if let Some(limit) = limit {
+            if count >= limit {
                 break;
             }
-            Ok(Event::Start(e)) => match e.name().as_ref() {
-                b"ClinVarSet" => {
-                    // load entire tag into buffer
-                    let elem_bytes = read_to_end_into_buffer(&mut reader, &e, &mut junk_buf)?;
-
-                    // out_stream.write(str.as_ref()).ok()?;
-
-                    let mut current_path = Vec::new();
-
-                    let str = std::str::from_utf8(&elem_bytes)?.to_string();
-                    let doc = roxmltree::Document::parse(&str)?;
-                    note_flatten_treat(&doc.root(), &mut current_path, handler, 0)?;
-                    match handler.end_record() {
-                        Ok(()) => Ok(()),
-                        Err(e) => match e {
-                            ClinvarXMLTabError::CSVError(e) => match e.kind() {
-                                csv::ErrorKind::Io(io_err) => match io_err.kind() {
-                                    std::io::ErrorKind::BrokenPipe => {
-                                        break;
-                                    }
-                                    _ => Err(ClinvarXMLTabError::from(e)),
-                                },
-                                _ => Err(ClinvarXMLTabError::from(e)),
-                            },
-                            _ => Err(e),
-                        },
-                    }?;
-                    count += 1;
-                    if let Some(limit) = limit {
-                        if count >= limit {
-                            break;
-                        }
-                    }
-                }
-                _ => {}
-            },
-            // Other Events are not important for us
-            _ => (),
         }
-        buf.clear();
+        if handler.done() {
+            break;
+        }
+    }
+    handler.finish()?;
+    Ok(stats)
+}
+
+/// Calls `on_set` with the raw bytes of every ClinVarSet and their range in the (uncompressed) input,
+/// until the end of input or until `on_set` returns `false`.
+pub fn scan_clinvar_sets(
+    reader: impl BufRead,
+    mut on_set: impl FnMut(std::ops::Range<u64>, &[u8]) -> Result<bool, ClinvarXMLTabError>,
+) -> Result<(), ClinvarXMLTabError> {
+    let mut reader = Reader::from_reader(reader);
+    // The input may start after the root start tag (see `read_prolog`)
+    reader.config_mut().allow_unmatched_ends = true;
+    let mut buf = Vec::new();
+    let mut junk_buf = Vec::new();
+    while let Some((offset, elem_bytes)) = next_clinvar_set(&mut reader, &mut buf, &mut junk_buf)? {
+        if !on_set(offset..reader.buffer_position(), &elem_bytes)? {
+            break;
+        }
     }
     Ok(())
 }
+
+/// Start of an XML document, up to and including the start tag of its root element
+#[derive(Debug, Clone, Default)]
+pub struct Prolog {
+    /// XML declaration, comments... and the root start tag, as found in the input
+    pub xml: Vec<u8>,
+    /// Name of the root element (`ReleaseSet`)
+    pub root: String,
+}
+
+/// Reads the input up to the start tag of the root element.
+/// Nothing further is consumed, so `reader` can then be given to `scan_clinvar_sets`.
+pub fn read_prolog<R: BufRead>(reader: R) -> Result<Prolog, ClinvarXMLTabError> {
+    let mut reader = Reader::from_reader(reader);
+    let mut prolog = Prolog::default();
+    let mut writer = Writer::new(&mut prolog.xml);
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match reader.read_event_into(&mut buf)? {
+            Event::Eof => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "no root element in the input",
+                )
+                .into())
+            }
+            Event::Start(e) => {
+                prolog.root = String::from_utf8_lossy(e.name().as_ref()).to_string();
+                writer.write_event(Event::Start(e))?;
+                return Ok(prolog);
+            }
+            event => writer.write_event(event)?,
+        }
+    }
+}
+
+/// Pull-style reader over a ClinVar XML release.
+///
+/// Yields one `ClinVarRecord` per `ClinVarSet`, with VCF coordinates taken from the given assembly
+/// (`"GRCh37"` or `"GRCh38"`):
+///
+/// ```no_run
+/// use clinvar_xml_tab::reader::ClinVarReader;
+///
+/// let input = clinvar_xml_tab::utils::file_reader(Some("ClinVarFullRelease.xml.gz")).unwrap();
+/// let pathogenic = ClinVarReader::new(input, "GRCh38")
+///     .filter_map(Result::ok)
+///     .filter(|record| record.clnsig() == Some("pathogenic"))
+///     .count();
+/// ```
+pub struct ClinVarReader<R: BufRead> {
+    reader: Reader<R>,
+    buf: Vec<u8>,
+    junk_buf: Vec<u8>,
+    assembly: &'static str,
+    done: bool,
+}
+
+impl<R: BufRead> ClinVarReader<R> {
+    pub fn new(reader: R, assembly: &'static str) -> Self {
+        Self {
+            reader: Reader::from_reader(reader),
+            buf: Vec::new(),
+            junk_buf: Vec::new(),
+            assembly,
+            done: false,
+        }
+    }
+
+    fn read_record(&mut self) -> Result<Option<ClinVarRecord>, ClinvarXMLTabError> {
+        let Some((_, elem_bytes)) =
+            next_clinvar_set(&mut self.reader, &mut self.buf, &mut self.junk_buf)?
+        else {
+            return Ok(None);
+        };
+        let set = ClinVarSet::from_xml(std::str::from_utf8(&elem_bytes)?)?;
+        Ok(Some(ClinVarRecord::from_clinvar_set(
+            &set,
+            Some(self.assembly),
+        )))
+    }
+}
+
+impl<R: BufRead> Iterator for ClinVarReader<R> {
+    type Item = Result<ClinVarRecord, ClinvarXMLTabError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.read_record() {
+            Ok(Some(record)) => Some(Ok(record)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                // The underlying XML stream cannot be recovered after an error
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const XML: &str = include_str!("../clinvarset_new.xml");
+
+    #[test]
+    fn test_clinvar_reader() {
+        let second = XML
+            .replacen(r#"ID="92148146""#, r#"ID="2""#, 1)
+            .replace(r#"positionVCF="124408632""#, r#"positionVCF="1000""#);
+        let input = format!(
+            "<?xml version=\"1.0\"?>\n<ReleaseSet Type=\"full\">\n{XML}\n{second}\n</ReleaseSet>\n"
+        );
+        let records: Vec<ClinVarRecord> = ClinVarReader::new(input.as_bytes(), "GRCh38")
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        let record = &records[0];
+        assert_eq!(record.clinvar_id(), Some(92148146));
+        assert_eq!(record.rcv(), Some("RCV000000188"));
+        assert_eq!(record.vcv(), Some("VCV000000165"));
+        assert_eq!(record.chromosome(), Some("chr10"));
+        assert_eq!(record.position(), Some(124408632));
+        assert_eq!(record.reference(), Some("CCACTCCTAT"));
+        assert_eq!(record.alternate(), Some("C"));
+        assert_eq!(record.clnsig(), Some("pathogenic/likely_pathogenic"));
+        assert_eq!(record.genes(), ["OAT"]);
+        assert_eq!(records[1].clinvar_id(), Some(2));
+        assert_eq!(records[1].position(), Some(1000));
+
+        // GRCh37 coordinates
+        let record = ClinVarReader::new(input.as_bytes(), "GRCh37")
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.position(), Some(126097201));
+    }
+
+    #[test]
+    fn test_clinvar_reader_error() {
+        // A mismatched end tag, then a truncated ClinVarSet
+        for input in [
+            format!("<ReleaseSet>\n{XML}\n<ClinVarSet ID=\"2\"><Title>x</Name></ClinVarSet>\n</ReleaseSet>"),
+            format!("<ReleaseSet>\n{XML}\n<ClinVarSet ID=\"2\"><Title>x"),
+        ] {
+            let mut reader = ClinVarReader::new(input.as_bytes(), "GRCh38");
+            assert!(matches!(reader.next(), Some(Ok(_))));
+            assert!(matches!(reader.next(), Some(Err(_))));
+            assert!(reader.next().is_none());
+            assert!(reader.next().is_none());
+        }
+    }
+}